@@ -2,6 +2,8 @@
 use anchor_lang::prelude::*;
 // 导入 Solana 程序库中的 Pubkey 结构体，用于表示账户地址。
 use anchor_lang::solana_program::pubkey::Pubkey;
+// 导入单条指令允许的账户扩容上限，用于限制 OpenOrders 的最大容量。
+use anchor_lang::solana_program::entrypoint::MAX_PERMITTED_DATA_INCREASE;
 // 导入 Anchor 对 SPL Token 2022 标准（TokenInterface）的支持库。
 // use anchor_spl::token_interface::{
 //     self as token, Mint, TokenAccount, TokenInterface, TransferChecked,
@@ -9,12 +11,14 @@ use anchor_lang::solana_program::pubkey::Pubkey;
 // 导入与 SPL Token 2022 标准交互所需的特定模块和结构体。
 use anchor_spl::{
     // 导入 token_2022 模块中的 transfer_checked 函数，用于安全的代币转账。
-    token_2022::{transfer_checked, Transfer, TransferChecked},
-    // 导入与代币交互所需的接口和账户类型，如 Mint, Token2022, TokenAccount, TokenInterface。
-    token_interface::{Mint, Token2022, TokenAccount, TokenInterface},
+    token_2022::{transfer_checked, TransferChecked},
+    // 导入与代币交互所需的接口和账户类型，如 Mint, TokenAccount, TokenInterface。
+    token_interface::{Mint, TokenAccount, TokenInterface},
 };
 // 导入 bytemuck 库，用于安全地进行零成本的类型转换，这对于 zero_copy 反序列化至关重要。
 use bytemuck::{Pod, Zeroable};
+// 导入 RefMut，用于把订单簿账户数据拆分为头部和节点数组的可变借用。
+use std::cell::RefMut;
 // 导入标准库中的 size_of 函数，用于获取类型的大小。
 use std::mem::size_of;
 
@@ -23,12 +27,18 @@ use std::mem::size_of;
 declare_id!("6Kw1m5tG9E6Hh9TSzuofdCbjLLtjdRuQGFhiFDuZaJuL");
 
 // --- 常量 ---
-// 每个订单簿（买单或卖单）可以容纳的最大订单数量。
-const MAX_ORDERS_PER_BOOK: usize = 64;
-// 每个用户可以拥有的最大未结订单数量。
-const MAX_OPEN_ORDERS_PER_USER: usize = 16;
-// 哨兵值，用于表示链表的末尾（类似于 null）。使用 u32 的最大值。
+// 事件队列可以容纳的最大待处理事件数量。
+const EVENT_QUEUE_CAPACITY: usize = 64;
+// 新建的 OpenOrders 账户默认可以容纳的活动订单数量。
+const DEFAULT_OPEN_ORDERS_CAPACITY: u16 = 16;
+// 通过 `resize_open_orders` 可以设置的最大活动订单数量（410）。
+// 单条指令最多只能把账户扩大 `MAX_PERMITTED_DATA_INCREASE`（10240）字节，取此上限使得从最小容量 1 一次扩容到最大容量也不会超出限制。
+const MAX_OPEN_ORDERS_CAPACITY: u16 =
+    1 + (MAX_PERMITTED_DATA_INCREASE / OpenOrderSlot::INIT_SPACE) as u16;
+// 哨兵值，用于表示空引用或链表的末尾（类似于 null）。使用 u32 的最大值。
 const SENTINEL: u32 = u32::MAX;
+// 订单簿树中子节点引用的最高位，置位表示引用的是叶子节点而不是内部节点。
+const LEAF_FLAG: u32 = 1 << 31;
 // 程序的版本号。
 const PROGRAM_VERSION: u8 = 2;
 // 默认的最小基础代币下单数量。
@@ -37,6 +47,12 @@ const DEFAULT_MIN_BASE_QTY: u64 = 1;
 const DEFAULT_MIN_NOTIONAL: u64 = 1;
 // 用于价格计算的缩放因子，避免浮点数运算。
 const PRICE_SCALE: u128 = 1_000_000;
+// 每个市场最多可以配置的手续费等级数量。
+const MAX_FEE_TIERS: usize = 8;
+// 用于确定手续费等级的交易量统计窗口（天）。
+const VOLUME_WINDOW_DAYS: usize = 30;
+// 一天的秒数。
+const SECONDS_PER_DAY: i64 = 86_400;
 
 // --- 工具结构体与枚举 ---
 
@@ -89,25 +105,77 @@ impl From<PodBool> for bool {
 // 订单簿中节点的标签，用于区分节点的状态。
 // 指定枚举使用 u8 类型表示。
 #[repr(u8)]
-// 派生常用的 trait。
-#[derive(PartialEq, Debug, Clone, Copy, Eq, Zeroable, AnchorSerialize, AnchorDeserialize)]
+// 派生常用的 trait，Default 默认值为 Uninitialized。
+#[derive(PartialEq, Debug, Clone, Copy, Eq, Default, Zeroable, AnchorSerialize, AnchorDeserialize)]
 // 定义 NodeTag 枚举。
 pub enum NodeTag {
     // 未初始化状态
+    #[default]
     Uninitialized,
     // 空闲节点，在空闲列表中
     FreeNode,
-    // 已被订单占用的节点
+    // 已被订单占用的叶子节点
     OrderNode,
+    // crit-bit 树的内部节点
+    InnerNode,
 }
 // `unsafe impl Pod` 表示我们向编译器保证这个枚举类型可以安全地进行零成本转换。
 unsafe impl Pod for NodeTag {}
-// 为 NodeTag 实现 Default trait。
-impl Default for NodeTag {
-    // 默认值为 Uninitialized。
-    fn default() -> Self {
-        // 返回 Uninitialized 成员。
-        NodeTag::Uninitialized
+
+// 自成交处理方式：当 taker 与同一 OpenOrders 账户的挂单价格交叉时如何处理。
+// 每个订单单独指定，并保存在订单簿的 `Order` 中，因此使用 u8 表示以便零拷贝存储。
+#[repr(u8)]
+// 派生常用的 trait，Default 默认值为 DecrementAndCancel。
+#[derive(
+    PartialEq, Debug, Clone, Copy, Eq, Default, Zeroable, AnchorSerialize, AnchorDeserialize,
+)]
+// 定义 SelfTradeBehavior 枚举。
+pub enum SelfTradeBehavior {
+    // 双方订单都扣减重叠的数量，不产生成交和手续费；数量较小的一方因此被完全取消
+    #[default]
+    DecrementAndCancel,
+    // 取消 maker（较早的挂单），taker 继续撮合
+    CancelMaker,
+    // 取消 taker（较新的订单）的剩余部分，maker 保持不变
+    CancelTaker,
+    // 同时取消 maker 和 taker 的剩余部分
+    CancelBoth,
+}
+// 与 NodeTag 相同，保证该枚举可以作为 Pod 存储在零拷贝账户中。
+unsafe impl Pod for SelfTradeBehavior {}
+
+// 事件队列中事件的类型。
+#[repr(u8)]
+// 派生常用的 trait，Default 默认值为 Fill。
+#[derive(
+    PartialEq, Debug, Clone, Copy, Eq, Default, Zeroable, AnchorSerialize, AnchorDeserialize,
+)]
+// 定义 EventKind 枚举。
+pub enum EventKind {
+    // 挂单成交
+    #[default]
+    Fill,
+    // 挂单被取消（例如自成交），不产生成交
+    Out,
+}
+// 与 NodeTag 相同，保证该枚举可以作为 Pod 存储在零拷贝账户中。
+unsafe impl Pod for EventKind {}
+
+// 为 SelfTradeBehavior 实现方法。
+impl SelfTradeBehavior {
+    // 根据自成交处理方式，计算 taker 和 maker 各自需要取消的数量。
+    // `taker_qty` 是 taker 剩余的数量，`maker_qty` 是 maker 挂单的数量。
+    // 返回 (taker 取消数量, maker 取消数量)。
+    pub fn cancel_quantities(self, taker_qty: u64, maker_qty: u64) -> (u64, u64) {
+        match self {
+            SelfTradeBehavior::DecrementAndCancel => {
+                let overlap = std::cmp::min(taker_qty, maker_qty);
+                (overlap, overlap)
+            }
+            SelfTradeBehavior::CancelMaker => (0, maker_qty),
+            SelfTradeBehavior::CancelTaker => (taker_qty, 0),
+            SelfTradeBehavior::CancelBoth => (taker_qty, maker_qty),
+        }
     }
 }
 
@@ -123,16 +191,89 @@ impl Default for NodeTag {
 pub struct Order {
     // 订单所有者的 OpenOrders 账户地址
     pub owner_account: Pubkey,
+    // 订单所有者的钱包地址，记录在成交事件中
+    pub owner: Pubkey,
     // 订单的唯一 ID
     pub order_id: u64,
+    // 客户端自定义的订单 ID（未指定时为 0）
+    pub client_order_id: u64,
     // 订单价格（整数表示）
     pub price: u64,
     // 订单的基础代币数量
     pub base_qty: u64,
+    // 买单剩余数量仍锁定的报价代币（成交额 + 预留的 taker 手续费），卖单为 0
+    pub locked_quote: u64,
+    // 订单作为 taker 时的自成交处理方式
+    pub self_trade_behavior: SelfTradeBehavior,
+    // 下单时用户所在的手续费等级（从 1 开始，0 表示使用市场统一费率）
+    pub fee_tier: u8,
+    // 下单时手续费等级的 maker 费率，仅在 `fee_tier` 不为 0 时使用
+    pub maker_fee_bps: i16,
+    // 买单下单时预留手续费所用的 taker 费率
+    pub locked_fee_bps: u16,
+    // 下单时手续费等级的 taker 费率，仅在 `fee_tier` 不为 0 时使用
+    pub taker_fee_bps: u16,
+}
+
+// 为 `Order` 实现方法。
+impl Order {
+    // 订单作为 maker 成交时适用的费率：下单时所在等级的费率，没有等级时使用市场当前的统一费率。
+    pub fn maker_fee_bps(&self, market: &Market) -> i16 {
+        if self.fee_tier > 0 {
+            self.maker_fee_bps
+        } else {
+            market.maker_fee_bps
+        }
+    }
+
+    // 订单在 `match_orders` 中作为较新的一方（taker）成交时适用的费率，规则与 `maker_fee_bps` 相同。
+    pub fn taker_fee_bps(&self, market: &Market) -> u16 {
+        if self.fee_tier > 0 {
+            self.taker_fee_bps
+        } else {
+            market.taker_fee_bps
+        }
+    }
+
+    // 买单减少 `base_qty` 数量（成交或取消）时释放的报价代币：按下单时的锁定公式和费率计算，且不超过剩余的锁定金额；
+    // 剩余数量全部减少时释放剩余的全部锁定金额，因此释放的总额与下单时锁定的金额完全一致，与之后的费率调整无关。
+    // 卖单没有锁定报价代币，返回 0。
+    pub fn quote_release(&self, base_qty: u64) -> Result<u64> {
+        if base_qty >= self.base_qty {
+            return Ok(self.locked_quote);
+        }
+        Ok(std::cmp::min(
+            bid_locked_quote(self.price, base_qty, self.locked_fee_bps)?,
+            self.locked_quote,
+        ))
+    }
+
+    // 买单成交 `base_qty` 数量、成交额为 `quote_qty` 时，释放的锁定金额中可以用于支付手续费的部分。
+    pub fn fee_reserve(&self, base_qty: u64, quote_qty: u64) -> Result<u64> {
+        Ok(self
+            .quote_release(base_qty)?
+            .checked_sub(quote_qty)
+            .ok_or(ErrorCode::MathOverflow)?)
+    }
+
+    // 减少订单的剩余数量，并扣除相应的锁定报价代币。
+    pub fn reduce(&mut self, base_qty: u64) -> Result<()> {
+        let released = self.quote_release(base_qty)?;
+        self.base_qty = self
+            .base_qty
+            .checked_sub(base_qty)
+            .ok_or(ErrorCode::MathOverflow)?;
+        self.locked_quote = self
+            .locked_quote
+            .checked_sub(released)
+            .ok_or(ErrorCode::MathOverflow)?;
+        // 返回成功。
+        Ok(())
+    }
 }
 
-// `OrderNode` 结构体，代表订单簿中的一个节点。
-// 它包含一个 `Order` 以及用于构成双向链表的指针。
+// `OrderNode` 结构体，代表订单簿 crit-bit 树中的一个叶子节点。
+// 它包含一个 `Order`，空闲时通过 `next` 串成空闲列表。
 // 派生常用的 trait。
 #[derive(Clone, Copy, Pod, Zeroable, Default, AnchorSerialize, AnchorDeserialize)]
 // 确保字段按 C 语言的内存布局排列。
@@ -141,14 +282,94 @@ pub struct Order {
 pub struct OrderNode {
     // 包含的订单信息
     pub order: Order,
-    // 指向下一个节点的索引
+    // 空闲列表中下一个空闲节点的索引
     pub next: u32,
-    // 指向前一个节点的索引
-    pub prev: u32,
     // 节点的当前状态标签
     pub tag: NodeTag,
     // 填充字节，确保结构体大小对齐，这对于内存操作很重要。
-    pub _padding: [u8; 7],
+    pub _padding: [u8; 3],
+}
+
+// `InnerNode` 结构体，代表订单簿 crit-bit 树中的一个内部节点。
+// 子树中所有关键字的前 `prefix_len` 位相同，第 `prefix_len` 位为 0 的在左子树，为 1 的在右子树。
+// 派生常用的 trait。
+#[derive(Clone, Copy, Pod, Zeroable, Default, AnchorSerialize, AnchorDeserialize)]
+// 确保字段按 C 语言的内存布局排列。
+#[repr(C)]
+// 定义 InnerNode 结构体。
+pub struct InnerNode {
+    // 子树中任意一个关键字（高 64 位在前），只有前 `prefix_len` 位有意义
+    pub key: [u64; 2],
+    // 左右子节点引用，最高位为 1 表示叶子节点（`LEAF_FLAG`）；空闲时 children[0] 指向下一个空闲节点
+    pub children: [u32; 2],
+    // 公共前缀的位数
+    pub prefix_len: u32,
+    // 节点的当前状态标签
+    pub tag: NodeTag,
+    // 填充字节，确保结构体大小对齐。
+    pub _padding: [u8; 3],
+}
+
+// 为 `InnerNode` 实现方法。
+impl InnerNode {
+    // 将拆成两个 u64 保存的关键字还原为 u128（避免 u128 在零拷贝账户中的对齐问题）。
+    pub fn key(&self) -> u128 {
+        ((self.key[0] as u128) << 64) | self.key[1] as u128
+    }
+
+    // 保存 u128 关键字。
+    pub fn set_key(&mut self, key: u128) {
+        self.key = [(key >> 64) as u64, key as u64];
+    }
+}
+
+// 判断一个子节点引用是否指向叶子节点。
+fn is_leaf_ref(node_ref: u32) -> bool {
+    node_ref & LEAF_FLAG != 0
+}
+
+// 从叶子节点引用中取出叶子数组的索引。
+fn leaf_index(node_ref: u32) -> u32 {
+    node_ref & !LEAF_FLAG
+}
+
+// 读取关键字从最高位开始数的第 `bit` 位（0 或 1），作为 crit-bit 树的子树方向。
+fn key_bit(key: u128, bit: u32) -> usize {
+    ((key >> (127 - bit)) & 1) as usize
+}
+
+// `QueueEvent` 结构体，事件队列中的一个事件，描述一笔需要结算到挂单所有者的变动。
+// 撮合时即根据当时的费率计算好各项余额变动，`consume_events` 只需按数值应用，与之后的费率调整无关。
+// 派生常用的 trait。
+#[derive(Clone, Copy, Pod, Zeroable, Default, AnchorSerialize, AnchorDeserialize)]
+// 确保字段按 C 语言的内存布局排列。
+#[repr(C)]
+// 定义 QueueEvent 结构体。
+pub struct QueueEvent {
+    // 挂单所有者的 OpenOrders 账户地址
+    pub owner_account: Pubkey,
+    // 挂单的订单 ID
+    pub order_id: u64,
+    // 成交或取消的基础代币数量
+    pub base_qty: u64,
+    // 成交的报价代币金额（Out 事件为 0）
+    pub quote_qty: u64,
+    // 挂单方支付的手续费，负数为返佣（Out 事件为 0）
+    pub fee: i64,
+    // 需要从锁定余额中扣除的基础代币
+    pub base_unlock: u64,
+    // 需要从锁定余额中扣除的报价代币
+    pub quote_unlock: u64,
+    // 需要计入可用余额的基础代币
+    pub base_credit: u64,
+    // 需要计入可用余额的报价代币
+    pub quote_credit: u64,
+    // 事件类型
+    pub kind: EventKind,
+    // 挂单是否已经完全成交或取消，需要从 OpenOrders 的活动订单列表中移除
+    pub order_done: PodBool,
+    // 填充字节，确保结构体大小对齐。
+    pub _padding: [u8; 6],
 }
 
 // `Market` 账户，存储一个交易对市场的所有配置和状态信息。
@@ -178,12 +399,14 @@ pub struct Market {
     pub bids: Pubkey,
     // 卖单簿账户地址
     pub asks: Pubkey,
+    // 事件队列账户地址
+    pub event_queue: Pubkey,
     // Market PDA 的 bump seed
     pub bump: u8,
     // 用于生成唯一订单 ID 的序列号
     pub order_sequence_number: u64,
-    // Maker（挂单者）手续费率（单位：基点, 1 bps = 0.01%）
-    pub maker_fee_bps: u16,
+    // Maker（挂单者）手续费率（单位：基点, 1 bps = 0.01%），负数表示向 maker 支付返佣
+    pub maker_fee_bps: i16,
     // Taker（吃单者）手续费率（单位：基点）
     pub taker_fee_bps: u16,
     // 基础代币的小数位数
@@ -200,6 +423,41 @@ pub struct Market {
     pub min_base_qty: u64,
     // 最小名义价值（价格 * 数量）
     pub min_notional: u64,
+    // 已提议、等待接受的新管理员地址（默认值表示没有待接受的提议）
+    pub pending_authority: Pubkey,
+    // 手续费分配：协议金库的代币账户地址（默认值表示尚未配置分配）
+    pub fee_treasury: Pubkey,
+    // 手续费分配：质押者奖励的代币账户地址
+    pub fee_stakers: Pubkey,
+    // 手续费分配：分给质押者的比例（基点），其余归协议金库
+    pub stakers_share_bps: u16,
+    // taker 手续费的收取币种
+    pub taker_fee_mode: TakerFeeMode,
+    // 以基础代币收取的手续费的金库地址（默认值表示尚未创建）
+    pub base_fee_vault: Pubkey,
+    // 下单时指定了推荐人时，taker 手续费分给推荐人的比例（单位：基点）
+    pub referrer_share_bps: u16,
+    // 当前配置的手续费等级数量（不为 0 时下单必须传入手续费等级账户，避免用户通过省略该账户绕开等级费率）
+    pub fee_tier_count: u8,
+}
+
+// 为 `Market` 实现订单校验方法。
+impl Market {
+    // 验证订单数量不低于最小下单量，名义价值（价格 * 数量 / PRICE_SCALE）不低于最小名义价值。
+    pub fn validate_order_size(&self, price: u64, quantity: u64) -> Result<()> {
+        require!(quantity >= self.min_base_qty, ErrorCode::BelowMinBaseQty);
+        require!(
+            quote_amount(price, quantity)? >= self.min_notional,
+            ErrorCode::BelowMinNotional
+        );
+        // 返回成功。
+        Ok(())
+    }
+
+    // 指定方向的 taker 是否以基础代币支付手续费（仅当买方以收到的资产支付时）。
+    pub fn taker_fee_in_base(&self, side: Side) -> bool {
+        side == Side::Bid && self.taker_fee_mode == TakerFeeMode::ReceivedAsset
+    }
 }
 
 // 为 `Market` 实现关联函数。
@@ -231,60 +489,267 @@ pub struct OpenOrders {
     pub base_token_locked: u64,
     // 因挂单而锁定的报价代币数量
     pub quote_token_locked: u64,
-    // 存储用户的活动订单 ID
-    pub order_ids: [u64; MAX_OPEN_ORDERS_PER_USER],
-    // 标记 order_ids 数组中的槽位是否被占用
-    pub is_initialized: [bool; MAX_OPEN_ORDERS_PER_USER],
+    // 活动订单的最大数量，可通过 `resize_open_orders` 调整
+    pub order_capacity: u16,
+    // 最近一次记录交易量的日期（Unix 时间戳 / 一天的秒数）
+    pub volume_day: u64,
+    // 最近 `VOLUME_WINDOW_DAYS` 天每天的成交额（报价代币），按日期对窗口长度取模循环使用
+    pub daily_volume: [u64; VOLUME_WINDOW_DAYS],
+    // 用户的活动订单，记录方向、价格和客户端订单 ID，取消时可直接在订单簿中按关键字定位
+    // 账户空间按 `order_capacity` 分配，`max_len` 只决定默认创建时的空间。
+    #[max_len(DEFAULT_OPEN_ORDERS_CAPACITY)]
+    pub orders: Vec<OpenOrderSlot>,
+}
+
+// `OpenOrders` 中的一个活动订单槽位。
+// 派生常用的 trait。
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, InitSpace)]
+// 定义 OpenOrderSlot 结构体。
+pub struct OpenOrderSlot {
+    // 订单 ID
+    pub order_id: u64,
+    // 客户端自定义的订单 ID（未指定时为 0）
+    pub client_order_id: u64,
+    // 订单在订单簿中的价格
+    pub price: u64,
+    // 是否为买单
+    pub is_bid: bool,
+}
+
+// 为 `OpenOrderSlot` 实现方法。
+impl OpenOrderSlot {
+    // 返回订单方向。
+    pub fn side(&self) -> Side {
+        if self.is_bid {
+            Side::Bid
+        } else {
+            Side::Ask
+        }
+    }
 }
 
 // 为 `OpenOrders` 实现方法。
 impl OpenOrders {
-    // 定义账户大小常量。
-    // pub const LEN: usize = 8 + size_of::<Self>();
-
-    // 查找一个未被使用的订单槽位。
-    pub fn find_free_slot(&self) -> Option<usize> {
-        // 遍历 is_initialized 数组，找到第一个为 false 的元素并返回其索引。
-        self.is_initialized.iter().position(|&init| !init)
-    }
-
-    // 将一个新订单 ID 添加到用户的活动订单列表中。
-    pub fn add_order(&mut self, order_id: u64) -> Result<()> {
-        // 找到一个空闲槽位，如果找不到则返回错误。
-        let slot = self.find_free_slot().ok_or(ErrorCode::OpenOrdersFull)?;
-        // 将订单 ID 存入该槽位。
-        self.order_ids[slot] = order_id;
-        // 标记该槽位已被占用。
-        self.is_initialized[slot] = true;
+    // 计算可容纳 `capacity` 个活动订单的账户空间（包括 8 字节的 discriminator）。
+    pub const fn space(capacity: u16) -> usize {
+        8 + Self::INIT_SPACE - DEFAULT_OPEN_ORDERS_CAPACITY as usize * OpenOrderSlot::INIT_SPACE
+            + capacity as usize * OpenOrderSlot::INIT_SPACE
+    }
+
+    // 如果账户是首次使用（market 地址为默认值），则进行初始化。
+    pub fn init_if_needed(&mut self, market: Pubkey, owner: Pubkey, bump: u8) {
+        if self.market == Pubkey::default() {
+            // 设置关联的市场地址。
+            self.market = market;
+            // 设置账户所有者地址。
+            self.owner = owner;
+            // 存储 OpenOrders PDA 的 bump seed。
+            self.bump = bump;
+            // 新账户的空间按默认容量分配。
+            self.order_capacity = DEFAULT_OPEN_ORDERS_CAPACITY;
+        }
+    }
+
+    // 将一个新订单添加到用户的活动订单列表中。
+    pub fn add_order(
+        &mut self,
+        order_id: u64,
+        client_order_id: u64,
+        price: u64,
+        side: Side,
+    ) -> Result<()> {
+        // 活动订单数量已达容量上限时返回错误。
+        require!(
+            self.orders.len() < self.order_capacity as usize,
+            ErrorCode::OpenOrdersFull
+        );
+        // 记录订单 ID、客户端订单 ID、价格和方向。
+        self.orders.push(OpenOrderSlot {
+            order_id,
+            client_order_id,
+            price,
+            is_bid: side == Side::Bid,
+        });
         // 返回成功。
         Ok(())
     }
 
+    // 根据订单 ID 查找用户的活动订单。
+    pub fn find_order(&self, order_id: u64) -> Option<OpenOrderSlot> {
+        // 查找订单 ID 匹配的槽位。
+        self.orders
+            .iter()
+            .find(|slot| slot.order_id == order_id)
+            .copied()
+    }
+
+    // 根据客户端订单 ID 查找用户的活动订单。
+    pub fn find_order_by_client_id(&self, client_order_id: u64) -> Option<OpenOrderSlot> {
+        // 查找客户端订单 ID 匹配的槽位。
+        self.orders
+            .iter()
+            .find(|slot| slot.client_order_id == client_order_id)
+            .copied()
+    }
+
     // 从用户的活动订单列表中移除一个订单 ID。
     pub fn remove_order(&mut self, order_id_to_remove: u64) -> Result<()> {
         // 查找指定 order_id 所在的槽位。
         let slot = self
-            // 迭代 is_initialized 数组。
-            .is_initialized
+            .orders
             .iter()
-            // 将其与 order_ids 数组的迭代器配对。
-            .zip(self.order_ids.iter())
-            // 查找第一个满足条件的元素位置：已初始化且订单ID匹配。
-            .position(|(&initialized, &id)| initialized && id == order_id_to_remove)
+            .position(|slot| slot.order_id == order_id_to_remove)
             // 如果找不到，则返回 OrderNotFoundInOpenOrders 错误。
             .ok_or(ErrorCode::OrderNotFoundInOpenOrders)?;
 
-        // 标记该槽位为空闲。
-        self.is_initialized[slot] = false;
-        // 重置订单ID为0。
-        self.order_ids[slot] = 0;
+        // 移除该槽位，保持其余订单的先后顺序。
+        self.orders.remove(slot);
+        // 返回成功。
+        Ok(())
+    }
+
+    // 将一笔成交额计入当天的交易量，进入新的一天时先清零窗口中已经过期的天数。
+    pub fn record_volume(&mut self, day: u64, quote_qty: u64) -> Result<()> {
+        let window = VOLUME_WINDOW_DAYS as u64;
+        if day > self.volume_day {
+            // 上次记录之后到今天之间的每一天（最多整个窗口）都没有交易量。
+            for offset in 0..std::cmp::min(day - self.volume_day, window) {
+                self.daily_volume[((day - offset) % window) as usize] = 0;
+            }
+            self.volume_day = day;
+        }
+        // 累加到最近一次记录的日期。
+        let volume = &mut self.daily_volume[(self.volume_day % window) as usize];
+        *volume = volume
+            .checked_add(quote_qty)
+            .ok_or(ErrorCode::MathOverflow)?;
         // 返回成功。
         Ok(())
     }
+
+    // 截至 `day`（含当天）最近 `VOLUME_WINDOW_DAYS` 天的成交额总和。
+    pub fn rolling_volume(&self, day: u64) -> u64 {
+        let window = VOLUME_WINDOW_DAYS as u64;
+        // 上次记录之后经过的天数已经移出窗口。
+        let elapsed = day.saturating_sub(self.volume_day);
+        if elapsed >= window {
+            return 0;
+        }
+        // 从最近一次记录的日期往前累加仍在窗口内的天数。
+        (0..window - elapsed)
+            .map(|offset| {
+                self.daily_volume[((self.volume_day + window - offset) % window) as usize]
+            })
+            .fold(0, u64::saturating_add)
+    }
+}
+
+// 手续费等级，用户满足门槛时使用该等级的费率代替市场统一费率。
+// 派生常用的 trait。
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, InitSpace)]
+// 定义 FeeTier 结构体。
+pub struct FeeTier {
+    // 最近 30 天成交额（报价代币）的门槛，0 表示不要求
+    pub min_volume: u64,
+    // 持有 `FeeTiers.holding_mint` 代币数量的门槛，0 表示不要求
+    pub min_holding: u64,
+    // 该等级的 maker 手续费率（负数为返佣）
+    pub maker_fee_bps: i16,
+    // 该等级的 taker 手续费率
+    pub taker_fee_bps: u16,
+}
+
+// `FeeTiers` 账户，每个市场一个，存储按交易量或持仓划分的手续费等级。
+// Anchor 宏，标记这是一个账户结构体。
+#[account]
+// 派生 Default 和 InitSpace traits。
+#[derive(Default, InitSpace)]
+// 定义 FeeTiers 结构体。
+pub struct FeeTiers {
+    // 关联的市场地址
+    pub market: Pubkey,
+    // FeeTiers PDA 的 bump seed
+    pub bump: u8,
+    // 用于持仓门槛的代币 mint（例如平台代币）
+    pub holding_mint: Pubkey,
+    // 手续费等级，按从低到高的顺序排列，用户满足门槛的最后一个等级生效
+    #[max_len(MAX_FEE_TIERS)]
+    pub tiers: Vec<FeeTier>,
+}
+
+// 为 `FeeTiers` 实现方法。
+impl FeeTiers {
+    // 根据用户的交易量和持仓确定适用的费率，不满足任何等级时使用市场统一费率。
+    pub fn rates_for(&self, market: &Market, volume: u64, holding: u64) -> FeeRates {
+        self.tiers
+            .iter()
+            .enumerate()
+            .rev()
+            .find(|(_, tier)| volume >= tier.min_volume && holding >= tier.min_holding)
+            .map_or(FeeRates::flat(market), |(index, tier)| FeeRates {
+                tier: index as u8 + 1,
+                maker_fee_bps: tier.maker_fee_bps,
+                taker_fee_bps: tier.taker_fee_bps,
+            })
+    }
+}
+
+// 用户下单时适用的手续费率。
+#[derive(Clone, Copy)]
+pub struct FeeRates {
+    // 所在的手续费等级（从 1 开始，0 表示使用市场统一费率）
+    pub tier: u8,
+    // maker 手续费率（负数为返佣）
+    pub maker_fee_bps: i16,
+    // taker 手续费率
+    pub taker_fee_bps: u16,
+}
+
+// 为 `FeeRates` 实现方法。
+impl FeeRates {
+    // 市场统一费率。
+    pub fn flat(market: &Market) -> Self {
+        FeeRates {
+            tier: 0,
+            maker_fee_bps: market.maker_fee_bps,
+            taker_fee_bps: market.taker_fee_bps,
+        }
+    }
+
+    // 确定用户适用的手续费率：按用户最近 30 天的成交额和持仓确定等级；
+    // 市场没有配置等级时可以省略手续费等级账户，使用市场统一费率。
+    // 持仓按指令执行时代币账户的余额计算，无法区分同一笔交易中临时借入（如闪电贷）的代币，
+    // 因此持仓门槛只适合用于折扣较小的等级，需要防范这种情况的市场应只使用交易量门槛。
+    pub fn for_user(
+        market: &Market,
+        fee_tiers: Option<&Account<FeeTiers>>,
+        holding_token_account: Option<&InterfaceAccount<TokenAccount>>,
+        owner: Pubkey,
+        volume: u64,
+    ) -> Result<Self> {
+        let Some(fee_tiers) = fee_tiers else {
+            require!(market.fee_tier_count == 0, ErrorCode::FeeTiersRequired);
+            return Ok(Self::flat(market));
+        };
+        // 持仓门槛只认用户自己的、mint 为 `holding_mint` 的代币账户，未传入时视为没有持仓。
+        let holding = match holding_token_account {
+            Some(account) => {
+                require_keys_eq!(account.owner, owner, ErrorCode::Unauthorized);
+                require_keys_eq!(account.mint, fee_tiers.holding_mint, ErrorCode::InvalidMint);
+                account.amount
+            }
+            None => 0,
+        };
+        Ok(fee_tiers.rates_for(market, volume, holding))
+    }
 }
 
 // `OrderBook` 账户，存储买单簿或卖单簿。
 // 使用 zero_copy，避免大数据在堆栈和堆之间复制，提高性能。
+// 订单以 crit-bit 树（二进制前缀树）组织，关键字为 (价格, 序号)，插入、按关键字查找/删除以及获取最优价格都与树高成正比，
+// 而不是像链表那样需要遍历整个订单簿。
+// 该结构体只是账户的头部：其后紧跟 `capacity` 个叶子节点 (`OrderNode`) 和 `capacity` 个内部节点 (`InnerNode`)，
+// 容量在 `initialize_market` 时指定，之后可以通过 `grow_order_book` 扩容。节点数组通过 `BookSide` 访问。
 #[account(zero_copy)]
 // 保证内存布局
 #[repr(C)]
@@ -298,1153 +763,3068 @@ pub struct OrderBook {
     pub bump: u8,
     // 填充字节以对齐内存。
     _padding1: [u8; 2],
-    // 链表的头节点索引
-    pub head: u32,
-    // 链表的尾节点索引
-    pub tail: u32,
-    // 空闲节点链表的头节点索引
+    // 树的根节点引用（`SENTINEL` 表示空树）
+    pub root: u32,
+    // 空闲叶子节点链表的头节点索引
     pub free_list_head: u32,
+    // 空闲内部节点链表的头节点索引
+    pub free_inner_head: u32,
     // 订单簿中的订单总数
     pub count: u32,
-    // 填充字节以对齐内存。
-    _padding2: [u8; 4],
-    // 存储所有订单节点的数组
-    pub nodes: [OrderNode; MAX_ORDERS_PER_BOOK],
+    // 账户可以容纳的订单数量（叶子节点和内部节点数组的长度）
+    pub capacity: u32,
+    // 从未被使用过的第一个叶子节点索引，空闲列表为空时从这里继续分配
+    pub leaf_bump_index: u32,
+    // 从未被使用过的第一个内部节点索引
+    pub inner_bump_index: u32,
 }
 
-// 为 `OrderBook` 实现方法。
+// 为 `OrderBook` 实现关联函数。
 impl OrderBook {
-    // 定义账户大小常量。
-    pub const LEN: usize = 8 + size_of::<Self>();
+    // 账户头部的大小，包括 8 字节的 discriminator。
+    pub const HEADER_LEN: usize = 8 + size_of::<Self>();
+
+    // 计算指定容量的订单簿账户所需的空间。
+    pub fn space(capacity: u32) -> usize {
+        Self::HEADER_LEN + capacity as usize * (size_of::<OrderNode>() + size_of::<InnerNode>())
+    }
+
+    // 创建时的容量是否有效：至少容纳一个订单，不超过叶子标志位，
+    // 且账户空间不超过通过 CPI 创建账户时的上限 `MAX_PERMITTED_DATA_INCREASE`（10240 字节）。
+    pub fn is_valid_initial_capacity(capacity: u32) -> bool {
+        capacity > 0 && capacity < LEAF_FLAG && Self::space(capacity) <= MAX_PERMITTED_DATA_INCREASE
+    }
+
+    // 计算指定容量下内部节点数组在账户数据中的起始偏移。
+    pub fn inner_nodes_offset(capacity: u32) -> usize {
+        Self::HEADER_LEN + capacity as usize * size_of::<OrderNode>()
+    }
 
-    // 初始化订单簿。
-    pub fn initialize(&mut self, market: Pubkey, is_bids: bool, bump: u8) -> Result<()> {
+    // 初始化订单簿头部。节点数组保持全零（`Uninitialized`），按需通过 bump 索引分配。
+    pub fn initialize(&mut self, market: Pubkey, is_bids: bool, bump: u8, capacity: u32) {
         // 设置关联的市场地址。
         self.market = market;
         // 将 bool 转换为 PodBool 并设置。
         self.is_bids = is_bids.into();
         // 设置 PDA 的 bump seed。
         self.bump = bump;
-        // 初始时链表为空，头指针指向哨兵值。
-        self.head = SENTINEL;
-        // 初始时链表为空，尾指针指向哨兵值。
-        self.tail = SENTINEL;
+        // 初始时树为空，根节点指向哨兵值。
+        self.root = SENTINEL;
+        // 初始时两个空闲列表都为空。
+        self.free_list_head = SENTINEL;
+        self.free_inner_head = SENTINEL;
         // 初始订单数量为 0。
         self.count = 0;
-        // 空闲列表从索引 0 开始。
-        self.free_list_head = 0;
-
-        // 遍历所有节点，将它们串成一个空闲列表。
-        for i in 0..MAX_ORDERS_PER_BOOK {
-            // 设置节点标签为空闲。
-            self.nodes[i].tag = NodeTag::FreeNode;
-            // 指向下一个节点，形成链表。
-            self.nodes[i].next = (i as u32) + 1;
-            // 初始时 prev 指向哨兵值。
-            self.nodes[i].prev = SENTINEL;
-            // 将订单数据重置为默认值。
-            self.nodes[i].order = Default::default();
-        }
-        // 最后一个空闲节点的 next 指向哨兵值，表示列表结束。
-        self.nodes[MAX_ORDERS_PER_BOOK - 1].next = SENTINEL;
-        // 返回成功。
-        Ok(())
+        // 设置容量，所有节点都尚未使用。
+        self.capacity = capacity;
+        self.leaf_bump_index = 0;
+        self.inner_bump_index = 0;
+    }
+}
+
+// `BookSide` 是订单簿账户的可变视图：头部加上紧随其后的叶子节点和内部节点数组。
+// 所有订单簿操作都通过它进行。
+pub struct BookSide<'a> {
+    // 账户头部
+    pub header: RefMut<'a, OrderBook>,
+    // 存储所有订单的叶子节点数组
+    pub nodes: RefMut<'a, [OrderNode]>,
+    // 存储树内部节点的数组（n 个叶子最多需要 n - 1 个内部节点）
+    pub inner_nodes: RefMut<'a, [InnerNode]>,
+}
+
+// 为 `BookSide` 实现方法。
+impl<'a> BookSide<'a> {
+    // 加载订单簿账户，并将其数据拆分为头部和两个节点数组。
+    pub fn load_mut(loader: &'a AccountLoader<'_, OrderBook>) -> Result<Self> {
+        // 先通过 AccountLoader 校验 discriminator 和可写性，并读取容量。
+        let capacity = loader.load_mut()?.capacity;
+        // 借用完整的账户数据。
+        let data = loader.as_ref().try_borrow_mut_data()?;
+        // 账户空间必须足以容纳声明的容量。
+        require!(
+            data.len() >= OrderBook::space(capacity),
+            ErrorCode::InvalidBookCapacity
+        );
+        // 依次拆分出头部、叶子节点数组和内部节点数组。
+        let leaves_len = capacity as usize * size_of::<OrderNode>();
+        let inners_len = capacity as usize * size_of::<InnerNode>();
+        let (header, nodes) =
+            RefMut::map_split(data, |data| data.split_at_mut(OrderBook::HEADER_LEN));
+        let header = RefMut::map(header, |header| bytemuck::from_bytes_mut(&mut header[8..]));
+        let (nodes, inner_nodes) = RefMut::map_split(nodes, |nodes| {
+            let (leaves, inners) = nodes.split_at_mut(leaves_len);
+            (
+                bytemuck::cast_slice_mut(leaves),
+                bytemuck::cast_slice_mut(&mut inners[..inners_len]),
+            )
+        });
+        // 返回视图。
+        Ok(Self {
+            header,
+            nodes,
+            inner_nodes,
+        })
+    }
+
+    // 计算订单在树中的关键字：高 64 位为价格，低 64 位为时间序号。
+    // 卖单簿取最小关键字为最优，序号即订单 ID（越早越小）；买单簿取最大关键字为最优，
+    // 因此对订单 ID 取反，使同价格下越早的订单关键字越大。
+    pub fn order_key(&self, price: u64, order_id: u64) -> u128 {
+        // 根据订单簿方向计算时间序号。
+        let seq = if self.header.is_bids.into() {
+            !order_id
+        } else {
+            order_id
+        };
+        // 拼接价格和序号。
+        ((price as u128) << 64) | seq as u128
     }
 
-    // 从空闲列表中获取一个新节点。
-    fn new_node(&mut self) -> Result<u32> {
-        // 如果空闲列表为空（头指针为哨兵值），则订单簿已满。
-        if self.free_list_head == SENTINEL {
-            // 返回 OrderBookFull 错误。
+    // 获取一个新叶子节点并写入订单：优先复用空闲列表，否则使用从未用过的节点。
+    fn new_leaf(&mut self, order: Order) -> Result<u32> {
+        let index = if self.header.free_list_head != SENTINEL {
+            // 从空闲列表头部取出节点，并更新空闲列表的头指针为下一个空闲节点。
+            let index = self.header.free_list_head;
+            self.header.free_list_head = self.nodes[index as usize].next;
+            index
+        } else if self.header.leaf_bump_index < self.header.capacity {
+            // 空闲列表为空时，分配下一个从未使用过的节点。
+            let index = self.header.leaf_bump_index;
+            self.header.leaf_bump_index += 1;
+            index
+        } else {
+            // 所有节点都已被占用，订单簿已满。
             return err!(ErrorCode::OrderBookFull);
-        }
-        // 获取当前空闲列表的头节点作为新节点。
-        let new_node_index = self.free_list_head;
-        // 更新空闲列表的头节点为下一个空闲节点。
-        let next_free_node_index = self.nodes[new_node_index as usize].next;
-        // 更新空闲列表的头指针。
-        self.free_list_head = next_free_node_index;
-        // 更新新节点的状态为 OrderNode。
-        self.nodes[new_node_index as usize].tag = NodeTag::OrderNode;
-        // 新节点的 next 指针初始化为哨兵值。
-        self.nodes[new_node_index as usize].next = SENTINEL;
-        // 新节点的 prev 指针初始化为哨兵值。
-        self.nodes[new_node_index as usize].prev = SENTINEL;
+        };
+        // 更新新节点的状态和数据。
+        let node = &mut self.nodes[index as usize];
+        node.tag = NodeTag::OrderNode;
+        node.next = SENTINEL;
+        node.order = order;
         // 订单数量加一，使用 checked_add 防止溢出。
-        self.count = self.count.checked_add(1).ok_or(ErrorCode::MathOverflow)?;
+        self.header.count = self
+            .header
+            .count
+            .checked_add(1)
+            .ok_or(ErrorCode::MathOverflow)?;
         // 返回新节点的索引。
-        Ok(new_node_index)
-    }
-
-    // 将一个节点释放回空闲列表。
-    fn release_node(&mut self, node_index: u32) -> Result<()> {
-        // 获取当前空闲列表的头节点。
-        let current_free_list_head = self.free_list_head;
-        // 更新节点状态为 FreeNode。
-        self.nodes[node_index as usize].tag = NodeTag::FreeNode;
-        // 将此节点的 next 指向原空闲列表的头部。
-        self.nodes[node_index as usize].next = current_free_list_head;
-        // 更新空闲列表的头指针为此节点。
-        self.free_list_head = node_index;
+        Ok(index)
+    }
+
+    // 将一个叶子节点释放回空闲列表。
+    fn release_leaf(&mut self, index: u32) {
+        // 更新节点状态为 FreeNode，并将其放到空闲列表头部。
+        let free_list_head = self.header.free_list_head;
+        let node = &mut self.nodes[index as usize];
+        node.tag = NodeTag::FreeNode;
+        node.next = free_list_head;
+        node.order = Default::default();
+        self.header.free_list_head = index;
         // 数量减一，saturating_sub 防止下溢（虽然在这里不太可能发生）。
-        self.count = self.count.saturating_sub(1);
-        // 返回成功。
-        Ok(())
+        self.header.count = self.header.count.saturating_sub(1);
     }
 
-    // 向订单簿中添加一个新订单，并保持价格/时间优先排序。
-    pub fn add_order(&mut self, order: Order) -> Result<()> {
-        // 1. 从空闲列表分配一个新节点用于存放订单。
-        let new_node_index = self.new_node()?;
-
-        // 2. 寻找正确的插入位置。
-        // 从头节点开始遍历，寻找第一个“不符合”排序规则的节点，即新订单应该插入到该节点之前。
-        let mut insertion_point = self.head;
-        while insertion_point != SENTINEL {
-            let current_node = &self.nodes[insertion_point as usize];
-
-            // 比较函数，确定新订单是否应该排在当前节点之前。
-            let should_insert_before = if self.is_bids.into() {
-                // 买单簿(bids)：价格从高到低，时间从早到晚 (order_id 小的优先)。
-                order.price > current_node.order.price
-                    || (order.price == current_node.order.price
-                        && order.order_id < current_node.order.order_id)
-            } else {
-                // 卖单簿(asks)：价格从低到高，时间从早到晚。
-                order.price < current_node.order.price
-                    || (order.price == current_node.order.price
-                        && order.order_id < current_node.order.order_id)
-            };
+    // 获取一个新内部节点：优先复用空闲列表，否则使用从未用过的节点。
+    fn new_inner(&mut self) -> Result<u32> {
+        let index = if self.header.free_inner_head != SENTINEL {
+            // 空闲时用 children[0] 串联空闲列表。
+            let index = self.header.free_inner_head;
+            self.header.free_inner_head = self.inner_nodes[index as usize].children[0];
+            index
+        } else if self.header.inner_bump_index < self.header.capacity {
+            // 分配下一个从未使用过的节点。
+            let index = self.header.inner_bump_index;
+            self.header.inner_bump_index += 1;
+            index
+        } else {
+            // 内部节点数量总是少于叶子节点，正常情况下不会耗尽。
+            return err!(ErrorCode::OrderBookFull);
+        };
+        // 更新节点状态。
+        self.inner_nodes[index as usize].tag = NodeTag::InnerNode;
+        // 返回新节点的索引。
+        Ok(index)
+    }
 
-            if should_insert_before {
-                // 找到了插入点，跳出循环。
-                break;
-            }
-            // 继续寻找下一个节点。
-            insertion_point = current_node.next;
+    // 将一个内部节点释放回空闲列表。
+    fn release_inner(&mut self, index: u32) {
+        // 重置节点，并用 children[0] 串联到空闲列表头部。
+        self.inner_nodes[index as usize] = InnerNode {
+            children: [self.header.free_inner_head, SENTINEL],
+            tag: NodeTag::FreeNode,
+            ..Default::default()
+        };
+        self.header.free_inner_head = index;
+    }
+
+    // 读取节点引用对应的关键字和有效前缀位数（叶子节点的前缀为完整的 128 位）。
+    fn node_key(&self, node_ref: u32) -> (u128, u32) {
+        if is_leaf_ref(node_ref) {
+            // 叶子节点：根据订单计算关键字。
+            let order = &self.nodes[leaf_index(node_ref) as usize].order;
+            (self.order_key(order.price, order.order_id), 128)
+        } else {
+            // 内部节点：返回保存的前缀关键字。
+            let inner = &self.inner_nodes[node_ref as usize];
+            (inner.key(), inner.prefix_len)
         }
+    }
 
-        // 3. 执行统一的链表插入逻辑。
-        // `insertion_point` 现在是新节点将要插入的位置的前一个节点 (即新节点的 next)。
-        // 它可以是链表中的一个节点，也可以是 SENTINEL (表示插入到尾部)。
+    // 向订单簿中添加一个新订单。
+    pub fn add_order(&mut self, order: Order) -> Result<()> {
+        // 1. 计算关键字，并从空闲列表分配一个叶子节点用于存放订单。
+        let key = self.order_key(order.price, order.order_id);
+        let leaf_ref = self.new_leaf(order)? | LEAF_FLAG;
 
-        let prev_node_index;
-        let next_node_index = insertion_point;
+        // 2. 空树时新叶子直接成为根节点。
+        if self.header.root == SENTINEL {
+            self.header.root = leaf_ref;
+            return Ok(());
+        }
 
-        if next_node_index == self.head {
-            // 情况 A: 插入到链表的最前面 (包括空链表的情况)。
-            prev_node_index = SENTINEL;
-            self.head = new_node_index;
-        } else {
-            // 情况 B: 插入到中间或尾部。
-            // 此时 `next_node_index` 要么是一个有效节点，要么是 SENTINEL (尾部)。
-            // 我们需要找到它的前一个节点。
-            if next_node_index == SENTINEL {
-                // 如果插入到尾部，前一个节点就是当前的 tail。
-                prev_node_index = self.tail;
-            } else {
-                // 如果插入到中间，前一个节点是 `next_node_index` 的 prev。
-                prev_node_index = self.nodes[next_node_index as usize].prev;
+        // 3. 从根节点向下查找：找到第一个前缀与新关键字不一致的节点，新叶子将与它分叉。
+        // `parent` 记录当前节点的父节点及其所在的子树方向。
+        let mut parent: Option<(u32, usize)> = None;
+        let mut current = self.header.root;
+        loop {
+            // 计算新关键字与当前节点关键字的公共前缀长度。
+            let (current_key, prefix_len) = self.node_key(current);
+            let shared_len = (key ^ current_key).leading_zeros();
+            if shared_len < prefix_len {
+                // 在公共前缀之后分叉，跳出循环插入新的内部节点。
+                break;
             }
-            // 更新前一个节点的 `next` 指针。
-            self.nodes[prev_node_index as usize].next = new_node_index;
+            // 叶子节点的前缀为完整关键字，完全相同说明订单重复。
+            require!(!is_leaf_ref(current), ErrorCode::DuplicateOrderKey);
+            // 前缀完全匹配，按下一位继续向下查找。
+            let dir = key_bit(key, prefix_len);
+            parent = Some((current, dir));
+            current = self.inner_nodes[current as usize].children[dir];
         }
 
-        if next_node_index == SENTINEL {
-            // 如果新节点是最后一个节点，更新 tail 指针。
-            self.tail = new_node_index;
-        } else {
-            // 如果新节点后面还有节点，更新后面那个节点的 `prev` 指针。
-            self.nodes[next_node_index as usize].prev = new_node_index;
+        // 4. 创建新的内部节点，新叶子和原子树按分叉位分别放到两侧。
+        let (current_key, _) = self.node_key(current);
+        let shared_len = (key ^ current_key).leading_zeros();
+        let dir = key_bit(key, shared_len);
+        let inner_index = self.new_inner()?;
+        let inner = &mut self.inner_nodes[inner_index as usize];
+        inner.set_key(key);
+        inner.prefix_len = shared_len;
+        inner.children[dir] = leaf_ref;
+        inner.children[1 - dir] = current;
+
+        // 5. 将新内部节点挂到原位置。
+        match parent {
+            None => self.header.root = inner_index,
+            Some((p, d)) => self.inner_nodes[p as usize].children[d] = inner_index,
         }
-
-        // 4. 设置新节点自身的指针和数据。
-        let new_node = &mut self.nodes[new_node_index as usize];
-        new_node.prev = prev_node_index;
-        new_node.next = next_node_index;
-        new_node.order = order;
-
+        // 返回成功。
         Ok(())
     }
-    // 从订单簿中移除一个订单。
-    pub fn remove_order(&mut self, node_index: u32) -> Result<()> {
-        // 获取要移除节点的引用。
-        let node = &self.nodes[node_index as usize];
-        // 获取前一个节点的索引。
-        let prev_index = node.prev;
-        // 获取后一个节点的索引。
-        let next_index = node.next;
-
-        // 更新前后节点的指针，将当前节点从链表中"摘除"。
-        if prev_index != SENTINEL {
-            // 如果存在前一个节点，更新其 next 指针。
-            self.nodes[prev_index as usize].next = next_index;
-        } else {
-            // 如果移除的是头节点，更新链表的头指针。
-            self.head = next_index;
+
+    // 按关键字查找叶子节点，返回叶子索引、父节点和祖父节点（各自带子树方向）。
+    #[allow(clippy::type_complexity)]
+    fn find_leaf(&self, key: u128) -> Option<(u32, Option<(u32, usize)>, Option<(u32, usize)>)> {
+        // 空树时直接返回。
+        if self.header.root == SENTINEL {
+            return None;
         }
-        // 如果存在后一个节点。
-        if next_index != SENTINEL {
-            // 更新其 prev 指针。
-            self.nodes[next_index as usize].prev = prev_index;
-        } else {
-            // 如果移除的是尾节点，更新链表的尾指针。
-            self.tail = prev_index;
+        // 从根节点开始，按每个内部节点的分叉位向下查找。
+        let mut parent = None;
+        let mut grandparent = None;
+        let mut current = self.header.root;
+        while !is_leaf_ref(current) {
+            let inner = &self.inner_nodes[current as usize];
+            let dir = key_bit(key, inner.prefix_len);
+            grandparent = parent;
+            parent = Some((current, dir));
+            current = inner.children[dir];
         }
-        // 将节点释放回空闲列表。
-        self.release_node(node_index)?;
-        // 返回成功。
-        Ok(())
+        // 到达叶子后比较完整关键字，确认是否命中。
+        let index = leaf_index(current);
+        let order = &self.nodes[index as usize].order;
+        if self.order_key(order.price, order.order_id) != key {
+            return None;
+        }
+        Some((index, parent, grandparent))
     }
 
-    // 根据索引获取一个节点的引用。
-    pub fn get_node(&self, node_index: u32) -> Option<&OrderNode> {
-        // 如果索引是哨兵值，表示无效节点。
-        if node_index == SENTINEL {
-            // 返回 None。
-            None
-        } else {
-            // 否则返回该索引处节点的引用。
-            Some(&self.nodes[node_index as usize])
+    // 根据价格和订单 ID 查找订单。
+    pub fn find_order(&self, price: u64, order_id: u64) -> Option<Order> {
+        // 计算关键字并查找对应的叶子节点。
+        let (index, _, _) = self.find_leaf(self.order_key(price, order_id))?;
+        Some(self.nodes[index as usize].order)
+    }
+
+    // 根据价格和订单 ID 从订单簿中移除一个订单，并返回被移除的订单。
+    pub fn remove_order(&mut self, price: u64, order_id: u64) -> Result<Order> {
+        // 查找对应的叶子节点，找不到则返回 OrderNotFound 错误。
+        let (index, parent, grandparent) = self
+            .find_leaf(self.order_key(price, order_id))
+            .ok_or(ErrorCode::OrderNotFound)?;
+        let order = self.nodes[index as usize].order;
+
+        match parent {
+            // 叶子就是根节点，移除后树为空。
+            None => self.header.root = SENTINEL,
+            // 否则用兄弟子树替换父节点，并释放父节点。
+            Some((p, d)) => {
+                let sibling = self.inner_nodes[p as usize].children[1 - d];
+                match grandparent {
+                    None => self.header.root = sibling,
+                    Some((g, gd)) => self.inner_nodes[g as usize].children[gd] = sibling,
+                }
+                self.release_inner(p);
+            }
+        }
+        // 将叶子节点释放回空闲列表。
+        self.release_leaf(index);
+        // 返回被移除的订单。
+        Ok(order)
+    }
+
+    // 原地减少一个订单的剩余数量，订单在树中的位置（即时间优先级）保持不变。
+    // 返回减少之前的订单。
+    pub fn reduce_order(&mut self, price: u64, order_id: u64, base_qty: u64) -> Result<Order> {
+        // 查找对应的叶子节点，找不到则返回 OrderNotFound 错误。
+        let (index, _, _) = self
+            .find_leaf(self.order_key(price, order_id))
+            .ok_or(ErrorCode::OrderNotFound)?;
+        let order = self.nodes[index as usize].order;
+        // 只能减少数量，不能增加或减为 0。
+        require!(
+            base_qty > 0 && base_qty < order.base_qty,
+            ErrorCode::InvalidOrderInput
+        );
+        // 写回新的剩余数量，并扣除减少部分的锁定金额。
+        self.nodes[index as usize]
+            .order
+            .reduce(order.base_qty - base_qty)?;
+        Ok(order)
+    }
+
+    // 获取最优价格订单所在的叶子节点索引。
+    fn best_leaf(&self) -> Option<u32> {
+        // 空树时没有最优订单。
+        if self.header.root == SENTINEL {
+            return None;
         }
+        // 买单簿取关键字最大的叶子（一直向右），卖单簿取最小的叶子（一直向左）。
+        let dir = if self.header.is_bids.into() { 1 } else { 0 };
+        let mut current = self.header.root;
+        while !is_leaf_ref(current) {
+            current = self.inner_nodes[current as usize].children[dir];
+        }
+        Some(leaf_index(current))
     }
 
     // 获取最优价格的订单（买单簿的最高价，卖单簿的最低价）。
     pub fn get_best_price_order(&self) -> Option<Order> {
-        // 获取头节点，并将其中的 order 字段映射出来。
-        self.get_node(self.head).map(|n| n.order)
-    }
-
-    // 根据订单 ID 查找订单。
-    pub fn find_order_by_id(&self, order_id: u64) -> Option<(u32, Order)> {
-        // 从头节点开始遍历。
-        let mut current_index = self.head;
-        // 循环直到链表末尾。
-        while current_index != SENTINEL {
-            // 获取当前节点。
-            let node = self.get_node(current_index)?;
-            // 如果找到匹配的订单 ID。
-            if node.order.order_id == order_id {
-                // 返回节点索引和订单信息。
-                return Some((current_index, node.order));
-            }
-            // 移动到下一个节点。
-            current_index = node.next;
+        // 找到最优叶子并返回其中的订单。
+        self.best_leaf()
+            .map(|index| self.nodes[index as usize].order)
+    }
+
+    // 从最优价格的订单中扣减成交数量，若完全成交则将其移出订单簿。
+    // 返回扣减后的订单（`base_qty` 为 0 表示已被移除）。
+    pub fn fill_best_order(&mut self, base_qty: u64) -> Result<Order> {
+        // 订单簿为空时无法成交。
+        let index = self.best_leaf().ok_or(ErrorCode::OrderBookEmpty)?;
+        let mut order = self.nodes[index as usize].order;
+        // 扣减剩余数量及相应的锁定金额。
+        order.reduce(base_qty)?;
+        if order.base_qty == 0 {
+            // 完全成交，从树中移除。
+            self.remove_order(order.price, order.order_id)?;
+        } else {
+            // 部分成交，写回剩余数量。
+            self.nodes[index as usize].order = order;
         }
-        // 如果遍历完都找不到，返回 None。
-        None
+        // 返回更新后的订单。
+        Ok(order)
     }
 }
 
-// --- 指令模块 ---
-// Anchor 宏，声明这是一个 Solana 程序的主模块。
-#[program]
-// 定义程序的主模块。
-pub mod order_book_dex {
-    // 导入父模块（顶层）的所有内容。
-    use super::*;
+// `EventQueue` 账户，存储撮合产生、尚未结算到 maker OpenOrders 账户的事件。
+// 撮合只需要写入事件，因此下单和撮合时不需要传入 maker 的账户；
+// 任何人都可以调用 `consume_events` 按顺序处理这些事件。
+// 使用 zero_copy 的环形缓冲区实现。
+#[account(zero_copy)]
+// 保证内存布局
+#[repr(C)]
+// 定义 EventQueue 结构体。
+pub struct EventQueue {
+    // 关联的市场地址
+    pub market: Pubkey,
+    // 第一个待处理事件的索引
+    pub head: u32,
+    // 待处理事件的数量
+    pub count: u32,
+    // 累计写入的事件总数
+    pub seq_num: u64,
+    // EventQueue PDA 的 bump seed
+    pub bump: u8,
+    // 填充字节以对齐内存。
+    _padding: [u8; 7],
+    // 存储事件的环形缓冲区
+    pub events: [QueueEvent; EVENT_QUEUE_CAPACITY],
+}
 
-    // `initialize_market` 指令：创建一个新的交易市场。
-    pub fn initialize_market(
-        // 账户上下文
-        ctx: Context<InitializeMarket>,
-        // Maker 手续费率
-        maker_fee_bps: u16,
-        // Taker 手续费率
-        taker_fee_bps: u16,
-        // 价格最小变动单位
-        tick_size: u64,
-        // 数量最小变动单位
-        base_lot_size: u64,
-        // 可选：最小下单数量
-        min_base_qty: Option<u64>,
-        // 可选：最小名义价值
-        min_notional: Option<u64>,
-    ) -> Result<()> {
-        // 获取 market 账户的可变引用。
-        let market = &mut ctx.accounts.market;
-        // 验证手续费率是否在有效范围内 (0% - 100%)。10_000 bps = 100%。
-        require!(
-            maker_fee_bps <= 10_000 && taker_fee_bps <= 10_000,
-            ErrorCode::InvalidFee
-        );
-        // 验证 tick_size 和 lot_size 必须大于 0。
-        require!(
-            tick_size > 0 && base_lot_size > 0,
-            ErrorCode::InvalidMarketParams
-        );
+// 为 `EventQueue` 实现方法。
+impl EventQueue {
+    // 定义账户大小常量。
+    pub const LEN: usize = 8 + size_of::<Self>();
 
-        // 初始化 Market 账户的各个字段。
-        market.version = PROGRAM_VERSION;
-        // 设置基础代币的 mint 地址。
-        market.base_mint = ctx.accounts.base_mint.key();
-        // 设置报价代币的 mint 地址。
-        market.quote_mint = ctx.accounts.quote_mint.key();
-        // 设置基础代币金库地址。
-        market.base_vault = ctx.accounts.base_vault.key();
-        // 设置报价代币金库地址。
-        market.quote_vault = ctx.accounts.quote_vault.key();
-        // 设置手续费金库地址。
-        market.fee_vault = ctx.accounts.fee_vault.key();
-        // 设置市场管理员地址。
-        market.authority = ctx.accounts.authority.key();
-        // 设置买单簿地址。
-        market.bids = ctx.accounts.bids.key();
-        // 设置卖单簿地址。
-        market.asks = ctx.accounts.asks.key();
-        // 存储 Market PDA 的 bump seed。
-        market.bump = ctx.bumps.market;
-        // 初始化订单序列号为 0。
-        market.order_sequence_number = 0;
-        // 设置 maker 手续费率。
-        market.maker_fee_bps = maker_fee_bps;
-        // 设置 taker 手续费率。
-        market.taker_fee_bps = taker_fee_bps;
-        // 初始化市场为未暂停状态。
-        market.paused = false;
-        // 设置价格的最小变动单位。
-        market.tick_size = tick_size;
-        // 设置数量的最小变动单位。
-        market.base_lot_size = base_lot_size;
-        // 设置最小下单数量，如果未提供则使用默认值。
-        market.min_base_qty = min_base_qty.unwrap_or(DEFAULT_MIN_BASE_QTY);
-        // 设置最小名义价值，如果未提供则使用默认值。
-        market.min_notional = min_notional.unwrap_or(DEFAULT_MIN_NOTIONAL);
-        // 记录基础代币的小数位数。
-        market.base_decimals = ctx.accounts.base_mint.decimals;
-        // 记录报价代币的小数位数。
-        market.quote_decimals = ctx.accounts.quote_mint.decimals;
-
-        // 初始化买单簿账户。
-        ctx.accounts
-            // 获取买单簿账户加载器。
-            .bids
-            // 加载并初始化 zero_copy 账户，这只在创建账户时调用一次。
-            .load_init()?
-            // 调用 OrderBook 的 initialize 方法。
-            .initialize(market.key(), true, ctx.bumps.bids)?;
-        // 初始化卖单簿账户。
-        ctx.accounts
-            // 获取卖单簿账户加载器。
-            .asks
-            // 加载并初始化 zero_copy 账户。
-            .load_init()?
-            // 调用 OrderBook 的 initialize 方法，false 表示是卖单簿。
-            .initialize(market.key(), false, ctx.bumps.asks)?;
-
-        // 发出一个事件，通知链下客户端市场已成功初始化。
-        emit!(MarketInitializedEvent {
-            // 市场地址。
-            market: market.key(),
-            // 基础代币 mint。
-            base_mint: market.base_mint,
-            // 报价代币 mint。
-            quote_mint: market.quote_mint,
-            // maker 手续费。
-            maker_fee_bps,
-            // taker 手续费。
-            taker_fee_bps,
-            // 价格精度。
-            tick_size,
-            // 数量精度。
-            base_lot_size,
-        });
+    // 初始化事件队列。
+    pub fn initialize(&mut self, market: Pubkey, bump: u8) {
+        // 设置关联的市场地址。
+        self.market = market;
+        // 设置 PDA 的 bump seed。
+        self.bump = bump;
+        // 初始时队列为空。
+        self.head = 0;
+        self.count = 0;
+        self.seq_num = 0;
+    }
+
+    // 队列是否已满。
+    pub fn is_full(&self) -> bool {
+        self.count as usize == EVENT_QUEUE_CAPACITY
+    }
+
+    // 队列剩余的空位数量。
+    pub fn free_slots(&self) -> usize {
+        EVENT_QUEUE_CAPACITY - self.count as usize
+    }
 
+    // 将一个事件追加到队尾，队列已满时返回错误。
+    pub fn push(&mut self, event: QueueEvent) -> Result<()> {
+        // 队列已满时，需要先调用 `consume_events` 处理积压的事件。
+        require!(!self.is_full(), ErrorCode::EventQueueFull);
+        // 计算队尾位置。
+        let slot = (self.head as usize + self.count as usize) % EVENT_QUEUE_CAPACITY;
+        // 写入事件。
+        self.events[slot] = event;
+        // 更新计数。
+        self.count += 1;
+        self.seq_num = self.seq_num.checked_add(1).ok_or(ErrorCode::MathOverflow)?;
         // 返回成功。
         Ok(())
     }
 
-    // `new_limit_order` 指令：下一个新的限价单。
-    pub fn new_limit_order(
-        // 账户上下文。
-        ctx: Context<NewLimitOrder>,
-        // 订单方向（买或卖）
-        side: Side,
-        // 价格
-        price: u64,
-        // 数量
-        quantity: u64,
-    ) -> Result<()> {
-        // 获取 market 账户的可变引用。
-        let market = &mut ctx.accounts.market;
-        // 获取 open_orders 账户的可变引用。
-        let open_orders = &mut ctx.accounts.open_orders;
+    // 查看队首的事件。
+    pub fn peek(&self) -> Option<&QueueEvent> {
+        if self.count == 0 {
+            return None;
+        }
+        Some(&self.events[self.head as usize])
+    }
 
-        // 验证市场是否暂停。
-        require!(!market.paused, ErrorCode::Paused);
-        // 验证价格和数量是否大于0。
-        require!(price > 0 && quantity > 0, ErrorCode::InvalidOrderInput);
-        // 验证价格是否是 tick_size 的整数倍。
-        require!(price % market.tick_size == 0, ErrorCode::InvalidTickSize);
-        // 验证数量是否是 base_lot_size 的整数倍。
-        require!(
-            quantity % market.base_lot_size == 0,
-            ErrorCode::InvalidLotSize
-        );
+    // 移除队首的事件。
+    pub fn pop(&mut self) -> Result<QueueEvent> {
+        // 队列为空时返回错误。
+        require!(self.count > 0, ErrorCode::EventQueueEmpty);
+        // 取出队首事件。
+        let event = self.events[self.head as usize];
+        // 移动队首位置。
+        self.head = ((self.head as usize + 1) % EVENT_QUEUE_CAPACITY) as u32;
+        self.count -= 1;
+        // 返回被移除的事件。
+        Ok(event)
+    }
+}
 
-        // 如果用户的 OpenOrders 账户是首次使用（market 地址为默认值），则进行初始化。
-        if open_orders.market == Pubkey::default() {
-            // 设置关联的市场地址。
-            open_orders.market = market.key();
-            // 设置账户所有者地址。
-            open_orders.owner = ctx.accounts.owner.key();
-            // 存储 OpenOrders PDA 的 bump seed。
-            open_orders.bump = ctx.bumps.open_orders;
+// 为 `QueueEvent` 实现方法。
+impl QueueEvent {
+    // 创建挂单成交事件。`fee` 是挂单方为这笔成交支付的手续费。
+    pub fn fill(
+        // 成交前的挂单。
+        order: &Order,
+        // 挂单的方向。
+        side: Side,
+        // 成交的基础代币数量。
+        base_qty: u64,
+        // 成交的报价代币金额。
+        quote_qty: u64,
+        // 挂单方支付的手续费，负数为返佣。
+        fee: i64,
+        // 挂单是否已完全成交。
+        order_done: bool,
+    ) -> Result<Self> {
+        let mut event = QueueEvent {
+            owner_account: order.owner_account,
+            order_id: order.order_id,
+            base_qty,
+            quote_qty,
+            fee,
+            kind: EventKind::Fill,
+            order_done: order_done.into(),
+            ..Default::default()
+        };
+        match side {
+            // 卖单：交付锁定的基础代币，收到扣除手续费（或加上返佣）后的报价代币。
+            Side::Ask => {
+                event.base_unlock = base_qty;
+                event.quote_credit = deduct_signed_fee(quote_qty, fee)?;
+            }
+            // 买单：获得基础代币，释放按自身出价锁定的报价代币。
+            Side::Bid => {
+                event.base_credit = base_qty;
+                // 这部分成交量当初锁定的报价代币（基于订单自身价格和下单时的费率，而非成交价和当前费率）。
+                let released = order.quote_release(base_qty)?;
+                event.quote_unlock = released;
+                // 返还金额 = 锁定金额 - 实际成交额 - 手续费（价格优待 + 节省的手续费 + 返佣）。
+                let unspent = released
+                    .checked_sub(quote_qty)
+                    .ok_or(ErrorCode::MathOverflow)?;
+                event.quote_credit = deduct_signed_fee(unspent, fee)?;
+            }
         }
+        Ok(event)
+    }
 
-        // 根据订单方向，锁定相应的代币。
+    // 创建挂单取消事件，被取消部分锁定的资金全部返还到可用余额。
+    pub fn out(
+        // 被取消的挂单。
+        order: &Order,
+        // 挂单的方向。
+        side: Side,
+        // 取消的基础代币数量。
+        base_qty: u64,
+        // 挂单是否已完全取消。
+        order_done: bool,
+    ) -> Result<Self> {
+        let mut event = QueueEvent {
+            owner_account: order.owner_account,
+            order_id: order.order_id,
+            base_qty,
+            kind: EventKind::Out,
+            order_done: order_done.into(),
+            ..Default::default()
+        };
         match side {
-            // 如果是买单。
+            // 买单：解锁下单时为这部分数量锁定的报价代币（成交额 + 最大 taker 手续费）。
             Side::Bid => {
-                // 计算需要锁定的报价代币总额。
-                let base_quote_amount = (price as u128)
-                    // 价格乘以数量。
-                    .checked_mul(quantity as u128)
-                    // 除以缩放因子，得到实际金额。
-                    .and_then(|v| v.checked_div(PRICE_SCALE))
-                    // 如果溢出则返回错误。
-                    .ok_or(ErrorCode::MathOverflow)? as u64;
-
-                // 计算可能的最大 taker fee。
-                let max_taker_fee =
-                    // 报价总额乘以 taker 手续费率。
-                    (base_quote_amount as u128 * market.taker_fee_bps as u128 / 10_000) as u64;
-
-                // 锁定的总金额 = 基础金额 + 最大手续费。
-                let total_quote_amount_to_lock = base_quote_amount
-                    // 使用 checked_add 防止溢出。
-                    .checked_add(max_taker_fee)
-                    .ok_or(ErrorCode::MathOverflow)?;
-                // 通过 CPI 调用 Token Program，将报价代币从用户账户转移到程序的金库账户。
-                transfer_checked(
-                    // 创建 CPI 上下文。
-                    CpiContext::new(
-                        // 传入 Token Program 的账户信息。
-                        ctx.accounts.token_program.to_account_info(),
-                        // 定义转账所需的账户。
-                        TransferChecked {
-                            // 源账户（用户）。
-                            from: ctx.accounts.user_quote_token_account.to_account_info(),
-                            // 代币的 mint。
-                            mint: ctx.accounts.quote_mint.to_account_info(),
-                            // 目标账户（程序金库）。
-                            to: ctx.accounts.quote_vault.to_account_info(),
-                            // 授权方（用户）。
-                            authority: ctx.accounts.owner.to_account_info(),
-                        },
-                    ),
-                    // 转账金额。
-                    total_quote_amount_to_lock,
-                    // 代币的小数位数。
-                    market.quote_decimals,
-                )?;
-                // 更新 OpenOrders 账户中锁定的报价代币数量。
-                open_orders.quote_token_locked = open_orders
-                    .quote_token_locked
-                    // 使用 checked_add 防止溢出。
-                    .checked_add(total_quote_amount_to_lock)
-                    .ok_or(ErrorCode::MathOverflow)?;
+                let quote_to_unlock = order.quote_release(base_qty)?;
+                event.quote_unlock = quote_to_unlock;
+                event.quote_credit = quote_to_unlock;
             }
-            // 如果是卖单。
+            // 卖单：解锁基础代币。
             Side::Ask => {
-                // 通过 CPI 调用 Token Program，将基础代币从用户账户转移到程序的金库账户。
-                transfer_checked(
-                    // 创建 CPI 上下文。
-                    CpiContext::new(
-                        // 传入 Token Program 的账户信息。
-                        ctx.accounts.token_program.to_account_info(),
-                        // 定义转账所需的账户。
-                        TransferChecked {
-                            // 源账户（用户）。
-                            from: ctx.accounts.user_base_token_account.to_account_info(),
-                            // 代币的 mint。
-                            mint: ctx.accounts.base_mint.to_account_info(),
-                            // 目标账户（程序金库）。
-                            to: ctx.accounts.base_vault.to_account_info(),
-                            // 授权方（用户）。
-                            authority: ctx.accounts.owner.to_account_info(),
-                        },
-                    ),
-                    // 转账金额（即卖单数量）。
-                    quantity,
-                    // 代币的小数位数。
-                    market.base_decimals,
-                )?;
-                // 更新 OpenOrders 账户中锁定的基础代币数量。
-                open_orders.base_token_locked = open_orders
-                    .base_token_locked
-                    // 使用 checked_add 防止溢出。
-                    .checked_add(quantity)
-                    .ok_or(ErrorCode::MathOverflow)?;
+                event.base_unlock = base_qty;
+                event.base_credit = base_qty;
             }
         }
+        Ok(event)
+    }
 
-        // 订单序列号加一，以生成新的唯一订单 ID。
-        market.order_sequence_number += 1;
-        // 获取新的订单 ID。
-        let order_id = market.order_sequence_number;
+    // 将另一个事件的余额变动累加到当前事件，用于批量取消时一次性结算。
+    pub fn accumulate(&mut self, other: &QueueEvent) -> Result<()> {
+        self.base_unlock = self
+            .base_unlock
+            .checked_add(other.base_unlock)
+            .ok_or(ErrorCode::MathOverflow)?;
+        self.quote_unlock = self
+            .quote_unlock
+            .checked_add(other.quote_unlock)
+            .ok_or(ErrorCode::MathOverflow)?;
+        self.base_credit = self
+            .base_credit
+            .checked_add(other.base_credit)
+            .ok_or(ErrorCode::MathOverflow)?;
+        self.quote_credit = self
+            .quote_credit
+            .checked_add(other.quote_credit)
+            .ok_or(ErrorCode::MathOverflow)?;
+        // 返回成功。
+        Ok(())
+    }
 
-        // 创建新的 Order 对象。
-        let new_order = Order {
-            // 订单所有者的 OpenOrders 账户地址。
-            owner_account: open_orders.key(),
-            // 订单 ID。
-            order_id,
-            // 价格。
-            price,
-            // 数量。
-            base_qty: quantity,
-        };
+    // 将事件的余额变动应用到挂单所有者的 OpenOrders 账户。
+    pub fn apply(&self, ooa: &mut OpenOrders) -> Result<()> {
+        // 从锁定余额中扣除。
+        ooa.base_token_locked = ooa
+            .base_token_locked
+            .checked_sub(self.base_unlock)
+            .ok_or(ErrorCode::MathOverflow)?;
+        ooa.quote_token_locked = ooa
+            .quote_token_locked
+            .checked_sub(self.quote_unlock)
+            .ok_or(ErrorCode::MathOverflow)?;
+        // 计入可用余额。
+        ooa.base_token_free = ooa
+            .base_token_free
+            .checked_add(self.base_credit)
+            .ok_or(ErrorCode::MathOverflow)?;
+        ooa.quote_token_free = ooa
+            .quote_token_free
+            .checked_add(self.quote_credit)
+            .ok_or(ErrorCode::MathOverflow)?;
+        // 挂单已结束时，从活动订单列表中移除；订单已不在列表中时无需移除，余额变动照常结算。
+        if self.order_done.into() && ooa.find_order(self.order_id).is_some() {
+            ooa.remove_order(self.order_id)?;
+        }
+        // 返回成功。
+        Ok(())
+    }
+}
+
+// --- 撮合辅助函数 ---
+
+// 计算给定价格和基础代币数量对应的报价代币金额 (价格 * 数量 / 缩放因子)。
+fn quote_amount(price: u64, base_qty: u64) -> Result<u64> {
+    // 使用 u128 进行中间计算，防止乘法溢出。
+    let amount = (price as u128)
+        .checked_mul(base_qty as u128)
+        .and_then(|v| v.checked_div(PRICE_SCALE))
+        .ok_or(ErrorCode::MathOverflow)?;
+    // 结果必须能放入 u64。
+    u64::try_from(amount).map_err(|_| error!(ErrorCode::MathOverflow))
+}
+
+// 根据手续费率（基点）计算手续费，向下取整。
+fn fee_amount(quote_qty: u64, fee_bps: u16) -> u64 {
+    // fee_bps 最大为 10_000，结果不会超过 quote_qty。
+    (quote_qty as u128 * fee_bps as u128 / 10_000) as u64
+}
+
+// 根据 maker 费率计算 maker 手续费，向零取整：正数为 maker 支付的手续费，负数为支付给 maker 的返佣。
+fn maker_fee_amount(quote_qty: u64, maker_fee_bps: i16) -> Result<i64> {
+    // 按费率的绝对值计算金额。
+    let amount = i64::try_from(fee_amount(quote_qty, maker_fee_bps.unsigned_abs()))
+        .map_err(|_| error!(ErrorCode::MathOverflow))?;
+    // 负费率表示返佣。
+    Ok(if maker_fee_bps < 0 { -amount } else { amount })
+}
+
+// maker 返佣由同一笔成交中以报价代币支付的 taker 手续费提供资金，不能超过该手续费。
+// 手续费等级中的返佣可能高于对手方 taker 的费率，因此在每笔成交时再限制一次。
+fn cap_maker_rebate(maker_fee: i64, quote_taker_fee: u64) -> Result<i64> {
+    let max_rebate = i64::try_from(quote_taker_fee).map_err(|_| error!(ErrorCode::MathOverflow))?;
+    Ok(maker_fee.max(-max_rebate))
+}
+
+// 当前日期（Unix 时间戳 / 一天的秒数），用于统计每日交易量。
+fn current_day() -> Result<u64> {
+    let day = Clock::get()?.unix_timestamp / SECONDS_PER_DAY;
+    Ok(u64::try_from(day).unwrap_or(0))
+}
+
+// 从金额中扣除带符号的手续费：手续费为负数（返佣）时金额增加。
+fn deduct_signed_fee(amount: u64, fee: i64) -> Result<u64> {
+    Ok(fee
+        .checked_neg()
+        .and_then(|v| amount.checked_add_signed(v))
+        .ok_or(ErrorCode::MathOverflow)?)
+}
+
+// 验证手续费率：均不超过 100%，且 maker 返佣不超过 taker 手续费，保证返佣总能由 taker 手续费支付。
+fn validate_fees(maker_fee_bps: i16, taker_fee_bps: u16) -> Result<()> {
+    // 手续费率不超过 10_000 bps (100%)。
+    require!(
+        maker_fee_bps <= 10_000 && taker_fee_bps <= 10_000,
+        ErrorCode::InvalidFee
+    );
+    // maker 返佣率不能超过 taker 费率。
+    require!(
+        maker_fee_bps >= 0 || maker_fee_bps.unsigned_abs() <= taker_fee_bps,
+        ErrorCode::MakerRebateExceedsTakerFee
+    );
+    // 返回成功。
+    Ok(())
+}
 
-        // 根据订单方向，加载对应的订单簿（可变）。
-        let mut order_book = match side {
-            // 如果是买单，加载买单簿。
-            Side::Bid => ctx.accounts.bids.load_mut()?,
-            // 如果是卖单，加载卖单簿。
-            Side::Ask => ctx.accounts.asks.load_mut()?,
+// 计算买单为指定数量锁定的报价代币总额（成交额 + 最大 taker 手续费）。
+// 下单时按当时的 taker 费率锁定，并将锁定金额和费率记录在订单中，取消和成交时按订单记录的值释放（见 `Order::quote_release`）。
+fn bid_locked_quote(price: u64, base_qty: u64, taker_fee_bps: u16) -> Result<u64> {
+    // 按订单自身价格计算的成交额。
+    let quote = quote_amount(price, base_qty)?;
+    // 加上按 taker 费率预留的手续费。
+    Ok(quote
+        .checked_add(fee_amount(quote, taker_fee_bps))
+        .ok_or(ErrorCode::MathOverflow)?)
+}
+
+// 吃单撮合的汇总结果。
+#[derive(Default)]
+struct TakerFills {
+    // 成交的基础代币总量。
+    base_qty: u64,
+    // 成交的报价代币总额（不含手续费）。
+    quote_qty: u64,
+    // taker 支付的手续费总额（币种由 `taker_fee_in_base` 决定）。
+    taker_fee: u64,
+    // taker 是否以基础代币支付手续费。
+    taker_fee_in_base: bool,
+    // 需要转入报价代币手续费金库的总额（maker 手续费 + 以报价代币支付的 taker 手续费 - maker 返佣 - 推荐人分成）。
+    total_fee: u64,
+    // 需要支付给推荐人的报价代币总额。
+    referrer_fee: u64,
+    // 因自成交而取消的 taker 数量，这部分不会成交也不会挂单。
+    self_trade_cancelled_qty: u64,
+    // 事件队列已满导致撮合提前停止（此时对手方仍有可以成交的订单）。
+    event_queue_full: bool,
+}
+
+// 为 `TakerFills` 实现方法。
+impl TakerFills {
+    // 已处理的 taker 数量：成交数量 + 因自成交取消的数量。
+    fn consumed_base_qty(&self) -> Result<u64> {
+        Ok(self
+            .base_qty
+            .checked_add(self.self_trade_cancelled_qty)
+            .ok_or(ErrorCode::MathOverflow)?)
+    }
+
+    // taker 为已成交部分需要支付的资金：买单支付报价代币（成交额 + 手续费），卖单支付基础代币。
+    fn taker_cost(&self, side: Side) -> Result<u64> {
+        match side {
+            Side::Bid => self.taker_cost_in_quote(side),
+            Side::Ask => Ok(self.base_qty),
+        }
+    }
+
+    // 已成交部分占用的报价代币预算：买单为支出（以报价代币支付手续费时含手续费），卖单为成交额。
+    fn taker_cost_in_quote(&self, side: Side) -> Result<u64> {
+        match side {
+            Side::Bid => Ok(self
+                .quote_qty
+                .checked_add(self.quote_taker_fee())
+                .ok_or(ErrorCode::MathOverflow)?),
+            Side::Ask => Ok(self.quote_qty),
+        }
+    }
+
+    // taker 已成交部分的所得：买单获得扣除基础代币手续费后的基础代币，卖单获得扣除手续费后的报价代币。
+    fn taker_proceeds(&self, side: Side) -> Result<u64> {
+        match side {
+            Side::Bid => Ok(self
+                .base_qty
+                .checked_sub(self.base_fee())
+                .ok_or(ErrorCode::MathOverflow)?),
+            Side::Ask => Ok(self
+                .quote_qty
+                .checked_sub(self.taker_fee)
+                .ok_or(ErrorCode::MathOverflow)?),
+        }
+    }
+
+    // 以报价代币支付的 taker 手续费。
+    fn quote_taker_fee(&self) -> u64 {
+        if self.taker_fee_in_base {
+            0
+        } else {
+            self.taker_fee
+        }
+    }
+
+    // 以基础代币支付的 taker 手续费，需要转入基础代币手续费金库。
+    fn base_fee(&self) -> u64 {
+        if self.taker_fee_in_base {
+            self.taker_fee
+        } else {
+            0
+        }
+    }
+}
+
+// 计算在给定价格下，报价代币预算最多能成交的基础代币数量（向下取整到 base_lot_size）。
+// 买单的预算需要同时覆盖成交额和按 taker 费率计算的手续费。
+fn max_base_for_quote(
+    side: Side,
+    price: u64,
+    quote_budget: u64,
+    taker_fee_bps: u16,
+    market: &Market,
+) -> Result<u64> {
+    // 每单位基础代币占用的预算（放大 10_000 倍以包含手续费基点）。
+    // 买方以基础代币支付手续费时，报价代币预算只需覆盖成交额。
+    let fee_multiplier = match side {
+        Side::Bid if !market.taker_fee_in_base(side) => 10_000 + taker_fee_bps as u128,
+        _ => 10_000,
+    };
+    let denominator = (price as u128)
+        .checked_mul(fee_multiplier)
+        .ok_or(ErrorCode::MathOverflow)?;
+    // 价格为 0 的订单不会出现在订单簿中，这里仅作防御。
+    require!(denominator > 0, ErrorCode::InvalidOrderInput);
+    // 预算 * 缩放因子 * 10_000 / (价格 * 费率乘数)。
+    let base_qty = (quote_budget as u128)
+        .checked_mul(PRICE_SCALE * 10_000)
+        .ok_or(ErrorCode::MathOverflow)?
+        / denominator;
+    // 超出 u64 范围时截断为最大值，随后会被其他上限约束。
+    let base_qty = u64::try_from(base_qty).unwrap_or(u64::MAX);
+    // 向下取整到 base_lot_size 的整数倍。
+    Ok(base_qty - base_qty % market.base_lot_size)
+}
+
+// 将 taker 已成交部分的所得计入其 OpenOrders 的可用余额。
+fn credit_taker_proceeds(ooa: &mut OpenOrders, side: Side, fills: &TakerFills) -> Result<()> {
+    let proceeds = fills.taker_proceeds(side)?;
+    match side {
+        // 买单获得基础代币。
+        Side::Bid => {
+            ooa.base_token_free = ooa
+                .base_token_free
+                .checked_add(proceeds)
+                .ok_or(ErrorCode::MathOverflow)?;
+        }
+        // 卖单获得扣除 taker 手续费后的报价代币。
+        Side::Ask => {
+            ooa.quote_token_free = ooa
+                .quote_token_free
+                .checked_add(proceeds)
+                .ok_or(ErrorCode::MathOverflow)?;
+        }
+    }
+    // 返回成功。
+    Ok(())
+}
+
+// 判断 taker 的限价是否与对手方订单价格交叉（即可以成交）。
+fn price_crosses(taker_side: Side, limit_price: u64, opposite_price: u64) -> bool {
+    match taker_side {
+        // 买单：卖价不高于限价即可成交。
+        Side::Bid => opposite_price <= limit_price,
+        // 卖单：买价不低于限价即可成交。
+        Side::Ask => opposite_price >= limit_price,
+    }
+}
+
+// 计算 PostOnlySlide 订单滑动后的价格：对手方最优价之前、最近的一个 tick 整数倍价格。
+fn post_only_slide_price(side: Side, best_opposite_price: u64, tick_size: u64) -> Result<u64> {
+    let price = match side {
+        // 买单：严格低于最优卖价的最大 tick 整数倍。
+        Side::Bid => (best_opposite_price.saturating_sub(1) / tick_size) * tick_size,
+        // 卖单：严格高于最优买价的最小 tick 整数倍。
+        Side::Ask => (best_opposite_price / tick_size)
+            .checked_add(1)
+            .and_then(|v| v.checked_mul(tick_size))
+            .ok_or(ErrorCode::MathOverflow)?,
+    };
+    // 买单滑动后价格为 0 时无法挂单。
+    require!(price > 0, ErrorCode::OrderWouldCross);
+    Ok(price)
+}
+
+// 吃单撮合的参数，描述一个新到达的订单。
+struct TakerOrder {
+    // taker 的方向。
+    side: Side,
+    // taker 的限价。
+    limit_price: u64,
+    // taker 最多成交的基础代币数量。
+    max_base_qty: u64,
+    // taker 最多使用的报价代币数量：买单为支出（含 taker 手续费），卖单为成交额。
+    max_quote_qty: u64,
+    // taker 的 OpenOrders 账户地址，用于识别自成交。
+    owner_account: Pubkey,
+    // taker 的自成交处理方式。
+    self_trade_behavior: SelfTradeBehavior,
+    // taker 适用的手续费率（市场统一费率或用户所在等级的费率）。
+    taker_fee_bps: u16,
+    // taker 手续费分给推荐人的比例（没有推荐人时为 0）。
+    referrer_share_bps: u16,
+    // taker 的订单 ID（兑换没有订单，为 0），记录在成交事件中。
+    order_id: u64,
+    // taker 的钱包地址，记录在成交事件中。
+    owner: Pubkey,
+}
+
+// 用一个新到达的订单（taker）扫过对手方订单簿，直到价格不再交叉或数量耗尽。
+// 每笔成交为 maker 写入一个 Fill 事件，由 `consume_events` 结算到 maker 的 OpenOrders 账户，
+// taker 自身的资金由调用方根据返回的汇总结果结算。
+// 遇到 taker 自己的挂单时按 `self_trade_behavior` 处理，被取消的挂单写入 Out 事件。
+fn match_taker_order(
+    // 对手方订单簿（taker 为买单时是卖单簿，反之亦然）。
+    book: &mut BookSide,
+    // 市场的事件队列。
+    event_queue: &mut EventQueue,
+    // taker 订单参数。
+    taker: &TakerOrder,
+    // 市场账户（手续费率和市场地址）。
+    market: &Account<Market>,
+) -> Result<TakerFills> {
+    // 初始化汇总结果，并确定 taker 手续费的币种。
+    let mut fills = TakerFills {
+        taker_fee_in_base: market.taker_fee_in_base(taker.side),
+        ..Default::default()
+    };
+
+    // 循环撮合，直到数量耗尽。
+    while fills.consumed_base_qty()? < taker.max_base_qty {
+        // 获取对手方最优订单，订单簿为空则停止。
+        let Some(best) = book.get_best_price_order() else {
+            break;
         };
+        // 价格不再交叉，停止撮合。
+        if !price_crosses(taker.side, taker.limit_price, best.price) {
+            break;
+        }
+        // 事件队列已满，无法再记录 maker 的结算，停止撮合并由调用方处理剩余数量，
+        // 避免积压的事件使所有吃单交易失败。
+        if event_queue.is_full() {
+            fills.event_queue_full = true;
+            break;
+        }
+        // 自成交：不产生成交，按 taker 的处理方式取消双方的部分或全部数量。
+        if best.owner_account == taker.owner_account {
+            let (taker_cancel_qty, maker_cancel_qty) = taker.self_trade_behavior.cancel_quantities(
+                taker.max_base_qty - fills.consumed_base_qty()?,
+                best.base_qty,
+            );
+            if maker_cancel_qty > 0 {
+                // 扣减订单簿中的挂单，并写入 Out 事件以解锁被取消部分的资金。
+                let remaining = book.fill_best_order(maker_cancel_qty)?;
+                event_queue.push(QueueEvent::out(
+                    &best,
+                    taker.side.opposite(),
+                    maker_cancel_qty,
+                    remaining.base_qty == 0,
+                )?)?;
+            }
+            // 记录 taker 被取消的数量，取消全部剩余数量时循环随之结束。
+            fills.self_trade_cancelled_qty = fills
+                .self_trade_cancelled_qty
+                .checked_add(taker_cancel_qty)
+                .ok_or(ErrorCode::MathOverflow)?;
+            continue;
+        }
 
-        // 将新订单添加到订单簿。
-        order_book.add_order(new_order)?;
-        // 将订单 ID 记录到用户的 OpenOrders 账户。
-        open_orders.add_order(order_id)?;
+        // 成交数量：taker 剩余数量与 maker 订单数量的较小者。
+        let mut trade_base_qty = std::cmp::min(
+            taker.max_base_qty - fills.consumed_base_qty()?,
+            best.base_qty,
+        );
+        // 如果 taker 有报价代币预算，成交数量还不能超过剩余预算在该价格下能成交的数量。
+        if taker.max_quote_qty != u64::MAX {
+            let remaining_quote = taker
+                .max_quote_qty
+                .checked_sub(fills.taker_cost_in_quote(taker.side)?)
+                .ok_or(ErrorCode::MathOverflow)?;
+            trade_base_qty = std::cmp::min(
+                trade_base_qty,
+                max_base_for_quote(
+                    taker.side,
+                    best.price,
+                    remaining_quote,
+                    taker.taker_fee_bps,
+                    market,
+                )?,
+            );
+        }
+        // 预算已经不足以成交一个 lot，停止撮合。
+        if trade_base_qty == 0 {
+            break;
+        }
+        // 以 maker 的挂单价格成交。
+        let trade_quote_qty = quote_amount(best.price, trade_base_qty)?;
+        // 计算双方手续费：maker 总是以报价代币支付（或获得返佣），taker 按市场配置的币种支付。
+        // maker 按其挂单时的费率等级计费，taker 按本次下单时的费率计费。
+        let (taker_fee, quote_taker_fee) = if fills.taker_fee_in_base {
+            (fee_amount(trade_base_qty, taker.taker_fee_bps), 0)
+        } else {
+            let fee = fee_amount(trade_quote_qty, taker.taker_fee_bps);
+            (fee, fee)
+        };
+        let mut maker_fee = cap_maker_rebate(
+            maker_fee_amount(trade_quote_qty, best.maker_fee_bps(market))?,
+            quote_taker_fee,
+        )?;
+        // 买单 maker 的手续费不能超过其下单时为这部分数量预留的金额（费率在下单后上调时按预留金额收取）。
+        if taker.side == Side::Ask {
+            let fee_reserve = best.fee_reserve(trade_base_qty, trade_quote_qty)?;
+            maker_fee = maker_fee.min(i64::try_from(fee_reserve).unwrap_or(i64::MAX));
+        }
+        // 推荐人分得以报价代币支付的 taker 手续费（扣除 maker 返佣后）的一部分，这部分不进入手续费金库。
+        let referrer_fee = fee_amount(
+            quote_taker_fee
+                .checked_add_signed(maker_fee.min(0))
+                .ok_or(ErrorCode::MathOverflow)?,
+            taker.referrer_share_bps,
+        );
 
-        // 发出下单事件。
-        emit!(OrderPlacedEvent {
+        // 更新订单簿中的 maker 订单。
+        let remaining = book.fill_best_order(trade_base_qty)?;
+        // 写入 maker 的成交事件，等待 `consume_events` 结算。
+        event_queue.push(QueueEvent::fill(
+            &best,
+            taker.side.opposite(),
+            trade_base_qty,
+            trade_quote_qty,
+            maker_fee,
+            remaining.base_qty == 0,
+        )?)?;
+
+        // 发出成交事件。
+        emit!(TradeEvent {
             // 市场地址。
             market: market.key(),
-            // 订单所有者地址。
-            owner: ctx.accounts.owner.key(),
-            // 订单 ID。
-            order_id,
-            // 价格。
-            price,
-            // 数量。
-            quantity,
-            // 订单方向。
-            side,
+            // maker 订单 ID。
+            maker_order_id: best.order_id,
+            // taker 订单 ID。
+            taker_order_id: taker.order_id,
+            // maker 的 OpenOrders 账户地址。
+            maker_open_orders: best.owner_account,
+            // maker 钱包地址。
+            maker_owner: best.owner,
+            // taker 钱包地址。
+            taker_owner: taker.owner,
+            // taker 的 OpenOrders 账户地址。
+            taker_open_orders: taker.owner_account,
+            // taker 的方向。
+            taker_side: taker.side,
+            // 成交价格。
+            price: best.price,
+            // 成交的基础代币数量。
+            base_qty: trade_base_qty,
+            // 成交的报价代币金额。
+            quote_qty: trade_quote_qty,
+            // maker 手续费。
+            maker_fee,
+            // taker 手续费。
+            taker_fee,
+            // taker 手续费是否以基础代币支付。
+            taker_fee_in_base: fills.taker_fee_in_base,
+            // 推荐人分得的手续费。
+            referrer_fee,
         });
 
-        // 返回成功。
-        Ok(())
+        // 累加成交结果。
+        fills.base_qty = fills
+            .base_qty
+            .checked_add(trade_base_qty)
+            .ok_or(ErrorCode::MathOverflow)?;
+        fills.quote_qty = fills
+            .quote_qty
+            .checked_add(trade_quote_qty)
+            .ok_or(ErrorCode::MathOverflow)?;
+        fills.taker_fee = fills
+            .taker_fee
+            .checked_add(taker_fee)
+            .ok_or(ErrorCode::MathOverflow)?;
+        // maker 返佣从 taker 手续费中支付，留在报价代币金库中计入 maker 的可用余额。
+        fills.total_fee = fills
+            .total_fee
+            .checked_add(quote_taker_fee)
+            .and_then(|v| v.checked_add_signed(maker_fee))
+            .and_then(|v| v.checked_sub(referrer_fee))
+            .ok_or(ErrorCode::MathOverflow)?;
+        fills.referrer_fee = fills
+            .referrer_fee
+            .checked_add(referrer_fee)
+            .ok_or(ErrorCode::MathOverflow)?;
     }
 
-    // `cancel_limit_order` 指令：取消一个限价单。
-    pub fn cancel_limit_order(ctx: Context<CancelLimitOrder>, order_id: u64) -> Result<()> {
-        // 获取 open_orders 账户的可变引用。
-        let open_orders = &mut ctx.accounts.open_orders;
+    // 返回汇总结果。
+    Ok(fills)
+}
 
-        // 查找订单。首先在买单簿查找，如果找不到再去卖单簿查找。
-        let (node_index, order, side) = {
-            // 加载买单簿（不可变）。
-            let bids = ctx.accounts.bids.load()?;
-            // 在买单簿中查找订单。
-            if let Some((index, order)) = bids.find_order_by_id(order_id) {
-                // 如果找到，返回节点索引、订单信息和买单方向。
-                (index, order, Side::Bid)
-            } else {
-                // 如果在买单簿中没找到，加载卖单簿（不可变）。
-                let asks = ctx.accounts.asks.load()?;
-                // 在卖单簿中查找订单。
-                let (index, order) = asks
-                    .find_order_by_id(order_id)
-                    // 如果在卖单簿也找不到，返回 OrderNotFound 错误。
-                    .ok_or(ErrorCode::OrderNotFound)?;
-                // 如果找到，返回节点索引、订单信息和卖单方向。
-                (index, order, Side::Ask)
-            }
-        };
+// 从订单簿中移除用户的一个活动订单，将其锁定的资金解锁到可用余额，从活动订单列表中移除并发出取消事件。
+fn cancel_open_order<'info>(
+    // 市场账户。
+    market: &Account<'info, Market>,
+    // 买单簿。
+    bids: &AccountLoader<'info, OrderBook>,
+    // 卖单簿。
+    asks: &AccountLoader<'info, OrderBook>,
+    // 订单所有者的 OpenOrders 账户。
+    open_orders: &mut Account<'info, OpenOrders>,
+    // 要取消的活动订单。
+    slot: OpenOrderSlot,
+) -> Result<()> {
+    let side = slot.side();
+
+    // 根据订单方向，加载对应的订单簿（可变）。
+    let mut order_book = match side {
+        // 如果是买单，加载买单簿。
+        Side::Bid => BookSide::load_mut(bids)?,
+        // 如果是卖单，加载卖单簿。
+        Side::Ask => BookSide::load_mut(asks)?,
+    };
+    // 根据价格和订单 ID 直接从订单簿中移除订单。
+    let order = order_book.remove_order(slot.price, slot.order_id)?;
+
+    // 验证订单簿中的订单确实属于调用者。
+    require_keys_eq!(
+        order.owner_account,
+        open_orders.key(),
+        ErrorCode::Unauthorized
+    );
+
+    // 解锁之前锁定的代币，将其从 `locked` 转移到 `free`，并从用户的 OpenOrders 账户中移除订单 ID。
+    // 用户自己的账户就在当前指令中，因此直接应用，与事件队列中的 Out 事件使用同一结算逻辑。
+    QueueEvent::out(&order, side, order.base_qty, true)?.apply(open_orders)?;
+
+    // 发出取消订单事件。
+    emit!(OrderCancelledEvent {
+        // 市场地址。
+        market: market.key(),
+        // 订单所有者地址。
+        owner: open_orders.owner,
+        // 被取消的订单 ID。
+        order_id: slot.order_id,
+        // 被取消订单的客户端订单 ID。
+        client_order_id: slot.client_order_id,
+    });
+
+    // 返回成功。
+    Ok(())
+}
 
-        // 验证调用者是否是订单的所有者。
-        require_keys_eq!(
-            order.owner_account,
-            open_orders.key(),
-            ErrorCode::Unauthorized
-        );
+// --- 代币转账辅助函数 ---
 
-        // 根据订单方向，从对应的订单簿中移除订单。
-        match side {
-            // 如果是买单，从买单簿移除。
-            Side::Bid => ctx.accounts.bids.load_mut()?.remove_order(node_index)?,
-            // 如果是卖单，从卖单簿移除。
-            Side::Ask => ctx.accounts.asks.load_mut()?.remove_order(node_index)?,
+// 由用户签名，将代币从用户的代币账户转入程序金库。
+fn transfer_from_user<'info>(
+    // Token Program。
+    token_program: &Interface<'info, TokenInterface>,
+    // 源账户（用户）。
+    from: &InterfaceAccount<'info, TokenAccount>,
+    // 代币的 mint。
+    mint: &InterfaceAccount<'info, Mint>,
+    // 目标账户（程序金库）。
+    to: &InterfaceAccount<'info, TokenAccount>,
+    // 授权方（用户）。
+    authority: &Signer<'info>,
+    // 转账金额。
+    amount: u64,
+    // 代币的小数位数。
+    decimals: u8,
+) -> Result<()> {
+    // 金额为 0 时无需转账。
+    if amount == 0 {
+        return Ok(());
+    }
+    // 通过 CPI 调用 Token Program 完成转账。
+    transfer_checked(
+        CpiContext::new(
+            token_program.to_account_info(),
+            TransferChecked {
+                from: from.to_account_info(),
+                mint: mint.to_account_info(),
+                to: to.to_account_info(),
+                authority: authority.to_account_info(),
+            },
+        ),
+        amount,
+        decimals,
+    )
+}
+
+// 由 Market PDA 签名，从程序金库转出代币。
+fn transfer_from_vault<'info>(
+    // Market 账户，作为金库的授权方。
+    market: &Account<'info, Market>,
+    // Token Program。
+    token_program: &Interface<'info, TokenInterface>,
+    // 源账户（程序金库）。
+    from: &InterfaceAccount<'info, TokenAccount>,
+    // 代币的 mint。
+    mint: &InterfaceAccount<'info, Mint>,
+    // 目标账户。
+    to: &InterfaceAccount<'info, TokenAccount>,
+    // 转账金额。
+    amount: u64,
+    // 代币的小数位数。
+    decimals: u8,
+) -> Result<()> {
+    // 金额为 0 时无需转账。
+    if amount == 0 {
+        return Ok(());
+    }
+    // 准备 Market PDA 的签名种子。
+    let seeds = &[
+        b"market".as_ref(),
+        market.base_mint.as_ref(),
+        market.quote_mint.as_ref(),
+        &[market.bump],
+    ];
+    // 将种子包装成签名者。
+    let signer = &[&seeds[..]];
+    // 通过带 PDA 签名的 CPI 完成转账。
+    transfer_checked(
+        CpiContext::new_with_signer(
+            token_program.to_account_info(),
+            TransferChecked {
+                from: from.to_account_info(),
+                mint: mint.to_account_info(),
+                to: to.to_account_info(),
+                // 授权方是 Market PDA。
+                authority: market.to_account_info(),
+            },
+            signer,
+        ),
+        amount,
+        decimals,
+    )
+}
+
+// 将手续费从程序金库转入对应币种的手续费金库，并发出本次调用的手续费汇总事件。
+// 报价代币手续费从 `quote_vault` 转入 `fee_vault`，基础代币手续费从 `base_vault` 转入 `base_fee_vault`。
+fn collect_fees_to_vault<'info>(
+    // Market 账户，作为金库的授权方。
+    market: &Account<'info, Market>,
+    // Token Program。
+    token_program: &Interface<'info, TokenInterface>,
+    // 程序金库。
+    vault: &InterfaceAccount<'info, TokenAccount>,
+    // 手续费代币的 mint。
+    mint: &InterfaceAccount<'info, Mint>,
+    // 手续费金库。
+    fee_vault: &InterfaceAccount<'info, TokenAccount>,
+    // 本次调用产生的手续费总额。
+    amount: u64,
+) -> Result<()> {
+    // 没有手续费时无需转账，也不发出事件。
+    if amount == 0 {
+        return Ok(());
+    }
+    transfer_from_vault(
+        market,
+        token_program,
+        vault,
+        mint,
+        fee_vault,
+        amount,
+        mint.decimals,
+    )?;
+    // 发出手续费收取事件。
+    emit!(FeeCollectedEvent {
+        // 市场地址。
+        market: market.key(),
+        // 手续费代币的 mint。
+        mint: mint.key(),
+        // 手续费金额。
+        amount,
+    });
+    // 返回成功。
+    Ok(())
+}
+
+// 将以基础代币收取的 taker 手续费从 `base_vault` 转入 `base_fee_vault`。
+// 只有产生了基础代币手续费时才要求传入 `base_fee_vault`。
+fn collect_base_fees_to_vault<'info>(
+    // Market 账户，作为金库的授权方。
+    market: &Account<'info, Market>,
+    // Token Program。
+    token_program: &Interface<'info, TokenInterface>,
+    // 基础代币金库。
+    base_vault: &InterfaceAccount<'info, TokenAccount>,
+    // 基础代币的 mint。
+    base_mint: &InterfaceAccount<'info, Mint>,
+    // 可选：基础代币手续费金库。
+    base_fee_vault: Option<&InterfaceAccount<'info, TokenAccount>>,
+    // 本次调用产生的基础代币手续费总额。
+    amount: u64,
+) -> Result<()> {
+    // 没有基础代币手续费时无需转账。
+    if amount == 0 {
+        return Ok(());
+    }
+    let base_fee_vault = base_fee_vault.ok_or(ErrorCode::BaseFeeVaultRequired)?;
+    collect_fees_to_vault(
+        market,
+        token_program,
+        base_vault,
+        base_mint,
+        base_fee_vault,
+        amount,
+    )
+}
+
+// 按配置的比例将手续费金库中的全部余额分给协议金库和质押者，并发出手续费分配事件。
+// 质押者按比例向下取整，余数归协议金库。
+fn distribute_vault_fees<'info>(
+    // Market 账户，作为金库的授权方。
+    market: &Account<'info, Market>,
+    // Token Program。
+    token_program: &Interface<'info, TokenInterface>,
+    // 手续费金库（`fee_vault` 或 `base_fee_vault`）。
+    fee_vault: &InterfaceAccount<'info, TokenAccount>,
+    // 手续费代币的 mint。
+    mint: &InterfaceAccount<'info, Mint>,
+    // 协议金库的代币账户。
+    treasury: &InterfaceAccount<'info, TokenAccount>,
+    // 质押者奖励的代币账户。
+    stakers: &InterfaceAccount<'info, TokenAccount>,
+) -> Result<()> {
+    // 计算分配金额。
+    let amount = fee_vault.amount;
+    let stakers_amount = fee_amount(amount, market.stakers_share_bps);
+    let treasury_amount = amount - stakers_amount;
+
+    // 由 Market PDA 签名，分别转给协议金库和质押者。
+    transfer_from_vault(
+        market,
+        token_program,
+        fee_vault,
+        mint,
+        treasury,
+        treasury_amount,
+        mint.decimals,
+    )?;
+    transfer_from_vault(
+        market,
+        token_program,
+        fee_vault,
+        mint,
+        stakers,
+        stakers_amount,
+        mint.decimals,
+    )?;
+
+    // 发出手续费分配事件。
+    emit!(FeesDistributedEvent {
+        // 市场地址。
+        market: market.key(),
+        // 手续费代币的 mint。
+        mint: mint.key(),
+        // 分给协议金库的数量。
+        treasury_amount,
+        // 分给质押者的数量。
+        stakers_amount,
+    });
+    // 返回成功。
+    Ok(())
+}
+
+// 将推荐人分得的手续费从 `quote_vault` 直接转给推荐人，不经过 `fee_vault`。
+fn pay_referrer<'info>(
+    // Market 账户，作为金库的授权方。
+    market: &Account<'info, Market>,
+    // Token Program。
+    token_program: &Interface<'info, TokenInterface>,
+    // 报价代币金库。
+    quote_vault: &InterfaceAccount<'info, TokenAccount>,
+    // 报价代币的 mint。
+    quote_mint: &InterfaceAccount<'info, Mint>,
+    // 可选：推荐人的报价代币账户（只有传入时才会产生分成）。
+    referrer: Option<&InterfaceAccount<'info, TokenAccount>>,
+    // 本次调用产生的推荐人分成总额。
+    amount: u64,
+) -> Result<()> {
+    let Some(referrer) = referrer else {
+        return Ok(());
+    };
+    transfer_from_vault(
+        market,
+        token_program,
+        quote_vault,
+        quote_mint,
+        referrer,
+        amount,
+        market.quote_decimals,
+    )
+}
+
+// --- 指令模块 ---
+// Anchor 0.31 的 `#[program]` 宏会在其所在位置生成 `__private::__idl` 模块，其中的 IDL 扩容指令调用了已弃用的
+// `AccountInfo::realloc`。把程序模块放在这个外层模块中，只对宏生成的代码放行该警告；外部通过下面的 `pub use` 照常访问。
+#[allow(deprecated)]
+mod dex {
+    // 引入 crate 根部的账户、事件、错误等定义。
+    use super::*;
+
+    // Anchor 宏，声明这是一个 Solana 程序的主模块。
+    #[program]
+    // 定义程序的主模块。
+    pub mod order_book_dex {
+        // 导入父模块（顶层）的所有内容。
+        use super::*;
+
+        // `initialize_market` 指令：创建一个新的交易市场。
+        #[allow(clippy::too_many_arguments)]
+        pub fn initialize_market(
+            // 账户上下文
+            ctx: Context<InitializeMarket>,
+            // Maker 手续费率（负数为返佣）
+            maker_fee_bps: i16,
+            // Taker 手续费率
+            taker_fee_bps: u16,
+            // 价格最小变动单位
+            tick_size: u64,
+            // 数量最小变动单位
+            base_lot_size: u64,
+            // 可选：最小下单数量
+            min_base_qty: Option<u64>,
+            // 可选：最小名义价值
+            min_notional: Option<u64>,
+            // 每个订单簿（买单簿和卖单簿）的初始容量，之后可以通过 `grow_order_book` 扩容
+            book_capacity: u32,
+        ) -> Result<()> {
+            // 获取 market 账户的可变引用。
+            let market = &mut ctx.accounts.market;
+            // 验证手续费率是否在有效范围内 (不超过 100%，10_000 bps = 100%)，且 maker 返佣不超过 taker 手续费。
+            validate_fees(maker_fee_bps, taker_fee_bps)?;
+            // 验证 tick_size 和 lot_size 必须大于 0。
+            require!(
+                tick_size > 0 && base_lot_size > 0,
+                ErrorCode::InvalidMarketParams
+            );
+
+            // 初始化 Market 账户的各个字段。
+            market.version = PROGRAM_VERSION;
+            // 设置基础代币的 mint 地址。
+            market.base_mint = ctx.accounts.base_mint.key();
+            // 设置报价代币的 mint 地址。
+            market.quote_mint = ctx.accounts.quote_mint.key();
+            // 设置基础代币金库地址。
+            market.base_vault = ctx.accounts.base_vault.key();
+            // 设置报价代币金库地址。
+            market.quote_vault = ctx.accounts.quote_vault.key();
+            // 设置手续费金库地址。
+            market.fee_vault = ctx.accounts.fee_vault.key();
+            // 设置市场管理员地址。
+            market.authority = ctx.accounts.authority.key();
+            // 初始时没有待接受的管理员提议。
+            market.pending_authority = Pubkey::default();
+            // 初始时尚未配置手续费分配。
+            market.fee_treasury = Pubkey::default();
+            market.fee_stakers = Pubkey::default();
+            market.stakers_share_bps = 0;
+            // 默认以报价代币收取 taker 手续费，基础代币手续费金库在切换收费方式时创建。
+            market.taker_fee_mode = TakerFeeMode::Quote;
+            market.base_fee_vault = Pubkey::default();
+            market.referrer_share_bps = 0;
+            // 初始时没有手续费等级。
+            market.fee_tier_count = 0;
+            // 设置买单簿地址。
+            market.bids = ctx.accounts.bids.key();
+            // 设置卖单簿地址。
+            market.asks = ctx.accounts.asks.key();
+            // 设置事件队列地址。
+            market.event_queue = ctx.accounts.event_queue.key();
+            // 存储 Market PDA 的 bump seed。
+            market.bump = ctx.bumps.market;
+            // 初始化订单序列号为 0。
+            market.order_sequence_number = 0;
+            // 设置 maker 手续费率。
+            market.maker_fee_bps = maker_fee_bps;
+            // 设置 taker 手续费率。
+            market.taker_fee_bps = taker_fee_bps;
+            // 初始化市场为未暂停状态。
+            market.paused = false;
+            // 设置价格的最小变动单位。
+            market.tick_size = tick_size;
+            // 设置数量的最小变动单位。
+            market.base_lot_size = base_lot_size;
+            // 设置最小下单数量，如果未提供则使用默认值。
+            market.min_base_qty = min_base_qty.unwrap_or(DEFAULT_MIN_BASE_QTY);
+            // 设置最小名义价值，如果未提供则使用默认值。
+            market.min_notional = min_notional.unwrap_or(DEFAULT_MIN_NOTIONAL);
+            // 记录基础代币的小数位数。
+            market.base_decimals = ctx.accounts.base_mint.decimals;
+            // 记录报价代币的小数位数。
+            market.quote_decimals = ctx.accounts.quote_mint.decimals;
+
+            // 初始化买单簿账户。
+            ctx.accounts
+                // 获取买单簿账户加载器。
+                .bids
+                // 加载并初始化 zero_copy 账户，这只在创建账户时调用一次。
+                .load_init()?
+                // 调用 OrderBook 的 initialize 方法。
+                .initialize(market.key(), true, ctx.bumps.bids, book_capacity);
+            // 初始化卖单簿账户。
+            ctx.accounts
+                // 获取卖单簿账户加载器。
+                .asks
+                // 加载并初始化 zero_copy 账户。
+                .load_init()?
+                // 调用 OrderBook 的 initialize 方法，false 表示是卖单簿。
+                .initialize(market.key(), false, ctx.bumps.asks, book_capacity);
+            // 初始化事件队列账户。
+            ctx.accounts
+                .event_queue
+                .load_init()?
+                .initialize(market.key(), ctx.bumps.event_queue);
+
+            // 发出一个事件，通知链下客户端市场已成功初始化。
+            emit!(MarketInitializedEvent {
+                // 市场地址。
+                market: market.key(),
+                // 基础代币 mint。
+                base_mint: market.base_mint,
+                // 报价代币 mint。
+                quote_mint: market.quote_mint,
+                // maker 手续费。
+                maker_fee_bps,
+                // taker 手续费。
+                taker_fee_bps,
+                // 价格精度。
+                tick_size,
+                // 数量精度。
+                base_lot_size,
+            });
+
+            // 返回成功。
+            Ok(())
         }
 
-        // 解锁之前锁定的代币，将其从 `locked` 转移到 `free`。
-        match side {
-            // 如果是买单。
-            Side::Bid => {
-                // 计算之前锁定的报价代币数量。
-                let quote_amount = (order.price as u128)
-                    .checked_mul(order.base_qty as u128)
-                    .and_then(|v| v.checked_div(PRICE_SCALE))
-                    .ok_or(ErrorCode::MathOverflow)? as u64;
-                // ‼️‼️ 还有一个潜在的 Bug：这里没有考虑手续费！ ‼️‼️
-                // 下单时锁定了 base_amount + fee，但这里只解锁了 base_amount
-                // 这会导致 "MathOverflow" on checked_sub.
-                // 我们需要用与下单时完全相同的逻辑。
-                let max_taker_fee = (quote_amount as u128
-                    * ctx.accounts.market.taker_fee_bps as u128
-                    / 10_000) as u64;
-                let total_quote_to_unlock = quote_amount
-                    .checked_add(max_taker_fee)
-                    .ok_or(ErrorCode::MathOverflow)?;
+        // `new_limit_order` 指令：下一个新的限价单。
+        // 订单首先作为 taker 扫过对手方订单簿，与价格交叉的挂单立即成交，剩余部分才挂入订单簿。
+        // 所需资金优先从 OpenOrders 的可用余额中扣除，不足部分才从用户的代币账户转入。
+        // maker 一方的结算写入事件队列，由 `consume_events` 完成。
+        #[allow(clippy::too_many_arguments)]
+        pub fn new_limit_order(
+            // 账户上下文。
+            ctx: Context<NewLimitOrder>,
+            // 订单方向（买或卖）
+            side: Side,
+            // 价格
+            price: u64,
+            // 数量
+            quantity: u64,
+            // 订单类型
+            order_type: OrderType,
+            // 自成交处理方式
+            self_trade_behavior: SelfTradeBehavior,
+            // 客户端订单 ID，0 表示不指定
+            client_order_id: u64,
+        ) -> Result<()> {
+            // 首次使用时初始化 OpenOrders 账户所需的 bump seed。
+            let bump = ctx.bumps.open_orders;
+            ctx.accounts.place_limit_order(
+                bump,
+                LimitOrderParams {
+                    side,
+                    price,
+                    quantity,
+                    order_type,
+                    self_trade_behavior,
+                    client_order_id,
+                },
+            )
+        }
 
-                // 从锁定的报价代币中减去该数量。
-                open_orders.quote_token_locked = open_orders
-                    .quote_token_locked
-                    .checked_sub(total_quote_to_unlock)
+        // `new_market_order` 指令：下一个市价单。
+        // 市价单以 taker 身份扫过对手方订单簿，未成交的部分直接取消，不会挂单。
+        // 数量可以按基础代币数量或报价代币预算指定：
+        // - `BaseQuantity`：最多成交 `amount` 个基础代币（例如卖出 5 SOL）。
+        // - `QuoteBudget`：买单最多花费 `amount` 个报价代币（含 taker 手续费），卖单最多换取 `amount` 个报价代币。
+        // 只会筹集实际成交所需的资金（优先使用 OpenOrders 的可用余额），因此不会有多余的锁定资金需要退还。
+        pub fn new_market_order(
+            // 账户上下文，与限价单共用。
+            ctx: Context<NewLimitOrder>,
+            // 订单方向（买或卖）
+            side: Side,
+            // 数量的计量方式
+            mode: MarketOrderMode,
+            // 数量（基础代币数量或报价代币预算）
+            amount: u64,
+            // 可选：最差成交价格。买单不会高于该价格成交，卖单不会低于该价格成交。
+            price_limit: Option<u64>,
+            // 自成交处理方式
+            self_trade_behavior: SelfTradeBehavior,
+        ) -> Result<()> {
+            // 获取 market 账户的引用。
+            let market = &ctx.accounts.market;
+            // 验证市场是否暂停。
+            require!(!market.paused, ErrorCode::Paused);
+            // 验证数量是否大于0。
+            require!(amount > 0, ErrorCode::InvalidOrderInput);
+
+            // 未指定最差价格时，买单可以吃任意价格的卖单，卖单可以吃任意价格的买单。
+            let limit_price = price_limit.unwrap_or(match side {
+                Side::Bid => u64::MAX,
+                Side::Ask => 0,
+            });
+            // 根据计量方式确定数量上限。
+            let (max_base_qty, max_quote_qty) = match mode {
+                // 按基础代币数量成交，数量必须是 base_lot_size 的整数倍。
+                MarketOrderMode::BaseQuantity => {
+                    require!(
+                        amount.is_multiple_of(market.base_lot_size),
+                        ErrorCode::InvalidLotSize
+                    );
+                    (amount, u64::MAX)
+                }
+                // 按报价代币预算成交。
+                MarketOrderMode::QuoteBudget => (u64::MAX, amount),
+            };
+
+            // 首次使用时初始化 OpenOrders 账户，并分配新的订单 ID。
+            let bump = ctx.bumps.open_orders;
+            ctx.accounts.init_open_orders(bump);
+            let order_id = ctx.accounts.next_order_id()?;
+            // 按用户当前的交易量和持仓确定手续费率。
+            let day = current_day()?;
+            let rates = ctx.accounts.fee_rates(day)?;
+
+            // 作为 taker 撮合对手方订单簿。
+            let fills = ctx.accounts.match_against_book(&TakerOrder {
+                side,
+                limit_price,
+                max_base_qty,
+                max_quote_qty,
+                owner_account: ctx.accounts.open_orders.key(),
+                self_trade_behavior,
+                taker_fee_bps: rates.taker_fee_bps,
+                referrer_share_bps: ctx.accounts.referrer_share_bps(),
+                order_id,
+                owner: ctx.accounts.owner.key(),
+            })?;
+            // 已成交的金额计入用户的交易量。
+            ctx.accounts
+                .open_orders
+                .record_volume(day, fills.quote_qty)?;
+
+            // 只筹集已成交部分所需的资金（优先使用可用余额），并收取手续费。
+            ctx.accounts.fund(side, fills.taker_cost(side)?)?;
+            ctx.accounts
+                .collect_fees(fills.total_fee, fills.base_fee(), fills.referrer_fee)?;
+            // 成交所得进入可用余额。
+            credit_taker_proceeds(&mut ctx.accounts.open_orders, side, &fills)?;
+
+            // 发出市价单成交事件。
+            emit!(MarketOrderFilledEvent {
+                // 市场地址。
+                market: ctx.accounts.market.key(),
+                // 订单所有者地址。
+                owner: ctx.accounts.owner.key(),
+                // 订单 ID。
+                order_id,
+                // 订单方向。
+                side,
+                // 成交的基础代币数量。
+                base_qty: fills.base_qty,
+                // 成交的报价代币金额（不含手续费）。
+                quote_qty: fills.quote_qty,
+                // taker 手续费。
+                taker_fee: fills.taker_fee,
+                // taker 手续费是否以基础代币支付。
+                taker_fee_in_base: fills.taker_fee_in_base,
+            });
+
+            // 返回成功。
+            Ok(())
+        }
+
+        // `cancel_limit_order` 指令：取消一个限价单。
+        pub fn cancel_limit_order(ctx: Context<CancelLimitOrder>, order_id: u64) -> Result<()> {
+            // 在用户的 OpenOrders 账户中查找订单，获取其价格和方向；不属于该用户的订单在这里就会被拒绝。
+            let slot = ctx
+                .accounts
+                .open_orders
+                .find_order(order_id)
+                .ok_or(ErrorCode::OrderNotFoundInOpenOrders)?;
+            // 从订单簿中移除并解锁资金。
+            ctx.accounts.cancel_order(slot)
+        }
+
+        // `cancel_order_by_client_id` 指令：按客户端订单 ID 取消一个限价单。
+        pub fn cancel_order_by_client_id(
+            ctx: Context<CancelLimitOrder>,
+            client_order_id: u64,
+        ) -> Result<()> {
+            // 0 表示未指定客户端订单 ID，不能用于查找。
+            require!(client_order_id != 0, ErrorCode::InvalidOrderInput);
+            // 在用户的 OpenOrders 账户中按客户端订单 ID 查找订单。
+            let slot = ctx
+                .accounts
+                .open_orders
+                .find_order_by_client_id(client_order_id)
+                .ok_or(ErrorCode::OrderNotFoundInOpenOrders)?;
+            // 从订单簿中移除并解锁资金。
+            ctx.accounts.cancel_order(slot)
+        }
+
+        // `cancel_orders` 指令：一次取消多个指定订单 ID 的限价单。
+        // 不在用户活动订单中、或已经完全成交（成交事件尚未处理）的订单会被跳过，而不是让整个指令失败。
+        pub fn cancel_orders(ctx: Context<CancelLimitOrder>, order_ids: Vec<u64>) -> Result<()> {
+            // 在用户的 OpenOrders 账户中查找这些订单。
+            let slots: Vec<OpenOrderSlot> = order_ids
+                .iter()
+                .filter_map(|&order_id| ctx.accounts.open_orders.find_order(order_id))
+                .collect();
+            // 批量取消并汇总解锁资金。
+            ctx.accounts.cancel_orders_in_bulk(&slots)
+        }
+
+        // `cancel_all_orders` 指令：取消用户的全部活动订单，可按方向筛选，并限制本次取消的数量。
+        pub fn cancel_all_orders(
+            ctx: Context<CancelLimitOrder>,
+            // 可选：只取消该方向的订单
+            side: Option<Side>,
+            // 可选：本次最多取消的订单数量，用于控制计算单元消耗
+            limit: Option<u16>,
+        ) -> Result<()> {
+            // 按方向筛选用户的活动订单，并截取前 `limit` 个。
+            let slots: Vec<OpenOrderSlot> = ctx
+                .accounts
+                .open_orders
+                .orders
+                .iter()
+                .filter(|slot| side.is_none_or(|side| slot.side() == side))
+                .take(limit.map_or(usize::MAX, usize::from))
+                .copied()
+                .collect();
+            // 批量取消并汇总解锁资金。
+            ctx.accounts.cancel_orders_in_bulk(&slots)
+        }
+
+        // `replace_order` 指令：原子地取消一个活动订单并以新的参数重新下单。
+        // 被取消订单解锁的资金进入可用余额，新订单优先使用，只有不足的部分才从用户的代币账户转入。
+        // 新订单会分配新的订单 ID，并按新的价格重新排队。
+        pub fn replace_order(
+            // 账户上下文，与限价单共用。
+            ctx: Context<NewLimitOrder>,
+            // 要替换的订单 ID
+            order_id: u64,
+            // 新订单的参数
+            params: LimitOrderParams,
+        ) -> Result<()> {
+            // 在用户的 OpenOrders 账户中查找订单；不属于该用户的订单在这里就会被拒绝。
+            let slot = ctx
+                .accounts
+                .open_orders
+                .find_order(order_id)
+                .ok_or(ErrorCode::OrderNotFoundInOpenOrders)?;
+            // 从订单簿中移除旧订单并解锁资金。
+            cancel_open_order(
+                &ctx.accounts.market,
+                &ctx.accounts.bids,
+                &ctx.accounts.asks,
+                &mut ctx.accounts.open_orders,
+                slot,
+            )?;
+            // 以新参数下单。
+            let bump = ctx.bumps.open_orders;
+            ctx.accounts.place_limit_order(bump, params)
+        }
+
+        // `place_orders` 指令：一次下多个限价单，供做市商批量挂出报价阶梯。
+        // 每个订单都与 `new_limit_order` 一样按 `tick_size`、`base_lot_size`、`min_base_qty` 和 `min_notional` 校验，
+        // 所需资金按方向汇总后每个方向只筹集一次，手续费也只收取一次。
+        pub fn place_orders(
+            ctx: Context<NewLimitOrder>,
+            orders: Vec<LimitOrderParams>,
+        ) -> Result<()> {
+            // 至少包含一个订单。
+            require!(!orders.is_empty(), ErrorCode::InvalidOrderInput);
+            let bump = ctx.bumps.open_orders;
+            // 按方向汇总需要筹集的资金，以及撮合产生的手续费。
+            let mut quote_to_fund: u64 = 0;
+            let mut base_to_fund: u64 = 0;
+            let mut quote_fee: u64 = 0;
+            let mut base_fee: u64 = 0;
+            let mut referrer_fee: u64 = 0;
+
+            for params in orders {
+                // 执行订单（包括全部参数校验），暂不转账。
+                let (deposit_amount, fills) = ctx.accounts.execute_limit_order(bump, params)?;
+                let to_fund = match params.side {
+                    Side::Bid => &mut quote_to_fund,
+                    Side::Ask => &mut base_to_fund,
+                };
+                *to_fund = to_fund
+                    .checked_add(deposit_amount)
                     .ok_or(ErrorCode::MathOverflow)?;
-                // 将该数量加到可用的报价代币中。
-                open_orders.quote_token_free = open_orders
-                    .quote_token_free
-                    .checked_add(total_quote_to_unlock)
+                quote_fee = quote_fee
+                    .checked_add(fills.total_fee)
                     .ok_or(ErrorCode::MathOverflow)?;
-            }
-            // 如果是卖单。
-            Side::Ask => {
-                // 从锁定的基础代币中减去订单数量。
-                open_orders.base_token_locked = open_orders
-                    .base_token_locked
-                    .checked_sub(order.base_qty)
+                base_fee = base_fee
+                    .checked_add(fills.base_fee())
                     .ok_or(ErrorCode::MathOverflow)?;
-                // 将订单数量加到可用的基础代币中。
-                open_orders.base_token_free = open_orders
-                    .base_token_free
-                    .checked_add(order.base_qty)
+                referrer_fee = referrer_fee
+                    .checked_add(fills.referrer_fee)
                     .ok_or(ErrorCode::MathOverflow)?;
             }
-        }
 
-        // 从用户的 OpenOrders 账户中移除订单 ID。
-        open_orders.remove_order(order_id)?;
+            // 每个方向只筹集一次资金（优先使用可用余额），并一次性收取手续费。
+            ctx.accounts.fund(Side::Bid, quote_to_fund)?;
+            ctx.accounts.fund(Side::Ask, base_to_fund)?;
+            ctx.accounts.collect_fees(quote_fee, base_fee, referrer_fee)
+        }
 
-        // 发出取消订单事件。
-        emit!(OrderCancelledEvent {
-            // 市场地址。
-            market: ctx.accounts.market.key(),
-            // 订单所有者地址。
-            owner: open_orders.owner,
-            // 被取消的订单 ID。
-            order_id
-        });
+        // `amend_order` 指令：原地减少一个活动订单的剩余数量，保留其在订单簿中的时间优先级。
+        // 减少部分对应的锁定资金直接解锁到可用余额，不需要任何代币转账。
+        pub fn amend_order(
+            ctx: Context<CancelLimitOrder>,
+            // 要修改的订单 ID
+            order_id: u64,
+            // 新的剩余数量，必须小于当前剩余数量
+            new_quantity: u64,
+        ) -> Result<()> {
+            // 新数量必须是 base_lot_size 的整数倍。
+            require!(
+                new_quantity.is_multiple_of(ctx.accounts.market.base_lot_size),
+                ErrorCode::InvalidLotSize
+            );
+            // 在用户的 OpenOrders 账户中查找订单。
+            let slot = ctx
+                .accounts
+                .open_orders
+                .find_order(order_id)
+                .ok_or(ErrorCode::OrderNotFoundInOpenOrders)?;
+            // 修改后的订单同样要满足最小下单量和最小名义价值，防止通过修改留下粉尘订单。
+            ctx.accounts
+                .market
+                .validate_order_size(slot.price, new_quantity)?;
+            let side = slot.side();
+
+            // 根据订单方向，加载对应的订单簿（可变）。
+            let mut order_book = match side {
+                // 如果是买单，加载买单簿。
+                Side::Bid => BookSide::load_mut(&ctx.accounts.bids)?,
+                // 如果是卖单，加载卖单簿。
+                Side::Ask => BookSide::load_mut(&ctx.accounts.asks)?,
+            };
+            // 原地减少订单数量。
+            let order = order_book.reduce_order(slot.price, slot.order_id, new_quantity)?;
 
-        // 返回成功。
-        Ok(())
-    }
+            // 验证订单簿中的订单确实属于调用者。
+            require_keys_eq!(
+                order.owner_account,
+                ctx.accounts.open_orders.key(),
+                ErrorCode::Unauthorized
+            );
 
-    // 1. 精确计算 Maker 当初为这笔交易实际锁定的金额（基于他自己的出价 `best_bid.price` 和预估的 Taker 手续费）。
-    // 2. 从 Maker 的 `quote_token_locked` 中减去上述锁定的总额。
-    // 3. 计算 Maker 因价格优待（以更低价成交）和手续费差（支付更低的 Maker 手e费）而应收到的返还金额 (rebate)。
-    // 4. 将此返还金额添加到 Maker 的 `quote_token_free` 余额中。
-    // 这确保了所有资金流动都是精确和公平的，符合订单簿撮合的原则。
-    pub fn match_orders<'info>(
-        // 使用特殊的生命周期注解来处理 remaining_accounts。
-        ctx: Context<'_, '_, 'info, 'info, MatchOrders<'info>>,
-        // 本次调用最多撮合的次数，防止交易消耗过多的计算单元 (CU)。
-        match_limit: u64,
-    ) -> Result<()> {
-        // --- 1. 初始化和前置检查 ---
-
-        // 获取 market 账户的不可变引用，用于读取市场配置。
-        let market = &ctx.accounts.market;
-        // 获取买单簿的账户加载器。
-        let bids_loader = &ctx.accounts.bids;
-        // 获取卖单簿的账户加载器。
-        let asks_loader = &ctx.accounts.asks;
-
-        // 加载买单簿（不可变），以获取最优买单价。
-        let best_bid_price = bids_loader
-            .load()?
-            // 获取价格最高的买单。
-            .get_best_price_order()
-            // 如果有订单，则返回其价格；否则返回 0。
-            .map_or(0, |o| o.price);
-
-        // 加载卖单簿（不可变），以获取最优卖单价。
-        let best_ask_price = asks_loader
-            .load()?
-            // 获取价格最低的卖单。
-            .get_best_price_order()
-            // 如果有订单，则返回其价格；否则返回 u64 的最大值，确保比较有效。
-            .map_or(u64::MAX, |o| o.price);
-
-        // 如果最优买价低于最优卖价（即买卖盘口存在价差），则没有可撮合的订单，直接返回。
-        if best_bid_price < best_ask_price {
-            // 记录日志，说明没有可撮合的订单。
-            msg!("No orders to match: spread exists.");
-            // 提前成功退出。
-            return Ok(());
+            // 解锁减少部分对应的资金，订单仍然保持活动状态。
+            let reduced_qty = order.base_qty - new_quantity;
+            QueueEvent::out(&order, side, reduced_qty, false)?
+                .apply(&mut ctx.accounts.open_orders)?;
+
+            // 发出修改订单事件。
+            emit!(OrderAmendedEvent {
+                // 市场地址。
+                market: ctx.accounts.market.key(),
+                // 订单所有者地址。
+                owner: ctx.accounts.open_orders.owner,
+                // 被修改的订单 ID。
+                order_id,
+                // 被修改订单的客户端订单 ID。
+                client_order_id: slot.client_order_id,
+                // 修改后的剩余数量。
+                base_qty: new_quantity,
+            });
+
+            // 返回成功。
+            Ok(())
         }
 
-        // --- 2. 准备 PDA 签名 ---
-
-        // 准备 Market PDA 的签名种子，用于后续代表程序进行代币转账（如收取手续费）。
-        let seeds = &[
-            // "market" 是 PDA 的种子之一。
-            b"market".as_ref(),
-            // 基础代币 mint 地址作为种子，确保 PDA 的唯一性。
-            market.base_mint.as_ref(),
-            // 报价代币 mint 地址作为种子。
-            market.quote_mint.as_ref(),
-            // PDA 的 bump seed。
-            &[market.bump],
-        ];
-        // 将种子包装成签名者数组，用于 CPI 调用。
-        let signer = &[&seeds[..]];
-
-        // --- 3. 循环撮合 ---
-
-        // 创建一个迭代器，用于按顺序读取 `remaining_accounts` 中传入的 Maker 们的 OpenOrders 账户。
-        let mut remaining_accounts_iter = ctx.remaining_accounts.iter();
-
-        // 循环撮合，直到达到 `match_limit` 或无法再撮合。
-        for i in 0..match_limit {
-            // 加载买单簿（可变），因为撮合会修改它。
-            let mut bids = bids_loader.load_mut()?;
-            // 加载卖单簿（可变）。
-            let mut asks = asks_loader.load_mut()?;
-
-            // 在循环内部再次获取最优订单，因为上一轮撮合可能已经改变了订单簿。
-            let best_bid_opt = bids.get_best_price_order();
-            let best_ask_opt = asks.get_best_price_order();
-
-            // 如果买单簿或卖单簿为空，则无法继续撮合，跳出循环。
-            if best_bid_opt.is_none() || best_ask_opt.is_none() {
-                msg!("Match loop {} stopped: one side of the book is empty.", i);
-                break;
+        // `match_orders` 指令：撮合订单簿中已经交叉的挂单。
+        // 新订单在下单时已即时撮合，此指令用于清理仍处于交叉状态的订单簿。
+        // 买卖双方均为挂单，成交结果以 Fill 事件写入事件队列，由 `consume_events` 按各自当初锁定的资金结算，
+        // 买单因以更低价成交或支付更低的 maker 手续费而节省的金额会返还到其 `quote_token_free`。
+        // 如果双方是同一账户（自成交），按较新订单的自成交处理方式取消订单（Out 事件），而不是让整个指令失败。
+        pub fn match_orders(
+            // 账户上下文。
+            ctx: Context<MatchOrders>,
+            // 本次调用最多撮合的次数，防止交易消耗过多的计算单元 (CU)。
+            match_limit: u64,
+        ) -> Result<()> {
+            // --- 1. 初始化和前置检查 ---
+
+            // 获取 market 账户的不可变引用，用于读取市场配置。
+            let market = &ctx.accounts.market;
+            // 获取买单簿的账户加载器。
+            let bids_loader = &ctx.accounts.bids;
+            // 获取卖单簿的账户加载器。
+            let asks_loader = &ctx.accounts.asks;
+
+            // 加载买单簿，以获取最优买单价。
+            let best_bid_price = BookSide::load_mut(bids_loader)?
+                // 获取价格最高的买单。
+                .get_best_price_order()
+                // 如果有订单，则返回其价格；否则返回 0。
+                .map_or(0, |o| o.price);
+
+            // 加载卖单簿，以获取最优卖单价。
+            let best_ask_price = BookSide::load_mut(asks_loader)?
+                // 获取价格最低的卖单。
+                .get_best_price_order()
+                // 如果有订单，则返回其价格；否则返回 u64 的最大值，确保比较有效。
+                .map_or(u64::MAX, |o| o.price);
+
+            // 如果最优买价低于最优卖价（即买卖盘口存在价差），则没有可撮合的订单，直接返回。
+            if best_bid_price < best_ask_price {
+                // 记录日志，说明没有可撮合的订单。
+                msg!("No orders to match: spread exists.");
+                // 提前成功退出。
+                return Ok(());
             }
 
-            // 解包订单，此时我们确定它们是存在的。
-            let mut best_bid = best_bid_opt.unwrap();
-            let mut best_ask = best_ask_opt.unwrap();
+            // --- 2. 循环撮合 ---
+
+            // 本次调用累计的手续费，循环结束后一次性转入手续费金库。
+            let mut total_fees: u64 = 0;
+            // 加载事件队列（可变），成交和取消结果都写入其中。
+            let mut event_queue = ctx.accounts.event_queue.load_mut()?;
+
+            // 循环撮合，直到达到 `match_limit` 或无法再撮合。
+            for i in 0..match_limit {
+                // 加载买单簿（可变），因为撮合会修改它。
+                let mut bids = BookSide::load_mut(bids_loader)?;
+                // 加载卖单簿（可变）。
+                let mut asks = BookSide::load_mut(asks_loader)?;
+
+                // 在循环内部再次获取最优订单，因为上一轮撮合可能已经改变了订单簿。
+                let (Some(best_bid), Some(best_ask)) =
+                    (bids.get_best_price_order(), asks.get_best_price_order())
+                else {
+                    // 如果买单簿或卖单簿为空，则无法继续撮合，跳出循环。
+                    msg!("Match loop {} stopped: one side of the book is empty.", i);
+                    break;
+                };
+
+                // 再次检查价格，确保依然可以撮合。这是一个安全检查。
+                if best_bid.price < best_ask.price {
+                    msg!("Match loop {} stopped: spread exists after a match.", i);
+                    break;
+                }
 
-            // 再次检查价格，确保依然可以撮合。这是一个安全检查。
-            if best_bid.price < best_ask.price {
-                msg!("Match loop {} stopped: spread exists after a match.", i);
-                break;
+                // 每轮撮合最多写入两个事件，事件队列空位不足时停止，等待 `consume_events` 处理积压的事件。
+                if event_queue.free_slots() < 2 {
+                    msg!("Match loop {} stopped: event queue is full.", i);
+                    break;
+                }
+
+                // 确定谁是 Maker（订单ID小的，先挂出）和 Taker。
+                let maker_is_bid = best_bid.order_id < best_ask.order_id;
+
+                // --- 3. 自成交 ---
+
+                // 双方属于同一个 OpenOrders 账户，不产生成交，
+                // 按较新订单（taker）的自成交处理方式取消双方的部分或全部数量，避免订单簿一直处于交叉状态。
+                if best_bid.owner_account == best_ask.owner_account {
+                    let (bid_cancel_qty, ask_cancel_qty) = if maker_is_bid {
+                        let (ask_qty, bid_qty) = best_ask
+                            .self_trade_behavior
+                            .cancel_quantities(best_ask.base_qty, best_bid.base_qty);
+                        (bid_qty, ask_qty)
+                    } else {
+                        best_bid
+                            .self_trade_behavior
+                            .cancel_quantities(best_bid.base_qty, best_ask.base_qty)
+                    };
+                    // 取消买单的相应数量，写入 Out 事件以解锁资金。
+                    if bid_cancel_qty > 0 {
+                        let bid_done = bids.fill_best_order(bid_cancel_qty)?.base_qty == 0;
+                        event_queue.push(QueueEvent::out(
+                            &best_bid,
+                            Side::Bid,
+                            bid_cancel_qty,
+                            bid_done,
+                        )?)?;
+                    }
+                    // 取消卖单的相应数量，写入 Out 事件以解锁资金。
+                    if ask_cancel_qty > 0 {
+                        let ask_done = asks.fill_best_order(ask_cancel_qty)?.base_qty == 0;
+                        event_queue.push(QueueEvent::out(
+                            &best_ask,
+                            Side::Ask,
+                            ask_cancel_qty,
+                            ask_done,
+                        )?)?;
+                    }
+                    // 继续下一轮撮合。
+                    continue;
+                }
+
+                // --- 4. 计算交易参数 ---
+
+                // 确定成交价格：遵循价格时间优先原则，先挂出的订单（ID更小）价格优先，这对 Maker 有利。
+                let (maker_order, taker_order, taker_side) = if maker_is_bid {
+                    // 如果买单是 Maker，以买单的价格成交。
+                    (&best_bid, &best_ask, Side::Ask)
+                } else {
+                    // 如果卖单是 Maker，以卖单的价格成交。
+                    (&best_ask, &best_bid, Side::Bid)
+                };
+                let trade_price = maker_order.price;
+
+                // 确定成交数量：取买卖双方订单数量的较小者。
+                let trade_base_qty = std::cmp::min(best_bid.base_qty, best_ask.base_qty);
+
+                // 计算成交的报价代币总额 (价格 * 数量 / 缩放因子)。
+                let trade_quote_qty = quote_amount(trade_price, trade_base_qty)?;
+
+                // 根据成交额计算 Maker 和 Taker 的手续费（maker 手续费为负数时是返佣）。
+                // 双方都按各自挂单时的费率等级计费：较早的挂单使用 maker 费率，较新的挂单使用 taker 费率。
+                // 买单的手续费不能超过其下单时为这部分数量预留的金额（费率在下单后上调时按预留金额收取）。
+                let bid_fee_reserve = best_bid.fee_reserve(trade_base_qty, trade_quote_qty)?;
+                let mut taker_fee = fee_amount(trade_quote_qty, taker_order.taker_fee_bps(market));
+                if !maker_is_bid {
+                    taker_fee = taker_fee.min(bid_fee_reserve);
+                }
+                let mut maker_fee = cap_maker_rebate(
+                    maker_fee_amount(trade_quote_qty, maker_order.maker_fee_bps(market))?,
+                    taker_fee,
+                )?;
+                if maker_is_bid {
+                    maker_fee = maker_fee.min(i64::try_from(bid_fee_reserve).unwrap_or(i64::MAX));
+                }
+                let signed_taker_fee =
+                    i64::try_from(taker_fee).map_err(|_| error!(ErrorCode::MathOverflow))?;
+                // 按角色分配买卖双方各自支付的手续费。
+                let (bid_fee, ask_fee) = if maker_is_bid {
+                    (maker_fee, signed_taker_fee)
+                } else {
+                    (signed_taker_fee, maker_fee)
+                };
+
+                // --- 5. 更新订单簿并写入成交事件 ---
+
+                // 扣减双方的剩余数量。
+                let bid_done = bids.fill_best_order(trade_base_qty)?.base_qty == 0;
+                let ask_done = asks.fill_best_order(trade_base_qty)?.base_qty == 0;
+                // 买卖双方都是订单簿中的挂单，均按各自订单当初锁定的资金结算。
+                event_queue.push(QueueEvent::fill(
+                    &best_bid,
+                    Side::Bid,
+                    trade_base_qty,
+                    trade_quote_qty,
+                    bid_fee,
+                    bid_done,
+                )?)?;
+                event_queue.push(QueueEvent::fill(
+                    &best_ask,
+                    Side::Ask,
+                    trade_base_qty,
+                    trade_quote_qty,
+                    ask_fee,
+                    ask_done,
+                )?)?;
+
+                // --- 6. 累计手续费 ---
+
+                // maker 返佣从 taker 手续费中扣除。
+                total_fees = total_fees
+                    .checked_add(taker_fee)
+                    .and_then(|v| v.checked_add_signed(maker_fee))
+                    .ok_or(ErrorCode::MathOverflow)?;
+
+                // 发出成交事件。
+                emit!(TradeEvent {
+                    // 市场地址。
+                    market: market.key(),
+                    // maker 订单 ID。
+                    maker_order_id: maker_order.order_id,
+                    // taker 订单 ID。
+                    taker_order_id: taker_order.order_id,
+                    // maker 的 OpenOrders 账户地址。
+                    maker_open_orders: maker_order.owner_account,
+                    // maker 钱包地址（较早挂单的所有者）。
+                    maker_owner: maker_order.owner,
+                    // taker 钱包地址（较新挂单的所有者）。
+                    taker_owner: taker_order.owner,
+                    // taker 的 OpenOrders 账户地址。
+                    taker_open_orders: taker_order.owner_account,
+                    // taker 的方向。
+                    taker_side,
+                    // 成交价格。
+                    price: trade_price,
+                    // 成交的基础代币数量。
+                    base_qty: trade_base_qty,
+                    // 成交的报价代币金额。
+                    quote_qty: trade_quote_qty,
+                    // maker 手续费。
+                    maker_fee,
+                    // taker 手续费。
+                    taker_fee,
+                    // 挂单之间的撮合总是以报价代币收取手续费。
+                    taker_fee_in_base: false,
+                    // 挂单之间的撮合没有推荐人。
+                    referrer_fee: 0,
+                });
             }
 
-            // 验证并禁止自我交易（同一个人不能自己和自己成交）。
-            require_keys_neq!(
-                best_bid.owner_account,
-                best_ask.owner_account,
-                ErrorCode::SelfTradeForbidden
-            );
+            // --- 7. 手续费转账 ---
 
-            // --- 4. 加载 Maker 账户并计算交易参数 ---
+            // 将本次调用累计的手续费从 `quote_vault` 一次性转入 `fee_vault`。
+            collect_fees_to_vault(
+                market,
+                &ctx.accounts.token_program,
+                &ctx.accounts.quote_vault,
+                &ctx.accounts.quote_mint,
+                &ctx.accounts.fee_vault,
+                total_fees,
+            )?;
 
-            // 从 `remaining_accounts` 中获取买家和卖家的 OpenOrders 账户信息。
-            // 链上程序无法动态加载账户，必须由客户端在交易指令中提前提供。
-            let bidder_ooa_info = next_account_info(&mut remaining_accounts_iter)?;
-            let asker_ooa_info = next_account_info(&mut remaining_accounts_iter)?;
-            // 验证传入的买家账户公钥是否与最优买单的 owner_account 匹配。
-            require_keys_eq!(
-                best_bid.owner_account,
-                bidder_ooa_info.key(),
-                ErrorCode::InvalidMakerAccount
-            );
-            // 验证传入的卖家账户公钥是否与最优卖单的 owner_account 匹配。
-            require_keys_eq!(
-                best_ask.owner_account,
-                asker_ooa_info.key(),
-                ErrorCode::InvalidMakerAccount
-            );
+            // 撮合循环结束，返回成功。
+            Ok(())
+        }
 
-            // 将账户信息反序列化为 `OpenOrders` 结构体，以便修改。
-            let mut bidder_ooa: Account<OpenOrders> = Account::try_from(bidder_ooa_info)?;
-            let mut asker_ooa: Account<OpenOrders> = Account::try_from(asker_ooa_info)?;
+        // `consume_events` 指令：按顺序处理事件队列，将成交和取消结果结算到 maker 的 OpenOrders 账户。
+        // 任何人都可以调用。事件涉及的 OpenOrders 账户通过 `remaining_accounts` 传入（顺序不限）；
+        // 遇到未传入账户的事件时停止，之后的事件留待下次处理，以保证事件按顺序结算。
+        // 账户已传入但事件无法结算时返回错误，该事件保留在队首，不会丢弃 maker 应得的资金。
+        pub fn consume_events<'info>(
+            // 使用特殊的生命周期注解来处理 remaining_accounts。
+            ctx: Context<'_, '_, 'info, 'info, ConsumeEvents<'info>>,
+            // 本次调用最多处理的事件数量，防止交易消耗过多的计算单元 (CU)。
+            limit: u16,
+        ) -> Result<()> {
+            // 加载事件队列（可变）。
+            let mut event_queue = ctx.accounts.event_queue.load_mut()?;
+            // 已处理的事件数量。
+            let mut consumed: u16 = 0;
+            // 当前日期，maker 的成交额计入当天的交易量。
+            let day = current_day()?;
+
+            while consumed < limit {
+                // 获取队首事件，队列为空则停止。
+                let Some(event) = event_queue.peek().copied() else {
+                    break;
+                };
+                // 在传入的账户中找到该事件对应的 OpenOrders 账户，找不到则停止。
+                let Some(owner_info) = ctx
+                    .remaining_accounts
+                    .iter()
+                    .find(|info| info.key() == event.owner_account)
+                else {
+                    msg!(
+                        "Stopped at event for missing account {}.",
+                        event.owner_account
+                    );
+                    break;
+                };
+                // 反序列化为 `OpenOrders` 结构体，应用事件后写回链上。
+                let mut owner_ooa: Account<OpenOrders> = Account::try_from(owner_info)?;
+                event.apply(&mut owner_ooa)?;
+                // 成交事件的金额计入 maker 的交易量，用于确定手续费等级。
+                if event.kind == EventKind::Fill {
+                    owner_ooa.record_volume(day, event.quote_qty)?;
+                }
+                owner_ooa.exit(ctx.program_id)?;
+                // 事件已结算，从队列中移除。
+                event_queue.pop()?;
+                consumed += 1;
+            }
 
-            // 确定成交价格：遵循价格时间优先原则，先挂出的订单（ID更小）价格优先，这对 Maker 有利。
-            let trade_price = if best_bid.order_id < best_ask.order_id {
-                // 如果买单是 Maker，以 Taker（卖单）的价格成交，对买家有利。
-                best_ask.price
+            // 记录日志。
+            msg!(
+                "Consumed {} events, {} remaining.",
+                consumed,
+                event_queue.count
+            );
+            // 返回成功。
+            Ok(())
+        }
+
+        // `swap` 指令：不需要 OpenOrders 账户的一次性兑换，直接在用户的代币账户之间结算。
+        // 兑换方向由输入和输出代币账户的 mint 决定：支付报价代币买入基础代币，或反之。
+        // 兑换以 taker 身份扫过对手方订单簿，maker 一方的结算写入事件队列。
+        // 如果实际得到的数量低于 `min_amount_out`，整笔交易失败。
+        pub fn swap(
+            // 账户上下文。
+            ctx: Context<Swap>,
+            // 最多支付的输入代币数量（买入时包含 taker 手续费）。
+            amount_in: u64,
+            // 至少需要得到的输出代币数量。
+            min_amount_out: u64,
+        ) -> Result<()> {
+            // 获取 market 账户的引用。
+            let market = &ctx.accounts.market;
+            // 验证市场是否暂停。
+            require!(!market.paused, ErrorCode::Paused);
+            // 验证输入数量是否大于0。
+            require!(amount_in > 0, ErrorCode::InvalidOrderInput);
+
+            // 根据输入和输出代币的 mint 确定兑换方向。
+            let source_mint = ctx.accounts.user_source_token_account.mint;
+            let destination_mint = ctx.accounts.user_destination_token_account.mint;
+            let side = if source_mint == market.quote_mint && destination_mint == market.base_mint {
+                // 支付报价代币，买入基础代币。
+                Side::Bid
+            } else if source_mint == market.base_mint && destination_mint == market.quote_mint {
+                // 支付基础代币，卖出换取报价代币。
+                Side::Ask
             } else {
-                // 如果卖单是 Maker，以 Taker（买单）的价格成交，对卖家有利。
-                best_bid.price
+                // 代币账户与市场不匹配。
+                return err!(ErrorCode::InvalidMint);
             };
 
-            // 确定成交数量：取买卖双方订单数量的较小者。
-            let trade_base_qty = std::cmp::min(best_bid.base_qty, best_ask.base_qty);
+            // 用户在本市场的 OpenOrders 地址，用于识别自成交（即使该账户尚未创建）。
+            let (owner_account, _) = Pubkey::find_program_address(
+                &[
+                    b"open_orders",
+                    market.key().as_ref(),
+                    ctx.accounts.owner.key().as_ref(),
+                ],
+                ctx.program_id,
+            );
+            // 按用户的交易量和持仓确定手续费率。
+            let day = current_day()?;
+            let rates = ctx.accounts.fee_rates(day)?;
+            // 买入时输入数量是报价代币预算；卖出时输入数量是基础代币数量（向下取整到 base_lot_size）。
+            let (max_base_qty, max_quote_qty) = match side {
+                Side::Bid => (u64::MAX, amount_in),
+                Side::Ask => (amount_in - amount_in % market.base_lot_size, u64::MAX),
+            };
 
-            // 计算成交的报价代币总额 (价格 * 数量 / 缩放因子)。
-            let trade_quote_qty = (trade_price as u128)
-                .checked_mul(trade_base_qty as u128)
-                .and_then(|v| v.checked_div(PRICE_SCALE))
-                .ok_or(ErrorCode::MathOverflow)? as u64;
+            // 作为 taker 撮合对手方订单簿，不设价格限制，由 `min_amount_out` 保护滑点。
+            let fills = {
+                let mut opposite_book = match side {
+                    Side::Bid => BookSide::load_mut(&ctx.accounts.asks)?,
+                    Side::Ask => BookSide::load_mut(&ctx.accounts.bids)?,
+                };
+                let mut event_queue = ctx.accounts.event_queue.load_mut()?;
+                match_taker_order(
+                    &mut opposite_book,
+                    &mut event_queue,
+                    &TakerOrder {
+                        side,
+                        limit_price: match side {
+                            Side::Bid => u64::MAX,
+                            Side::Ask => 0,
+                        },
+                        max_base_qty,
+                        max_quote_qty,
+                        owner_account,
+                        // 兑换不会挂单，遇到用户自己的挂单时停止，不影响这些挂单。
+                        self_trade_behavior: SelfTradeBehavior::CancelTaker,
+                        // 按用户所在的手续费等级计费。
+                        taker_fee_bps: rates.taker_fee_bps,
+                        referrer_share_bps: ctx.accounts.referrer_share_bps(),
+                        order_id: 0,
+                        owner: ctx.accounts.owner.key(),
+                    },
+                    market,
+                )?
+            };
 
-            // 确定谁是 Maker（订单ID小的）和 Taker。
-            let maker_is_bid = best_bid.order_id < best_ask.order_id;
+            // 计算实际支付和得到的数量：得到的代币已扣除 taker 手续费。
+            let amount_paid = fills.taker_cost(side)?;
+            let amount_out = fills.taker_proceeds(side)?;
+            // 滑点保护。
+            require!(amount_out >= min_amount_out, ErrorCode::SlippageExceeded);
+            // 传入了 OpenOrders 账户时，成交额计入用户的交易量。
+            if let Some(open_orders) = ctx.accounts.open_orders.as_mut() {
+                open_orders.record_volume(day, fills.quote_qty)?;
+            }
 
-            // 根据成交额计算 Maker 和 Taker 的手续费。
-            let (maker_fee, taker_fee) = {
-                let quote_as_u128 = trade_quote_qty as u128;
-                let maker_fee = (quote_as_u128 * market.maker_fee_bps as u128 / 10_000) as u64;
-                let taker_fee = (quote_as_u128 * market.taker_fee_bps as u128 / 10_000) as u64;
-                (maker_fee, taker_fee)
+            // 根据方向确定输入和输出代币对应的金库、mint 和小数位数。
+            let accounts = &ctx.accounts;
+            let (in_vault, in_mint, in_decimals, out_vault, out_mint, out_decimals) = match side {
+                Side::Bid => (
+                    &accounts.quote_vault,
+                    &accounts.quote_mint,
+                    market.quote_decimals,
+                    &accounts.base_vault,
+                    &accounts.base_mint,
+                    market.base_decimals,
+                ),
+                Side::Ask => (
+                    &accounts.base_vault,
+                    &accounts.base_mint,
+                    market.base_decimals,
+                    &accounts.quote_vault,
+                    &accounts.quote_mint,
+                    market.quote_decimals,
+                ),
             };
-            // 计算总手续费，用于后续转账。
-            let total_fee = maker_fee
-                .checked_add(taker_fee)
-                .ok_or(ErrorCode::MathOverflow)?;
+            // 将输入代币从用户账户转入金库。
+            transfer_from_user(
+                &accounts.token_program,
+                &accounts.user_source_token_account,
+                in_mint,
+                in_vault,
+                &accounts.owner,
+                amount_paid,
+                in_decimals,
+            )?;
+            // 将输出代币从金库转给用户。
+            transfer_from_vault(
+                market,
+                &accounts.token_program,
+                out_vault,
+                out_mint,
+                &accounts.user_destination_token_account,
+                amount_out,
+                out_decimals,
+            )?;
+            // 将本次撮合产生的手续费转入手续费金库。
+            collect_fees_to_vault(
+                market,
+                &accounts.token_program,
+                &accounts.quote_vault,
+                &accounts.quote_mint,
+                &accounts.fee_vault,
+                fills.total_fee,
+            )?;
+            collect_base_fees_to_vault(
+                market,
+                &accounts.token_program,
+                &accounts.base_vault,
+                &accounts.base_mint,
+                accounts.base_fee_vault.as_ref(),
+                fills.base_fee(),
+            )?;
+            // 将推荐人的分成转给推荐人。
+            pay_referrer(
+                market,
+                &accounts.token_program,
+                &accounts.quote_vault,
+                &accounts.quote_mint,
+                accounts.referrer.as_ref(),
+                fills.referrer_fee,
+            )?;
 
-            // 根据 Maker 是买方还是卖方，确定 maker 和 taker 的账户可变引用，简化后续代码。
-            let (maker_ooa, taker_ooa, maker_side) = if maker_is_bid {
-                // 如果 Maker 是买家，返回 (买家账户, 卖家账户, 买方标识)。
-                (&mut bidder_ooa, &mut asker_ooa, Side::Bid)
-            } else {
-                // 如果 Maker 是卖家，返回 (卖家账户, 买家账户, 卖方标识)。
-                (&mut asker_ooa, &mut bidder_ooa, Side::Ask)
+            // 发出兑换事件。
+            emit!(SwapEvent {
+                // 市场地址。
+                market: market.key(),
+                // 用户地址。
+                owner: accounts.owner.key(),
+                // 兑换方向。
+                side,
+                // 实际支付的数量。
+                amount_in: amount_paid,
+                // 实际得到的数量。
+                amount_out,
+                // taker 手续费。
+                taker_fee: fills.taker_fee,
+                // taker 手续费是否以基础代币支付。
+                taker_fee_in_base: fills.taker_fee_in_base,
+            });
+
+            // 返回成功。
+            Ok(())
+        }
+
+        // `deposit` 指令：将代币存入用户的 OpenOrders 账户，计入可用余额。
+        // 之后下单会优先使用可用余额，无需每次都进行代币转账。
+        pub fn deposit(
+            ctx: Context<Deposit>,
+            // 存入的基础代币数量
+            base_amount: u64,
+            // 存入的报价代币数量
+            quote_amount: u64,
+        ) -> Result<()> {
+            // 至少存入一种代币。
+            require!(
+                base_amount > 0 || quote_amount > 0,
+                ErrorCode::InvalidAmount
+            );
+            // 首次使用时初始化 OpenOrders 账户。
+            let bump = ctx.bumps.open_orders;
+            let market_key = ctx.accounts.market.key();
+            let owner_key = ctx.accounts.owner.key();
+            ctx.accounts
+                .open_orders
+                .init_if_needed(market_key, owner_key, bump);
+
+            // 存入基础代币。
+            if base_amount > 0 {
+                transfer_from_user(
+                    &ctx.accounts.token_program,
+                    &ctx.accounts.user_base_token_account,
+                    &ctx.accounts.base_mint,
+                    &ctx.accounts.base_vault,
+                    &ctx.accounts.owner,
+                    base_amount,
+                    ctx.accounts.market.base_decimals,
+                )?;
+                ctx.accounts.open_orders.base_token_free = ctx
+                    .accounts
+                    .open_orders
+                    .base_token_free
+                    // 使用 checked_add 防止溢出。
+                    .checked_add(base_amount)
+                    .ok_or(ErrorCode::MathOverflow)?;
+            }
+            // 存入报价代币。
+            if quote_amount > 0 {
+                transfer_from_user(
+                    &ctx.accounts.token_program,
+                    &ctx.accounts.user_quote_token_account,
+                    &ctx.accounts.quote_mint,
+                    &ctx.accounts.quote_vault,
+                    &ctx.accounts.owner,
+                    quote_amount,
+                    ctx.accounts.market.quote_decimals,
+                )?;
+                ctx.accounts.open_orders.quote_token_free = ctx
+                    .accounts
+                    .open_orders
+                    .quote_token_free
+                    // 使用 checked_add 防止溢出。
+                    .checked_add(quote_amount)
+                    .ok_or(ErrorCode::MathOverflow)?;
+            }
+
+            // 发出存款事件。
+            emit!(FundsDepositedEvent {
+                // 市场地址。
+                market: ctx.accounts.market.key(),
+                // 用户地址。
+                owner: ctx.accounts.owner.key(),
+                // 存入的基础代币数量。
+                base_amount,
+                // 存入的报价代币数量。
+                quote_amount,
+            });
+
+            // 返回成功。
+            Ok(())
+        }
+
+        // `withdraw` 指令：从用户 OpenOrders 账户的可用余额中提取部分资金到钱包，其余资金继续留在市场中用于交易。
+        // 与下单时一致，`Side::Bid` 表示报价代币，`Side::Ask` 表示基础代币。
+        pub fn withdraw(ctx: Context<SettleFunds>, side: Side, amount: u64) -> Result<()> {
+            // 提取数量必须大于 0。
+            require!(amount > 0, ErrorCode::InvalidAmount);
+            let accounts = ctx.accounts;
+            // 根据方向选择可用余额、金库、用户账户和 mint。
+            let (free, vault, user_token_account, mint, decimals) = match side {
+                Side::Bid => (
+                    &mut accounts.open_orders.quote_token_free,
+                    &accounts.quote_vault,
+                    &accounts.user_quote_token_account,
+                    &accounts.quote_mint,
+                    accounts.market.quote_decimals,
+                ),
+                Side::Ask => (
+                    &mut accounts.open_orders.base_token_free,
+                    &accounts.base_vault,
+                    &accounts.user_base_token_account,
+                    &accounts.base_mint,
+                    accounts.market.base_decimals,
+                ),
             };
+            // 从可用余额中扣除，余额不足时拒绝。
+            *free = free
+                .checked_sub(amount)
+                .ok_or(ErrorCode::InsufficientFreeBalance)?;
+            // 由 Market PDA 签名，将代币从金库转到用户钱包。
+            transfer_from_vault(
+                &accounts.market,
+                &accounts.token_program,
+                vault,
+                mint,
+                user_token_account,
+                amount,
+                decimals,
+            )?;
 
-            // --- 5. 核心：资金结算 ---
-
-            // 根据 Maker 的方向（买或卖）来更新双方的 OpenOrders 账户余额。
-            match maker_side {
-                // 场景 A: Maker 是卖家 (Asker), Taker 是买家 (Bidder)。
-                Side::Ask => {
-                    // --- Taker (买家) 更新 ---
-                    // Taker 需要支付的总额 = 成交额 + Taker手续费。
-                    let total_quote_paid_by_taker = trade_quote_qty
-                        .checked_add(taker_fee)
-                        .ok_or(ErrorCode::MathOverflow)?;
-                    // 从 Taker 锁定的报价代币中扣除支付总额。
-                    taker_ooa.quote_token_locked = taker_ooa
-                        .quote_token_locked
-                        .checked_sub(total_quote_paid_by_taker)
-                        .ok_or(ErrorCode::MathOverflow)?;
-                    // Taker 获得的基础代币进入可用余额。
-                    taker_ooa.base_token_free = taker_ooa
-                        .base_token_free
-                        .checked_add(trade_base_qty)
-                        .ok_or(ErrorCode::MathOverflow)?;
-
-                    // --- Maker (卖家)  ---
-                    // Maker 获得的净额 = 成交额 - Maker手续费。
-                    let quote_received_by_maker = trade_quote_qty
-                        .checked_sub(maker_fee)
-                        .ok_or(ErrorCode::MathOverflow)?;
-                    // 从 Maker 锁定的基础代币中扣除卖出的数量。
-                    maker_ooa.base_token_locked = maker_ooa
-                        .base_token_locked
-                        .checked_sub(trade_base_qty)
-                        .ok_or(ErrorCode::MathOverflow)?;
-                    // Maker 获得的报价代币进入可用余额。
-                    maker_ooa.quote_token_free = maker_ooa
-                        .quote_token_free
-                        .checked_add(quote_received_by_maker)
-                        .ok_or(ErrorCode::MathOverflow)?;
-                }
+            // 发出提款事件。
+            emit!(FundsWithdrawnEvent {
+                // 市场地址。
+                market: accounts.market.key(),
+                // 用户地址。
+                owner: accounts.owner.key(),
+                // 提取的代币（买方向为报价代币，卖方向为基础代币）。
+                side,
+                // 提取的数量。
+                amount,
+            });
+
+            // 返回成功。
+            Ok(())
+        }
 
-                // 场景 B: Maker 是买家 (Bidder), Taker 是卖家 (Asker)。
-                Side::Bid => {
-                    // --- Taker (卖家)  ---
-                    // 从 Taker 锁定的基础代币中扣除卖出的数量。
-                    taker_ooa.base_token_locked = taker_ooa
-                        .base_token_locked
-                        .checked_sub(trade_base_qty)
-                        .ok_or(ErrorCode::MathOverflow)?;
-                    // Taker 获得的净额 = 成交额 - Taker手续费。
-                    let quote_for_taker = trade_quote_qty
-                        .checked_sub(taker_fee)
-                        .ok_or(ErrorCode::MathOverflow)?;
-                    // Taker 获得的报价代币进入可用余额。
-                    taker_ooa.quote_token_free = taker_ooa
-                        .quote_token_free
-                        .checked_add(quote_for_taker)
-                        .ok_or(ErrorCode::MathOverflow)?;
-
-                    // --- Maker (买家) 更新 ---
-                    // 1. Maker 获得基础代币。
-                    maker_ooa.base_token_free = maker_ooa
-                        .base_token_free
-                        .checked_add(trade_base_qty)
-                        .ok_or(ErrorCode::MathOverflow)?;
-
-                    // 2. 计算 Maker 当初为这部分成交量 `锁定的` 报价代币金额。
-                    //    这是基于他自己的出价 `best_bid.price`，而不是最终成交价。
-                    let original_quote_locked = (best_bid.price as u128)
-                        .checked_mul(trade_base_qty as u128)
-                        .and_then(|v| v.checked_div(PRICE_SCALE))
-                        .ok_or(ErrorCode::MathOverflow)?
-                        as u64;
-
-                    // 3. 计算他当初锁定时，为这部分金额预估的 Taker 手续费。
-                    let original_fee_locked = (original_quote_locked as u128
-                        * market.taker_fee_bps as u128
-                        / 10_000) as u64;
-
-                    // 4. 计算当初锁定的总额。
-                    let total_original_locked = original_quote_locked
-                        .checked_add(original_fee_locked)
-                        .ok_or(ErrorCode::MathOverflow)?;
-
-                    // 5. 从 Maker 的锁定总额中减去这部分被 "消费" 的锁定金额。
-                    maker_ooa.quote_token_locked = maker_ooa
-                        .quote_token_locked
-                        .checked_sub(total_original_locked)
-                        .ok_or(ErrorCode::MathOverflow)?;
-
-                    // 6. 计算应返还给 Maker 的金额 (Rebate)。
-                    //    返还金额 = 当初锁定的总额 - 实际花费的成交额 - 他自己应付的 Maker 手续费。
-                    let rebate_amount = total_original_locked
-                        .checked_sub(trade_quote_qty)
-                        .and_then(|v| v.checked_sub(maker_fee))
-                        .ok_or(ErrorCode::MathOverflow)?;
-
-                    // 7. 将返还的金额（价格优待 + 节省的手续费）加入 Maker 的可用余额。
-                    maker_ooa.quote_token_free = maker_ooa
-                        .quote_token_free
-                        .checked_add(rebate_amount)
-                        .ok_or(ErrorCode::MathOverflow)?;
-                }
+        // `settle_funds` 指令：用户提取其 OpenOrders 账户中的全部可用资金；部分提取使用 `withdraw`。
+        pub fn settle_funds(ctx: Context<SettleFunds>) -> Result<()> {
+            // 获取 market 账户的引用。
+            let market = &ctx.accounts.market;
+            // 获取 open_orders 账户的可变引用。
+            let open_orders = &mut ctx.accounts.open_orders;
+
+            // 准备 PDA 签名种子。
+            let seeds = &[
+                b"market".as_ref(),
+                market.base_mint.as_ref(),
+                market.quote_mint.as_ref(),
+                &[market.bump],
+            ];
+            // 将种子包装成签名者。
+            let signer = &[&seeds[..]];
+
+            // 获取可用的基础代币数量。
+            let base_amount = open_orders.base_token_free;
+            // 如果有可用的基础代币。
+            if base_amount > 0 {
+                // 将可用余额清零。
+                open_orders.base_token_free = 0;
+                // 通过 CPI 将基础代币从程序金库转移到用户钱包。
+                transfer_checked(
+                    // 创建带签名的 CPI 上下文。
+                    CpiContext::new_with_signer(
+                        // 传入 Token Program。
+                        ctx.accounts.token_program.to_account_info(),
+                        // 定义转账账户。
+                        TransferChecked {
+                            // 源账户（基础代币金库）。
+                            from: ctx.accounts.base_vault.to_account_info(),
+                            // 代币 mint。
+                            mint: ctx.accounts.base_mint.to_account_info(),
+                            // 目标账户（用户钱包）。
+                            to: ctx.accounts.user_base_token_account.to_account_info(),
+                            // 授权方（Market PDA）。
+                            authority: market.to_account_info(),
+                        },
+                        // 传入 PDA 签名。
+                        signer,
+                    ),
+                    // 转账金额。
+                    base_amount,
+                    // 代币小数位数。
+                    market.base_decimals,
+                )?;
             }
 
-            // --- 6. 手续费转账 ---
-
-            // 如果本笔交易产生了手续费。
-            if total_fee > 0 {
-                // 通过 CPI 调用 Token Program，将总手续费从程序的 `quote_vault` 转移到 `fee_vault`。
+            // 获取可用的报价代币数量。
+            let quote_amount = open_orders.quote_token_free;
+            // 如果有可用的报价代币。
+            if quote_amount > 0 {
+                // 将可用余额清零。
+                open_orders.quote_token_free = 0;
+                // 通过 CPI 将报价代币从程序金库转移到用户钱包。
                 transfer_checked(
-                    // 创建带 PDA 签名的 CPI 上下文。
+                    // 创建带签名的 CPI 上下文。
                     CpiContext::new_with_signer(
+                        // 传入 Token Program。
                         ctx.accounts.token_program.to_account_info(),
+                        // 定义转账账户。
                         TransferChecked {
+                            // 源账户（报价代币金库）。
                             from: ctx.accounts.quote_vault.to_account_info(),
+                            // 代币 mint。
                             mint: ctx.accounts.quote_mint.to_account_info(),
-                            to: ctx.accounts.fee_vault.to_account_info(),
-                            // 授权方是 Market PDA。
+                            // 目标账户（用户钱包）。
+                            to: ctx.accounts.user_quote_token_account.to_account_info(),
+                            // 授权方（Market PDA）。
                             authority: market.to_account_info(),
                         },
                         // 传入 PDA 签名。
                         signer,
                     ),
-                    // 转账金额为总手续费。
-                    total_fee,
-                    // 报价代币的小数位数。
+                    // 转账金额。
+                    quote_amount,
+                    // 代币小数位数。
                     market.quote_decimals,
                 )?;
             }
 
-            // --- 7. 更新订单簿和 OpenOrders 账户 ---
+            // 返回成功。
+            Ok(())
+        }
+
+        // `close_open_orders` 指令：关闭用户的 OpenOrders 账户并回收租金。
+        pub fn close_open_orders(ctx: Context<CloseOpenOrders>) -> Result<()> {
+            // 获取 open_orders 账户的引用。
+            let open_orders = &ctx.accounts.open_orders;
+            // 验证账户中没有任何可用的基础代币。
+            require!(
+                open_orders.base_token_free == 0,
+                ErrorCode::OpenOrdersAccountNotEmpty
+            );
+            // 验证账户中没有任何可用的报价代币。
+            require!(
+                open_orders.quote_token_free == 0,
+                ErrorCode::OpenOrdersAccountNotEmpty
+            );
+            // 验证账户中没有任何锁定的基础代币。
+            require!(
+                open_orders.base_token_locked == 0,
+                ErrorCode::OpenOrdersAccountNotEmpty
+            );
+            // 验证账户中没有任何锁定的报价代币。
+            require!(
+                open_orders.quote_token_locked == 0,
+                ErrorCode::OpenOrdersAccountNotEmpty
+            );
+            // 验证账户中没有活动订单：完全成交的挂单要等 `consume_events` 结算后才会移除，
+            // 账户在此之前关闭会让事件队列中的事件找不到对应的账户。
+            require!(
+                open_orders.orders.is_empty(),
+                ErrorCode::OpenOrdersAccountNotEmpty
+            );
+            // Anchor 的 `close` 约束会自动处理账户关闭和租金返还的逻辑。
+            // 这里不需要写额外的代码，只需验证前提条件即可。
+            // 返回成功。
+            Ok(())
+        }
+
+        // `resize_open_orders` 指令：调整 OpenOrders 账户可容纳的活动订单数量。
+        // 账户空间由 `realloc` 约束调整：扩容时由用户支付租金，缩容时退还多余的租金。
+        pub fn resize_open_orders(ctx: Context<ResizeOpenOrders>, capacity: u16) -> Result<()> {
+            // 获取 open_orders 账户的可变引用。
+            let open_orders = &mut ctx.accounts.open_orders;
+            // 新容量必须在允许范围内，并且不能少于当前的活动订单数量。
+            require!(
+                capacity > 0
+                    && capacity <= MAX_OPEN_ORDERS_CAPACITY
+                    && capacity as usize >= open_orders.orders.len(),
+                ErrorCode::InvalidOpenOrdersCapacity
+            );
+            // 更新容量。
+            open_orders.order_capacity = capacity;
+            // 返回成功。
+            Ok(())
+        }
+
+        // `set_pause` 指令：管理员暂停或恢复市场交易。
+        pub fn set_pause(ctx: Context<AdminOnly>, paused: bool) -> Result<()> {
+            // 设置 market 账户的 paused 字段。
+            ctx.accounts.market.paused = paused;
+            // 发出市场暂停/恢复事件。
+            emit!(PauseEvent {
+                // 市场地址。
+                market: ctx.accounts.market.key(),
+                // 新的暂停状态。
+                paused
+            });
+            // 返回成功。
+            Ok(())
+        }
+
+        // `set_fees` 指令：管理员设置新的手续费率。
+        // maker 费率可以为负数（返佣），返佣率不能超过 taker 费率，并且只能在以报价代币收取 taker 手续费时使用。
+        pub fn set_fees(
+            ctx: Context<AdminOnly>,
+            maker_fee_bps: i16,
+            taker_fee_bps: u16,
+        ) -> Result<()> {
+            // 验证手续费率在有效范围内。
+            validate_fees(maker_fee_bps, taker_fee_bps)?;
+            // 获取 market 账户的可变引用。
+            let market = &mut ctx.accounts.market;
+            // maker 返佣以报价代币支付，只能由以报价代币收取的 taker 手续费提供资金。
+            require!(
+                maker_fee_bps >= 0 || market.taker_fee_mode == TakerFeeMode::Quote,
+                ErrorCode::RebateRequiresQuoteTakerFee
+            );
+            // 更新 maker 手续费率。
+            market.maker_fee_bps = maker_fee_bps;
+            // 更新 taker 手续费率。
+            market.taker_fee_bps = taker_fee_bps;
+            // 发出手续费更新事件。
+            emit!(FeesUpdatedEvent {
+                // 市场地址。
+                market: market.key(),
+                // 新的 maker 手续费率。
+                maker_fee_bps,
+                // 新的 taker 手续费率。
+                taker_fee_bps
+            });
+            // 返回成功。
+            Ok(())
+        }
+
+        // `set_market_params` 指令：管理员更新价格精度和数量精度。
+        // 新的精度只在下单和改单时校验；已经挂在订单簿中的订单保留原有的价格和数量（不受新精度约束），
+        // 可以继续正常成交和取消。PostOnlySlide 滑动后的价格总是对齐到新的 tick_size。
+        pub fn set_market_params(
+            ctx: Context<AdminOnly>,
+            // 新的价格最小变动单位
+            tick_size: u64,
+            // 新的数量最小变动单位
+            base_lot_size: u64,
+        ) -> Result<()> {
+            // 验证 tick_size 和 lot_size 必须大于 0。
+            require!(
+                tick_size > 0 && base_lot_size > 0,
+                ErrorCode::InvalidMarketParams
+            );
+            // 获取 market 账户的可变引用。
+            let market = &mut ctx.accounts.market;
+            // 记录原有的精度，供事件使用。
+            let old_tick_size = market.tick_size;
+            let old_base_lot_size = market.base_lot_size;
+            // 更新价格精度。
+            market.tick_size = tick_size;
+            // 更新数量精度。
+            market.base_lot_size = base_lot_size;
+            // 发出市场参数更新事件，前端据此刷新价格和数量的输入精度。
+            emit!(MarketParamsUpdatedEvent {
+                // 市场地址。
+                market: market.key(),
+                // 原有的价格精度。
+                old_tick_size,
+                // 新的价格精度。
+                tick_size,
+                // 原有的数量精度。
+                old_base_lot_size,
+                // 新的数量精度。
+                base_lot_size,
+            });
+            // 返回成功。
+            Ok(())
+        }
+
+        // `propose_authority` 指令：管理员提议将市场管理权限转移给新地址，需要新地址调用 `accept_authority` 后才生效。
+        // 提议默认地址可以撤销尚未被接受的提议。
+        pub fn propose_authority(ctx: Context<AdminOnly>, new_authority: Pubkey) -> Result<()> {
+            // 获取 market 账户的可变引用。
+            let market = &mut ctx.accounts.market;
+            // 记录待接受的新管理员。
+            market.pending_authority = new_authority;
+            // 发出管理员提议事件。
+            emit!(AuthorityProposedEvent {
+                // 市场地址。
+                market: market.key(),
+                // 当前管理员地址。
+                authority: market.authority,
+                // 提议的新管理员地址。
+                pending_authority: new_authority,
+            });
+            // 返回成功。
+            Ok(())
+        }
 
-            // 更新订单簿中订单的剩余数量。
-            best_bid.base_qty = best_bid
-                .base_qty
-                .checked_sub(trade_base_qty)
-                .ok_or(ErrorCode::MathOverflow)?;
-            best_ask.base_qty = best_ask
-                .base_qty
-                .checked_sub(trade_base_qty)
-                .ok_or(ErrorCode::MathOverflow)?;
+        // `accept_authority` 指令：被提议的新管理员签名接受市场管理权限。
+        pub fn accept_authority(ctx: Context<AcceptAuthority>) -> Result<()> {
+            // 获取 market 账户的可变引用。
+            let market = &mut ctx.accounts.market;
+            // 记录原管理员，供事件使用。
+            let old_authority = market.authority;
+            // 转移管理权限，并清除待接受的提议。
+            market.authority = ctx.accounts.new_authority.key();
+            market.pending_authority = Pubkey::default();
+            // 发出管理员转移事件。
+            emit!(AuthorityTransferredEvent {
+                // 市场地址。
+                market: market.key(),
+                // 原管理员地址。
+                old_authority,
+                // 新管理员地址。
+                new_authority: market.authority,
+            });
+            // 返回成功。
+            Ok(())
+        }
 
-            // 如果买单完全成交（剩余数量为0）。
-            if best_bid.base_qty == 0 {
-                // 【修正】先将 head 的值存入一个局部变量。
-                // 这样对 `bids.head` 的不可变借用会在这里立即结束。
-                let head_index = bids.head;
-                // 现在，`remove_order` 可以安全地获取对 `bids` 的可变借用，因为不再有活跃的不可变借用。
-                bids.remove_order(head_index)?;
-                // 从买家的 OpenOrders 账户的活动订单列表中移除该订单 ID。
-                bidder_ooa.remove_order(best_bid.order_id)?;
-            } else {
-                // 【修正】同样地，先读取 head 的值。
-                let head_index = bids.head as usize;
-                // 然后再用这个值去访问 nodes 数组并修改它。
-                bids.nodes[head_index].order = best_bid;
-            }
+        // `renounce_authority` 指令：管理员永久放弃市场管理权限。
+        // 管理员被设置为默认地址，之后所有管理员指令都无法再执行，市场参数从此固定。
+        // 必须先配置手续费分配账户，否则放弃后手续费金库中的资金将无法再被提取。
+        pub fn renounce_authority(ctx: Context<AdminOnly>) -> Result<()> {
+            // 获取 market 账户的可变引用。
+            let market = &mut ctx.accounts.market;
+            // 手续费分配账户未配置时不能放弃管理权限。
+            require!(
+                market.fee_treasury != Pubkey::default(),
+                ErrorCode::FeeDistributionNotConfigured
+            );
+            // 记录原管理员，供事件使用。
+            let old_authority = market.authority;
+            // 清除管理权限和待接受的提议。
+            market.authority = Pubkey::default();
+            market.pending_authority = Pubkey::default();
+            // 发出管理员放弃事件。
+            emit!(AuthorityRenouncedEvent {
+                // 市场地址。
+                market: market.key(),
+                // 原管理员地址。
+                old_authority,
+            });
+            // 返回成功。
+            Ok(())
+        }
 
-            // 如果卖单完全成交。
-            if best_ask.base_qty == 0 {
-                // 【修正】对 `asks` 做同样的处理。
-                let head_index = asks.head;
-                // `asks.remove_order` 现在可以安全地可变借用 `asks`。
-                asks.remove_order(head_index)?;
-                // 从卖家的 OpenOrders 账户的活动订单列表中移除该订单 ID。
-                asker_ooa.remove_order(best_ask.order_id)?;
-            } else {
-                // 【修正】同样地，先读取 head 的值。
-                let head_index = asks.head as usize;
-                // 然后再修改。
-                asks.nodes[head_index].order = best_ask;
-            }
+        // `set_order_size_limits` 指令：管理员更新最小下单量和最小名义价值。
+        // 只影响之后的新订单，已经挂在订单簿中的订单不受影响。
+        pub fn set_order_size_limits(
+            ctx: Context<AdminOnly>,
+            // 新的最小基础代币下单量
+            min_base_qty: u64,
+            // 新的最小名义价值
+            min_notional: u64,
+        ) -> Result<()> {
+            // 两个下限都必须大于 0。
+            require!(
+                min_base_qty > 0 && min_notional > 0,
+                ErrorCode::InvalidMarketParams
+            );
+            // 获取 market 账户的可变引用。
+            let market = &mut ctx.accounts.market;
+            // 更新最小下单量。
+            market.min_base_qty = min_base_qty;
+            // 更新最小名义价值。
+            market.min_notional = min_notional;
+            // 发出下单限制更新事件。
+            emit!(OrderSizeLimitsUpdatedEvent {
+                // 市场地址。
+                market: market.key(),
+                // 新的最小基础代币下单量。
+                min_base_qty,
+                // 新的最小名义价值。
+                min_notional,
+            });
+            // 返回成功。
+            Ok(())
+        }
 
-            // --- 8. 写回账户数据 ---
+        // `set_taker_fee_mode` 指令：管理员选择 taker 手续费的收取币种。
+        // 切换为以收到的资产收费时，买方的 taker 手续费以基础代币收取，首次切换会创建基础代币手续费金库。
+        // 挂单之间的撮合（`match_orders`）以及 maker 手续费始终以报价代币收取。
+        // 市场配置了手续费等级时必须传入手续费等级账户，任何等级含有 maker 返佣时同样不能切换为以收到的资产收费。
+        pub fn set_taker_fee_mode(ctx: Context<SetTakerFeeMode>, mode: TakerFeeMode) -> Result<()> {
+            // 获取 market 账户的可变引用。
+            let market = &mut ctx.accounts.market;
+            // 存在 maker 返佣（市场统一费率或任一等级）时，taker 手续费必须以报价代币收取。
+            if mode == TakerFeeMode::ReceivedAsset {
+                require!(
+                    market.maker_fee_bps >= 0,
+                    ErrorCode::RebateRequiresQuoteTakerFee
+                );
+                if market.fee_tier_count > 0 {
+                    let fee_tiers = ctx
+                        .accounts
+                        .fee_tiers
+                        .as_ref()
+                        .ok_or(ErrorCode::FeeTiersRequired)?;
+                    require!(
+                        fee_tiers.tiers.iter().all(|tier| tier.maker_fee_bps >= 0),
+                        ErrorCode::RebateRequiresQuoteTakerFee
+                    );
+                }
+            }
+            // 更新收费方式，并记录基础代币手续费金库地址。
+            market.taker_fee_mode = mode;
+            market.base_fee_vault = ctx.accounts.base_fee_vault.key();
+            // 发出收费方式更新事件。
+            emit!(TakerFeeModeUpdatedEvent {
+                // 市场地址。
+                market: market.key(),
+                // 新的收费方式。
+                mode,
+                // 基础代币手续费金库地址。
+                base_fee_vault: market.base_fee_vault,
+            });
+            // 返回成功。
+            Ok(())
+        }
 
-            // 将修改后的买家 OpenOrders 账户数据写回到链上。
-            // `exit` 会处理序列化和数据写入。
-            bidder_ooa.exit(&ctx.program_id)?;
-            // 将修改后的卖家 OpenOrders 账户数据写回到链上。
-            asker_ooa.exit(&ctx.program_id)?;
+        // `set_fee_tiers` 指令：管理员配置市场的手续费等级，首次调用时创建手续费等级账户。
+        // 等级按从低到高排列，用户同时满足最近 30 天成交额和持仓门槛的最后一个等级生效；
+        // 配置了等级后下单必须传入手续费等级账户并按所在等级计费，挂单作为 maker 成交时使用下单时等级的 maker 费率。
+        pub fn set_fee_tiers(
+            ctx: Context<SetFeeTiers>,
+            // 用于持仓门槛的代币 mint
+            holding_mint: Pubkey,
+            // 新的手续费等级（覆盖原有等级，传入空列表表示全部使用市场统一费率）
+            tiers: Vec<FeeTier>,
+        ) -> Result<()> {
+            // 等级数量不能超过账户空间。
+            require!(tiers.len() <= MAX_FEE_TIERS, ErrorCode::TooManyFeeTiers);
+            let market = &ctx.accounts.market;
+            for tier in &tiers {
+                // 每个等级的费率都需要满足与 `set_fees` 相同的限制。
+                validate_fees(tier.maker_fee_bps, tier.taker_fee_bps)?;
+                require!(
+                    tier.maker_fee_bps >= 0 || market.taker_fee_mode == TakerFeeMode::Quote,
+                    ErrorCode::RebateRequiresQuoteTakerFee
+                );
+            }
+            // 记录等级数量，之后下单时据此要求传入手续费等级账户。
+            let market = &mut ctx.accounts.market;
+            market.fee_tier_count = tiers.len() as u8;
+            // 更新手续费等级账户。
+            let fee_tiers = &mut ctx.accounts.fee_tiers;
+            fee_tiers.market = market.key();
+            fee_tiers.bump = ctx.bumps.fee_tiers;
+            fee_tiers.holding_mint = holding_mint;
+            fee_tiers.tiers = tiers.clone();
+            // 发出手续费等级更新事件。
+            emit!(FeeTiersUpdatedEvent {
+                // 市场地址。
+                market: market.key(),
+                // 用于持仓门槛的代币 mint。
+                holding_mint,
+                // 新的手续费等级。
+                tiers,
+            });
+            // 返回成功。
+            Ok(())
         }
 
-        // 撮合循环结束，返回成功。
-        Ok(())
-    }
+        // `set_referrer_share` 指令：管理员设置推荐人分得的 taker 手续费比例。
+        // 下单或兑换时传入推荐人的报价代币账户，撮合时按该比例从 taker 手续费（扣除 maker 返佣后）中直接支付给推荐人；
+        // 以基础代币收取的 taker 手续费不参与分成。
+        pub fn set_referrer_share(ctx: Context<AdminOnly>, referrer_share_bps: u16) -> Result<()> {
+            // 分成比例不能超过 100%。
+            require!(referrer_share_bps <= 10_000, ErrorCode::InvalidFee);
+            // 获取 market 账户的可变引用。
+            let market = &mut ctx.accounts.market;
+            // 更新推荐人分成比例。
+            market.referrer_share_bps = referrer_share_bps;
+            // 发出推荐人分成比例更新事件。
+            emit!(ReferrerShareUpdatedEvent {
+                // 市场地址。
+                market: market.key(),
+                // 新的推荐人分成比例。
+                referrer_share_bps,
+            });
+            // 返回成功。
+            Ok(())
+        }
 
-    // `settle_funds` 指令：用户提取其 OpenOrders 账户中的可用资金。
-    pub fn settle_funds(ctx: Context<SettleFunds>) -> Result<()> {
-        // 获取 market 账户的引用。
-        let market = &ctx.accounts.market;
-        // 获取 open_orders 账户的可变引用。
-        let open_orders = &mut ctx.accounts.open_orders;
-
-        // 准备 PDA 签名种子。
-        let seeds = &[
-            b"market".as_ref(),
-            market.base_mint.as_ref(),
-            market.quote_mint.as_ref(),
-            &[market.bump],
-        ];
-        // 将种子包装成签名者。
-        let signer = &[&seeds[..]];
-
-        // 获取可用的基础代币数量。
-        let base_amount = open_orders.base_token_free;
-        // 如果有可用的基础代币。
-        if base_amount > 0 {
-            // 将可用余额清零。
-            open_orders.base_token_free = 0;
-            // 通过 CPI 将基础代币从程序金库转移到用户钱包。
-            transfer_checked(
-                // 创建带签名的 CPI 上下文。
-                CpiContext::new_with_signer(
-                    // 传入 Token Program。
-                    ctx.accounts.token_program.to_account_info(),
-                    // 定义转账账户。
-                    TransferChecked {
-                        // 源账户（基础代币金库）。
-                        from: ctx.accounts.base_vault.to_account_info(),
-                        // 代币 mint。
-                        mint: ctx.accounts.base_mint.to_account_info(),
-                        // 目标账户（用户钱包）。
-                        to: ctx.accounts.user_base_token_account.to_account_info(),
-                        // 授权方（Market PDA）。
-                        authority: market.to_account_info(),
-                    },
-                    // 传入 PDA 签名。
-                    signer,
-                ),
-                // 转账金额。
-                base_amount,
-                // 代币小数位数。
-                market.base_decimals,
+        // `withdraw_fees` 指令：管理员从 `fee_vault` 或 `base_fee_vault` 中提取手续费到指定的代币账户。
+        pub fn withdraw_fees(ctx: Context<WithdrawFees>, amount: u64) -> Result<()> {
+            // 提取数量必须大于 0，且不超过金库余额。
+            require!(amount > 0, ErrorCode::InvalidAmount);
+            require!(
+                amount <= ctx.accounts.fee_vault.amount,
+                ErrorCode::InsufficientFeeBalance
+            );
+            // 由 Market PDA 签名，将手续费转到目标账户。
+            transfer_from_vault(
+                &ctx.accounts.market,
+                &ctx.accounts.token_program,
+                &ctx.accounts.fee_vault,
+                &ctx.accounts.mint,
+                &ctx.accounts.destination,
+                amount,
+                ctx.accounts.mint.decimals,
             )?;
+            // 发出手续费提取事件。
+            emit!(FeesWithdrawnEvent {
+                // 市场地址。
+                market: ctx.accounts.market.key(),
+                // 目标代币账户。
+                destination: ctx.accounts.destination.key(),
+                // 提取的数量。
+                amount,
+            });
+            // 返回成功。
+            Ok(())
         }
 
-        // 获取可用的报价代币数量。
-        let quote_amount = open_orders.quote_token_free;
-        // 如果有可用的报价代币。
-        if quote_amount > 0 {
-            // 将可用余额清零。
-            open_orders.quote_token_free = 0;
-            // 通过 CPI 将报价代币从程序金库转移到用户钱包。
-            transfer_checked(
-                // 创建带签名的 CPI 上下文。
-                CpiContext::new_with_signer(
-                    // 传入 Token Program。
-                    ctx.accounts.token_program.to_account_info(),
-                    // 定义转账账户。
-                    TransferChecked {
-                        // 源账户（报价代币金库）。
-                        from: ctx.accounts.quote_vault.to_account_info(),
-                        // 代币 mint。
-                        mint: ctx.accounts.quote_mint.to_account_info(),
-                        // 目标账户（用户钱包）。
-                        to: ctx.accounts.user_quote_token_account.to_account_info(),
-                        // 授权方（Market PDA）。
-                        authority: market.to_account_info(),
-                    },
-                    // 传入 PDA 签名。
-                    signer,
-                ),
-                // 转账金额。
-                quote_amount,
-                // 代币小数位数。
-                market.quote_decimals,
-            )?;
+        // `set_fee_distribution` 指令：管理员配置手续费的分配方式。
+        // `stakers_share_bps` 比例的手续费分给质押者，其余归协议金库；推荐人的分成在撮合时直接支付，不经过 `fee_vault`。
+        // 协议金库和质押者账户以代币账户的形式传入，由账户约束验证它们是报价代币账户，避免配置出无法分配的地址。
+        pub fn set_fee_distribution(
+            ctx: Context<SetFeeDistribution>,
+            // 分给质押者的比例（基点）
+            stakers_share_bps: u16,
+        ) -> Result<()> {
+            // 分配比例不能超过 100%。
+            require!(stakers_share_bps <= 10_000, ErrorCode::InvalidMarketParams);
+            // 获取 market 账户的可变引用。
+            let market = &mut ctx.accounts.market;
+            // 更新分配配置。
+            market.fee_treasury = ctx.accounts.treasury.key();
+            market.fee_stakers = ctx.accounts.stakers.key();
+            market.stakers_share_bps = stakers_share_bps;
+            // 发出手续费分配配置更新事件。
+            emit!(FeeDistributionUpdatedEvent {
+                // 市场地址。
+                market: market.key(),
+                // 协议金库的代币账户。
+                treasury: market.fee_treasury,
+                // 质押者奖励的代币账户。
+                stakers: market.fee_stakers,
+                // 分给质押者的比例。
+                stakers_share_bps,
+            });
+            // 返回成功。
+            Ok(())
         }
 
-        // 返回成功。
-        Ok(())
-    }
+        // `distribute_fees` 指令：按配置的比例将 `fee_vault` 中的全部手续费分配给协议金库和质押者。
+        // 目标账户已由管理员配置，因此任何人都可以调用。
+        pub fn distribute_fees(ctx: Context<DistributeFees>) -> Result<()> {
+            let market = &ctx.accounts.market;
+            // 必须先配置手续费分配。
+            require!(
+                market.fee_treasury != Pubkey::default(),
+                ErrorCode::FeeDistributionNotConfigured
+            );
+            // 按比例分配 `fee_vault` 中的全部手续费。
+            distribute_vault_fees(
+                market,
+                &ctx.accounts.token_program,
+                &ctx.accounts.fee_vault,
+                &ctx.accounts.quote_mint,
+                &ctx.accounts.treasury,
+                &ctx.accounts.stakers,
+            )
+        }
 
-    // `close_open_orders` 指令：关闭用户的 OpenOrders 账户并回收租金。
-    pub fn close_open_orders(ctx: Context<CloseOpenOrders>) -> Result<()> {
-        // 获取 open_orders 账户的引用。
-        let open_orders = &ctx.accounts.open_orders;
-        // 验证账户中没有任何可用的基础代币。
-        require!(
-            open_orders.base_token_free == 0,
-            ErrorCode::OpenOrdersAccountNotEmpty
-        );
-        // 验证账户中没有任何可用的报价代币。
-        require!(
-            open_orders.quote_token_free == 0,
-            ErrorCode::OpenOrdersAccountNotEmpty
-        );
-        // 验证账户中没有任何锁定的基础代币。
-        require!(
-            open_orders.base_token_locked == 0,
-            ErrorCode::OpenOrdersAccountNotEmpty
-        );
-        // 验证账户中没有任何锁定的报价代币。
-        require!(
-            open_orders.quote_token_locked == 0,
-            ErrorCode::OpenOrdersAccountNotEmpty
-        );
-        // Anchor 的 `close` 约束会自动处理账户关闭和租金返还的逻辑。
-        // 这里不需要写额外的代码，只需验证前提条件即可。
-        // 返回成功。
-        Ok(())
-    }
+        // `distribute_base_fees` 指令：按同样的比例将 `base_fee_vault` 中以基础代币收取的手续费分配出去。
+        // 接收方是已配置的协议金库和质押者报价代币账户的所有者名下的基础代币账户，因此无需单独配置，
+        // 放弃管理员权限后任何人仍然可以调用。
+        pub fn distribute_base_fees(ctx: Context<DistributeBaseFees>) -> Result<()> {
+            // 必须先配置手续费分配。
+            require!(
+                ctx.accounts.market.fee_treasury != Pubkey::default(),
+                ErrorCode::FeeDistributionNotConfigured
+            );
+            // 按比例分配 `base_fee_vault` 中的全部手续费。
+            distribute_vault_fees(
+                &ctx.accounts.market,
+                &ctx.accounts.token_program,
+                &ctx.accounts.base_fee_vault,
+                &ctx.accounts.base_mint,
+                &ctx.accounts.base_treasury,
+                &ctx.accounts.base_stakers,
+            )
+        }
 
-    // `set_pause` 指令：管理员暂停或恢复市场交易。
-    pub fn set_pause(ctx: Context<AdminOnly>, paused: bool) -> Result<()> {
-        // 设置 market 账户的 paused 字段。
-        ctx.accounts.market.paused = paused;
-        // 发出市场暂停/恢复事件。
-        emit!(PauseEvent {
-            // 市场地址。
-            market: ctx.accounts.market.key(),
-            // 新的暂停状态。
-            paused
-        });
-        // 返回成功。
-        Ok(())
-    }
+        // `grow_order_book` 指令：管理员为买单簿或卖单簿扩容。
+        // 账户空间已由 `realloc` 约束扩大，新增部分被清零；这里把内部节点数组整体后移，为新增的叶子节点腾出位置。
+        // 单条指令最多只能增加 10240 字节，更大的扩容可以在一笔交易中放入多条该指令。
+        pub fn grow_order_book(
+            ctx: Context<GrowOrderBook>,
+            additional_capacity: u32,
+        ) -> Result<()> {
+            // 扩容数量必须大于 0。
+            require!(additional_capacity > 0, ErrorCode::InvalidBookCapacity);
+            // 获取订单簿账户加载器。
+            let order_book = &ctx.accounts.order_book;
+            // 读取原容量并计算新容量。
+            let old_capacity = order_book.load()?.capacity;
+            let new_capacity = old_capacity
+                .checked_add(additional_capacity)
+                .ok_or(ErrorCode::MathOverflow)?;
+            // 节点引用的最高位用于区分叶子节点，容量不能超过它。
+            require!(new_capacity < LEAF_FLAG, ErrorCode::InvalidBookCapacity);
+
+            {
+                // 借用完整的账户数据。
+                let mut data = order_book.as_ref().try_borrow_mut_data()?;
+                // 原内部节点数组的位置和长度。
+                let old_inner_start = OrderBook::inner_nodes_offset(old_capacity);
+                let new_inner_start = OrderBook::inner_nodes_offset(new_capacity);
+                let inner_len = old_capacity as usize * size_of::<InnerNode>();
+                // 将内部节点数组移动到新的位置（区域可能重叠，copy_within 可以正确处理）。
+                data.copy_within(
+                    old_inner_start..old_inner_start + inner_len,
+                    new_inner_start,
+                );
+                // 原位置现在属于新增的叶子节点，清零为 Uninitialized 状态。
+                data[old_inner_start..new_inner_start].fill(0);
+            }
 
-    // `set_fees` 指令：管理员设置新的手续费率。
-    pub fn set_fees(ctx: Context<AdminOnly>, maker_fee_bps: u16, taker_fee_bps: u16) -> Result<()> {
-        // 验证手续费率在有效范围内。
-        require!(
-            maker_fee_bps <= 10_000 && taker_fee_bps <= 10_000,
-            ErrorCode::InvalidFee
-        );
-        // 获取 market 账户的可变引用。
-        let market = &mut ctx.accounts.market;
-        // 更新 maker 手续费率。
-        market.maker_fee_bps = maker_fee_bps;
-        // 更新 taker 手续费率。
-        market.taker_fee_bps = taker_fee_bps;
-        // 发出手续费更新事件。
-        emit!(FeesUpdatedEvent {
-            // 市场地址。
-            market: market.key(),
-            // 新的 maker 手续费率。
-            maker_fee_bps,
-            // 新的 taker 手续费率。
-            taker_fee_bps
-        });
-        // 返回成功。
-        Ok(())
+            // 更新容量。新增的节点通过 bump 索引按需分配，无需加入空闲列表。
+            order_book.load_mut()?.capacity = new_capacity;
+
+            // 发出订单簿扩容事件。
+            emit!(OrderBookGrownEvent {
+                // 市场地址。
+                market: ctx.accounts.market.key(),
+                // 订单簿地址。
+                order_book: order_book.key(),
+                // 新的容量。
+                capacity: new_capacity
+            });
+            // 返回成功。
+            Ok(())
+        }
     }
 }
+// 重新导出程序模块及宏生成的 `cpi`、`accounts`、`instruction` 等模块，保持原有的访问路径。
+pub use dex::*;
 
 // --- 账户上下文 (正确地定义在顶层) ---
 
 // `InitializeMarket` 指令的账户上下文。
 #[derive(Accounts)]
+// 订单簿账户的空间取决于指令参数 `book_capacity`。
+#[instruction(
+    maker_fee_bps: i16,
+    taker_fee_bps: u16,
+    tick_size: u64,
+    base_lot_size: u64,
+    min_base_qty: Option<u64>,
+    min_notional: Option<u64>,
+    book_capacity: u32
+)]
 // 定义 InitializeMarket 结构体。
 pub struct InitializeMarket<'info> {
     // `market` 账户: 将被创建和初始化的 PDA 账户。
@@ -1458,7 +3838,10 @@ pub struct InitializeMarket<'info> {
         // `seeds` 定义用于派生 PDA 地址的种子。
         seeds = [b"market", base_mint.key().as_ref(), quote_mint.key().as_ref()],
         // `bump` 告诉 Anchor 存储找到的 bump seed。
-        bump
+        bump,
+        // 验证订单簿的初始容量。该约束在创建 `bids` 和 `asks` 之前执行，
+        // 容量过大时返回明确的错误，而不是让创建账户的 CPI 失败。
+        constraint = OrderBook::is_valid_initial_capacity(book_capacity) @ ErrorCode::InvalidBookCapacity
     )]
     // 账户类型是 Market，使用 Box 避免堆栈溢出。
     pub market: Box<Account<'info, Market>>,
@@ -1505,8 +3888,8 @@ pub struct InitializeMarket<'info> {
     #[account(
         init,
         payer = authority,
-        // 空间大小使用常量定义。
-        space = OrderBook::LEN,
+        // 空间大小由头部和指定容量的节点数组组成。
+        space = OrderBook::space(book_capacity),
         seeds = [b"bids", base_mint.key().as_ref(), quote_mint.key().as_ref()],
         bump
     )]
@@ -1516,11 +3899,20 @@ pub struct InitializeMarket<'info> {
     #[account(
         init,
         payer = authority,
-        space = OrderBook::LEN,
+        space = OrderBook::space(book_capacity),
         seeds = [b"asks", base_mint.key().as_ref(), quote_mint.key().as_ref()],
         bump
     )]
     pub asks: AccountLoader<'info, OrderBook>,
+    // `event_queue` 账户：事件队列，将被创建和初始化。
+    #[account(
+        init,
+        payer = authority,
+        space = EventQueue::LEN,
+        seeds = [b"event_queue", base_mint.key().as_ref(), quote_mint.key().as_ref()],
+        bump
+    )]
+    pub event_queue: AccountLoader<'info, EventQueue>,
     // `authority` 账户：市场的创建者和管理者，必须签名交易。
     // `mut` 表示该账户的数据是可变的（因为租金会从中扣除）。
     #[account(mut)]
@@ -1546,13 +3938,16 @@ pub struct NewLimitOrder<'info> {
     // `asks` 卖单簿账户。
     #[account(mut, seeds = [b"asks", market.base_mint.as_ref(), market.quote_mint.as_ref()], bump)]
     pub asks: AccountLoader<'info, OrderBook>,
+    // `event_queue` 事件队列，写入 maker 一方的成交和取消事件。
+    #[account(mut, address = market.event_queue)]
+    pub event_queue: AccountLoader<'info, EventQueue>,
 
     // `open_orders` 账户：用户的订单状态账户。如果不存在，则自动创建。
     #[account(
         // 如果账户不存在，则使用 payer 和 space 等参数创建它。
         init_if_needed,
         payer = owner,
-        space = 8+ OpenOrders::INIT_SPACE,
+        space = OpenOrders::space(DEFAULT_OPEN_ORDERS_CAPACITY),
         seeds = [b"open_orders", market.key().as_ref(), owner.key().as_ref()],
         bump,
     )]
@@ -1564,6 +3959,25 @@ pub struct NewLimitOrder<'info> {
     // 程序的报价代币金库。
     #[account(mut, address = market.quote_vault, seeds = [b"quote_vault", market.key().as_ref()],bump)]
     pub quote_vault: InterfaceAccount<'info, TokenAccount>,
+    // 手续费金库，接收即时撮合产生的手续费。
+    #[account(mut, address = market.fee_vault)]
+    pub fee_vault: InterfaceAccount<'info, TokenAccount>,
+    // 可选：基础代币手续费金库，买方以基础代币支付 taker 手续费时必须传入。
+    #[account(mut, address = market.base_fee_vault)]
+    pub base_fee_vault: Option<InterfaceAccount<'info, TokenAccount>>,
+    // 可选：市场的手续费等级账户，传入时按用户所在的等级计算手续费。
+    #[account(seeds = [b"fee_tiers", market.key().as_ref()], bump = fee_tiers.bump)]
+    pub fee_tiers: Option<Account<'info, FeeTiers>>,
+    // 可选：用户持有 `fee_tiers.holding_mint` 代币的账户，用于判断持仓门槛。
+    pub holding_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+    // 可选：推荐人的报价代币账户，传入时按 `market.referrer_share_bps` 分得 taker 手续费；
+    // 不能由签名的钱包持有（只能拒绝同一个钱包，无法识别用户的其他钱包）。
+    #[account(
+        mut,
+        constraint = referrer.mint == market.quote_mint @ ErrorCode::InvalidMint,
+        constraint = referrer.owner != owner.key() @ ErrorCode::SelfReferral,
+    )]
+    pub referrer: Option<InterfaceAccount<'info, TokenAccount>>,
     // 用户的基础代币账户。
     #[account(mut)]
     pub user_base_token_account: InterfaceAccount<'info, TokenAccount>,
@@ -1585,6 +3999,346 @@ pub struct NewLimitOrder<'info> {
     pub rent: Sysvar<'info, Rent>,
 }
 
+// 为 `NewLimitOrder` 实现下单指令共用的辅助方法。
+impl<'info> NewLimitOrder<'info> {
+    // 如果用户的 OpenOrders 账户是首次使用（market 地址为默认值），则进行初始化。
+    fn init_open_orders(&mut self, bump: u8) {
+        self.open_orders
+            .init_if_needed(self.market.key(), self.owner.key(), bump);
+    }
+
+    // 订单序列号加一，返回新的唯一订单 ID。
+    fn next_order_id(&mut self) -> Result<u64> {
+        self.market.order_sequence_number = self
+            .market
+            .order_sequence_number
+            .checked_add(1)
+            .ok_or(ErrorCode::MathOverflow)?;
+        Ok(self.market.order_sequence_number)
+    }
+
+    // 传入推荐人账户时 taker 手续费分给推荐人的比例，否则为 0。
+    fn referrer_share_bps(&self) -> u16 {
+        self.referrer
+            .as_ref()
+            .map_or(0, |_| self.market.referrer_share_bps)
+    }
+
+    // 确定用户本次下单适用的手续费率。
+    fn fee_rates(&self, day: u64) -> Result<FeeRates> {
+        FeeRates::for_user(
+            &self.market,
+            self.fee_tiers.as_ref(),
+            self.holding_token_account.as_ref(),
+            self.owner.key(),
+            self.open_orders.rolling_volume(day),
+        )
+    }
+
+    // 以 taker 身份撮合对手方订单簿：买单撮合卖单簿，卖单撮合买单簿。
+    fn match_against_book(&self, taker: &TakerOrder) -> Result<TakerFills> {
+        // 加载对手方订单簿（可变）。
+        let mut opposite_book = match taker.side {
+            Side::Bid => BookSide::load_mut(&self.asks)?,
+            Side::Ask => BookSide::load_mut(&self.bids)?,
+        };
+        // 加载事件队列（可变），maker 一方的结算写入其中。
+        let mut event_queue = self.event_queue.load_mut()?;
+        // 扫单直到价格不再交叉或数量耗尽。
+        match_taker_order(&mut opposite_book, &mut event_queue, taker, &self.market)
+    }
+
+    // 将用户的代币转入程序金库：买单转入报价代币，卖单转入基础代币。
+    fn deposit(&self, side: Side, amount: u64) -> Result<()> {
+        match side {
+            Side::Bid => transfer_from_user(
+                &self.token_program,
+                &self.user_quote_token_account,
+                &self.quote_mint,
+                &self.quote_vault,
+                &self.owner,
+                amount,
+                self.market.quote_decimals,
+            ),
+            Side::Ask => transfer_from_user(
+                &self.token_program,
+                &self.user_base_token_account,
+                &self.base_mint,
+                &self.base_vault,
+                &self.owner,
+                amount,
+                self.market.base_decimals,
+            ),
+        }
+    }
+
+    // 下一个限价单：执行订单后筹集所需资金并收取手续费。
+    fn place_limit_order(&mut self, bump: u8, params: LimitOrderParams) -> Result<()> {
+        let (deposit_amount, fills) = self.execute_limit_order(bump, params)?;
+        self.fund(params.side, deposit_amount)?;
+        self.collect_fees(fills.total_fee, fills.base_fee(), fills.referrer_fee)
+    }
+
+    // 执行一个限价单：校验参数、以 taker 身份撮合、锁定余额并将剩余部分挂入订单簿。
+    // 不进行代币转账，返回需要筹集的资金和撮合产生的手续费，由调用方筹集（批量下单时可以汇总后一次转账）。
+    fn execute_limit_order(
+        &mut self,
+        // OpenOrders PDA 的 bump seed。
+        bump: u8,
+        // 订单参数。
+        params: LimitOrderParams,
+    ) -> Result<(u64, TakerFills)> {
+        let LimitOrderParams {
+            side,
+            price,
+            quantity,
+            order_type,
+            self_trade_behavior,
+            client_order_id,
+        } = params;
+        // 获取 market 账户的引用。
+        let market = &self.market;
+
+        // 验证市场是否暂停。
+        require!(!market.paused, ErrorCode::Paused);
+        // 验证价格和数量是否大于0。
+        require!(price > 0 && quantity > 0, ErrorCode::InvalidOrderInput);
+        // 验证价格是否是 tick_size 的整数倍。
+        require!(
+            price.is_multiple_of(market.tick_size),
+            ErrorCode::InvalidTickSize
+        );
+        // 验证数量是否是 base_lot_size 的整数倍。
+        require!(
+            quantity.is_multiple_of(market.base_lot_size),
+            ErrorCode::InvalidLotSize
+        );
+
+        // PostOnly 订单不能与对手方成交：拒绝，或将价格滑动到不交叉的位置。
+        let price = if matches!(order_type, OrderType::PostOnly | OrderType::PostOnlySlide) {
+            // 获取对手方的最优订单。
+            let best_opposite = match side {
+                Side::Bid => BookSide::load_mut(&self.asks)?.get_best_price_order(),
+                Side::Ask => BookSide::load_mut(&self.bids)?.get_best_price_order(),
+            };
+            match best_opposite {
+                // 对手方存在会成交的订单。
+                Some(best) if price_crosses(side, price, best.price) => {
+                    // PostOnly 直接拒绝。
+                    require!(
+                        order_type == OrderType::PostOnlySlide,
+                        ErrorCode::OrderWouldCross
+                    );
+                    // PostOnlySlide 调整到对手方最优价的前一个 tick。
+                    post_only_slide_price(side, best.price, market.tick_size)?
+                }
+                // 不会成交，保持原价格。
+                _ => price,
+            }
+        } else {
+            price
+        };
+        // 验证最小下单量和最小名义价值，防止粉尘订单占满订单簿。
+        market.validate_order_size(price, quantity)?;
+
+        // 首次使用时初始化 OpenOrders 账户，并分配新的订单 ID。
+        self.init_open_orders(bump);
+        let order_id = self.next_order_id()?;
+        // 按用户当前的交易量和持仓确定手续费率。
+        let day = current_day()?;
+        let rates = self.fee_rates(day)?;
+        // 同一 OpenOrders 账户中的活动订单不能使用重复的客户端订单 ID。
+        require!(
+            client_order_id == 0
+                || self
+                    .open_orders
+                    .find_order_by_client_id(client_order_id)
+                    .is_none(),
+            ErrorCode::DuplicateClientOrderId
+        );
+
+        // --- 1. 作为 taker 撮合对手方订单簿 ---
+        let fills = self.match_against_book(&TakerOrder {
+            side,
+            limit_price: price,
+            max_base_qty: quantity,
+            max_quote_qty: u64::MAX,
+            owner_account: self.open_orders.key(),
+            self_trade_behavior,
+            taker_fee_bps: rates.taker_fee_bps,
+            referrer_share_bps: self.referrer_share_bps(),
+            order_id,
+            owner: self.owner.key(),
+        })?;
+        // 已成交的金额计入用户的交易量。
+        self.open_orders.record_volume(day, fills.quote_qty)?;
+        // FillOrKill 订单必须全部成交，否则整笔交易回滚。
+        require!(
+            order_type != OrderType::FillOrKill || fills.base_qty == quantity,
+            ErrorCode::FillOrKillNotFilled
+        );
+        // 未成交的剩余数量将挂入订单簿；IOC 和 FOK 订单的剩余部分直接取消。
+        // 因自成交而取消的数量同样不会挂单。事件队列已满时剩余部分仍与对手方交叉，挂单会使订单簿交叉，因此也直接取消。
+        if fills.event_queue_full {
+            msg!("Event queue is full, the unfilled remainder is cancelled.");
+        }
+        let resting_qty = if order_type.rests() && !fills.event_queue_full {
+            quantity
+                .checked_sub(fills.consumed_base_qty()?)
+                .ok_or(ErrorCode::MathOverflow)?
+        } else {
+            0
+        };
+
+        // --- 2. 计算需要筹集的资金 ---
+        // 剩余部分挂单需要锁定的资金：买单按自身价格锁定报价代币（含最大 taker 手续费），卖单锁定基础代币。
+        let amount_to_lock = match side {
+            Side::Bid => bid_locked_quote(price, resting_qty, self.market.taker_fee_bps)?,
+            Side::Ask => resting_qty,
+        };
+        // 需要筹集的总额 = 已成交部分的支出 + 挂单锁定的资金。
+        let deposit_amount = fills
+            .taker_cost(side)?
+            .checked_add(amount_to_lock)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        // --- 3. 更新 OpenOrders 账户余额 ---
+        let open_orders = &mut self.open_orders;
+        // 已成交部分的所得进入可用余额。
+        credit_taker_proceeds(open_orders, side, &fills)?;
+        // 挂单锁定的资金计入锁定余额。
+        match side {
+            // 买单锁定报价代币。
+            Side::Bid => {
+                open_orders.quote_token_locked = open_orders
+                    .quote_token_locked
+                    // 使用 checked_add 防止溢出。
+                    .checked_add(amount_to_lock)
+                    .ok_or(ErrorCode::MathOverflow)?;
+            }
+            // 卖单锁定基础代币。
+            Side::Ask => {
+                open_orders.base_token_locked = open_orders
+                    .base_token_locked
+                    // 使用 checked_add 防止溢出。
+                    .checked_add(amount_to_lock)
+                    .ok_or(ErrorCode::MathOverflow)?;
+            }
+        }
+
+        // --- 4. 剩余部分挂入订单簿 ---
+        if resting_qty > 0 {
+            // 创建新的 Order 对象。
+            let new_order = Order {
+                // 订单所有者的 OpenOrders 账户地址。
+                owner_account: open_orders.key(),
+                // 订单所有者的钱包地址。
+                owner: open_orders.owner,
+                // 订单 ID。
+                order_id,
+                // 客户端订单 ID。
+                client_order_id,
+                // 价格。
+                price,
+                // 剩余数量。
+                base_qty: resting_qty,
+                // 自成交处理方式，在 `match_orders` 中该订单较新时使用。
+                self_trade_behavior,
+                // 买单锁定的报价代币及预留手续费所用的费率，成交和取消时按此释放。
+                locked_quote: match side {
+                    Side::Bid => amount_to_lock,
+                    Side::Ask => 0,
+                },
+                locked_fee_bps: self.market.taker_fee_bps,
+                // 下单时所在的手续费等级及其费率，订单之后作为 maker 或在 `match_orders` 中作为 taker 成交时使用。
+                fee_tier: rates.tier,
+                maker_fee_bps: rates.maker_fee_bps,
+                taker_fee_bps: rates.taker_fee_bps,
+            };
+
+            // 根据订单方向，加载对应的订单簿（可变）。
+            let mut order_book = match side {
+                // 如果是买单，加载买单簿。
+                Side::Bid => BookSide::load_mut(&self.bids)?,
+                // 如果是卖单，加载卖单簿。
+                Side::Ask => BookSide::load_mut(&self.asks)?,
+            };
+
+            // 将新订单添加到订单簿。
+            order_book.add_order(new_order)?;
+            // 将订单 ID、客户端订单 ID、价格和方向记录到用户的 OpenOrders 账户。
+            open_orders.add_order(order_id, client_order_id, price, side)?;
+        }
+
+        // 发出下单事件。
+        emit!(OrderPlacedEvent {
+            // 市场地址。
+            market: self.market.key(),
+            // 订单所有者地址。
+            owner: self.owner.key(),
+            // 订单 ID。
+            order_id,
+            // 客户端订单 ID。
+            client_order_id,
+            // 价格。
+            price,
+            // 数量。
+            quantity,
+            // 订单方向。
+            side,
+        });
+
+        // 返回需要筹集的资金和撮合结果（包含手续费）。
+        Ok((deposit_amount, fills))
+    }
+
+    // 为订单筹集资金：优先从 OpenOrders 的可用余额中扣除，不足部分再从用户账户转入，以减少代币转账。
+    fn fund(&mut self, side: Side, amount: u64) -> Result<()> {
+        // 买单使用报价代币，卖单使用基础代币。
+        let free = match side {
+            Side::Bid => &mut self.open_orders.quote_token_free,
+            Side::Ask => &mut self.open_orders.base_token_free,
+        };
+        let from_free = amount.min(*free);
+        *free -= from_free;
+        // 可用余额不足的部分需要 CPI 转账。
+        let to_transfer = amount - from_free;
+        if to_transfer > 0 {
+            self.deposit(side, to_transfer)?;
+        }
+        // 返回成功。
+        Ok(())
+    }
+
+    // 将撮合产生的手续费转入手续费金库：报价代币手续费转入 `fee_vault`，基础代币手续费转入 `base_fee_vault`。
+    fn collect_fees(&self, quote_fee: u64, base_fee: u64, referrer_fee: u64) -> Result<()> {
+        pay_referrer(
+            &self.market,
+            &self.token_program,
+            &self.quote_vault,
+            &self.quote_mint,
+            self.referrer.as_ref(),
+            referrer_fee,
+        )?;
+        collect_fees_to_vault(
+            &self.market,
+            &self.token_program,
+            &self.quote_vault,
+            &self.quote_mint,
+            &self.fee_vault,
+            quote_fee,
+        )?;
+        collect_base_fees_to_vault(
+            &self.market,
+            &self.token_program,
+            &self.base_vault,
+            &self.base_mint,
+            self.base_fee_vault.as_ref(),
+            base_fee,
+        )
+    }
+}
+
 // `CancelLimitOrder` 指令的账户上下文。
 #[derive(Accounts)]
 pub struct CancelLimitOrder<'info> {
@@ -1610,6 +4364,83 @@ pub struct CancelLimitOrder<'info> {
     pub owner: Signer<'info>,
 }
 
+// 为 `CancelLimitOrder` 实现取消指令共用的辅助方法（单个取消与批量取消）。
+impl CancelLimitOrder<'_> {
+    // 从订单簿中移除用户的一个活动订单，解锁资金并发出取消事件。
+    fn cancel_order(&mut self, slot: OpenOrderSlot) -> Result<()> {
+        cancel_open_order(
+            &self.market,
+            &self.bids,
+            &self.asks,
+            &mut self.open_orders,
+            slot,
+        )?;
+        // 返回成功。
+        Ok(())
+    }
+
+    // 批量取消用户的活动订单：每个订单在订单簿中按关键字直接定位，解锁的资金汇总后一次性计入 OpenOrders。
+    // 订单簿中已不存在的订单（已完全成交，成交事件尚未处理）会被跳过，由之后的成交事件将其移出活动订单列表。
+    fn cancel_orders_in_bulk(&mut self, slots: &[OpenOrderSlot]) -> Result<()> {
+        // 同时加载买单簿和卖单簿（可变）。
+        let mut bids = BookSide::load_mut(&self.bids)?;
+        let mut asks = BookSide::load_mut(&self.asks)?;
+        // 汇总所有被取消订单的余额变动。
+        let mut total = QueueEvent::default();
+        // 记录实际被取消的订单 ID。
+        let mut cancelled_ids: Vec<u64> = Vec::with_capacity(slots.len());
+
+        for slot in slots {
+            let side = slot.side();
+            // 根据订单方向选择对应的订单簿。
+            let book = match side {
+                Side::Bid => &mut bids,
+                Side::Ask => &mut asks,
+            };
+            // 订单簿中已不存在该订单时跳过。
+            if book.find_order(slot.price, slot.order_id).is_none() {
+                continue;
+            }
+            // 从订单簿中移除订单。
+            let order = book.remove_order(slot.price, slot.order_id)?;
+            // 验证订单簿中的订单确实属于调用者。
+            require_keys_eq!(
+                order.owner_account,
+                self.open_orders.key(),
+                ErrorCode::Unauthorized
+            );
+            // 计算解锁金额，并累加到汇总中。
+            let event = QueueEvent::out(&order, side, order.base_qty, false)?;
+            total.accumulate(&event)?;
+            cancelled_ids.push(slot.order_id);
+
+            // 发出取消订单事件。
+            emit!(OrderCancelledEvent {
+                // 市场地址。
+                market: self.market.key(),
+                // 订单所有者地址。
+                owner: self.open_orders.owner,
+                // 被取消的订单 ID。
+                order_id: slot.order_id,
+                // 被取消订单的客户端订单 ID。
+                client_order_id: slot.client_order_id,
+            });
+        }
+
+        // 一次性解锁资金。
+        total.apply(&mut self.open_orders)?;
+        // 从活动订单列表中移除被取消的订单（排序后二分查找，避免二次方的比较次数）。
+        cancelled_ids.sort_unstable();
+        self.open_orders
+            .orders
+            .retain(|slot| cancelled_ids.binary_search(&slot.order_id).is_err());
+        // 记录日志。
+        msg!("Cancelled {} orders.", cancelled_ids.len());
+        // 返回成功。
+        Ok(())
+    }
+}
+
 // `MatchOrders` 指令的账户上下文。
 #[derive(Accounts)]
 pub struct MatchOrders<'info> {
@@ -1622,21 +4453,158 @@ pub struct MatchOrders<'info> {
     // 卖单簿，可变。
     #[account(mut, seeds = [b"asks", market.base_mint.as_ref(), market.quote_mint.as_ref()], bump)]
     pub asks: AccountLoader<'info, OrderBook>,
+    // 事件队列，写入 maker 一方的成交和取消事件。
+    #[account(mut, address = market.event_queue)]
+    pub event_queue: AccountLoader<'info, EventQueue>,
     // 基础代币金库。`address` 约束验证传入的账户地址是否正确。
     #[account(mut, address = market.base_vault)]
     pub base_vault: InterfaceAccount<'info, TokenAccount>,
     // 报价代币金库。
     #[account(mut, address = market.quote_vault)]
     pub quote_vault: InterfaceAccount<'info, TokenAccount>,
-    // 手续费金库。
-    #[account(mut, address = market.fee_vault)]
-    pub fee_vault: InterfaceAccount<'info, TokenAccount>,
+    // 手续费金库。
+    #[account(mut, address = market.fee_vault)]
+    pub fee_vault: InterfaceAccount<'info, TokenAccount>,
+    // 基础代币 mint。
+    pub base_mint: InterfaceAccount<'info, Mint>,
+    // 报价代币 mint。
+    pub quote_mint: InterfaceAccount<'info, Mint>,
+    // Token Program。
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+// `Swap` 指令的账户上下文。
+#[derive(Accounts)]
+pub struct Swap<'info> {
+    // 市场账户。
+    #[account(has_one = base_vault, has_one = quote_vault, has_one = fee_vault)]
+    pub market: Account<'info, Market>,
+    // 买单簿，可变。
+    #[account(mut, seeds = [b"bids", market.base_mint.as_ref(), market.quote_mint.as_ref()], bump)]
+    pub bids: AccountLoader<'info, OrderBook>,
+    // 卖单簿，可变。
+    #[account(mut, seeds = [b"asks", market.base_mint.as_ref(), market.quote_mint.as_ref()], bump)]
+    pub asks: AccountLoader<'info, OrderBook>,
+    // 事件队列，写入 maker 一方的成交和取消事件。
+    #[account(mut, address = market.event_queue)]
+    pub event_queue: AccountLoader<'info, EventQueue>,
+    // 基础代币金库。
+    #[account(mut)]
+    pub base_vault: InterfaceAccount<'info, TokenAccount>,
+    // 报价代币金库。
+    #[account(mut)]
+    pub quote_vault: InterfaceAccount<'info, TokenAccount>,
+    // 手续费金库。
+    #[account(mut)]
+    pub fee_vault: InterfaceAccount<'info, TokenAccount>,
+    // 可选：基础代币手续费金库，买方以基础代币支付 taker 手续费时必须传入。
+    #[account(mut, address = market.base_fee_vault)]
+    pub base_fee_vault: Option<InterfaceAccount<'info, TokenAccount>>,
+    // 可选：用户在本市场的 OpenOrders 账户，传入时按其交易量确定手续费等级，成交额也计入其交易量。
+    #[account(mut, seeds = [b"open_orders", market.key().as_ref(), owner.key().as_ref()], bump = open_orders.bump)]
+    pub open_orders: Option<Account<'info, OpenOrders>>,
+    // 可选：市场的手续费等级账户，市场配置了等级时必须传入。
+    #[account(seeds = [b"fee_tiers", market.key().as_ref()], bump = fee_tiers.bump)]
+    pub fee_tiers: Option<Account<'info, FeeTiers>>,
+    // 可选：用户持有 `fee_tiers.holding_mint` 代币的账户，用于判断持仓门槛。
+    pub holding_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+    // 可选：推荐人的报价代币账户，传入时按 `market.referrer_share_bps` 分得 taker 手续费；
+    // 不能由签名的钱包持有（只能拒绝同一个钱包，无法识别用户的其他钱包）。
+    #[account(
+        mut,
+        constraint = referrer.mint == market.quote_mint @ ErrorCode::InvalidMint,
+        constraint = referrer.owner != owner.key() @ ErrorCode::SelfReferral,
+    )]
+    pub referrer: Option<InterfaceAccount<'info, TokenAccount>>,
+    // 用户支付代币的账户，`token::authority` 约束确保由签名者持有。
+    #[account(mut, token::authority = owner)]
+    pub user_source_token_account: InterfaceAccount<'info, TokenAccount>,
+    // 用户接收代币的账户。
+    #[account(mut)]
+    pub user_destination_token_account: InterfaceAccount<'info, TokenAccount>,
+    // 用户账户，必须签名。
+    pub owner: Signer<'info>,
+    // 基础代币 mint。
+    #[account(address = market.base_mint)]
+    pub base_mint: InterfaceAccount<'info, Mint>,
+    // 报价代币 mint。
+    #[account(address = market.quote_mint)]
+    pub quote_mint: InterfaceAccount<'info, Mint>,
+    // Token Program。
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+// 为 `Swap` 实现辅助方法。
+impl Swap<'_> {
+    // 传入推荐人账户时 taker 手续费分给推荐人的比例，否则为 0。
+    fn referrer_share_bps(&self) -> u16 {
+        self.referrer
+            .as_ref()
+            .map_or(0, |_| self.market.referrer_share_bps)
+    }
+
+    // 确定用户本次兑换适用的手续费率，没有传入 OpenOrders 账户时交易量视为 0。
+    fn fee_rates(&self, day: u64) -> Result<FeeRates> {
+        FeeRates::for_user(
+            &self.market,
+            self.fee_tiers.as_ref(),
+            self.holding_token_account.as_ref(),
+            self.owner.key(),
+            self.open_orders
+                .as_ref()
+                .map_or(0, |open_orders| open_orders.rolling_volume(day)),
+        )
+    }
+}
+
+// `ConsumeEvents` 指令的账户上下文。
+#[derive(Accounts)]
+pub struct ConsumeEvents<'info> {
+    // 市场账户。
+    pub market: Account<'info, Market>,
+    // 事件队列，可变。
+    #[account(mut, address = market.event_queue)]
+    pub event_queue: AccountLoader<'info, EventQueue>,
+}
+
+// `Deposit` 指令的账户上下文。
+#[derive(Accounts)]
+pub struct Deposit<'info> {
+    // 市场账户。`has_one` 约束验证金库账户的归属。
+    #[account(has_one = base_vault, has_one = quote_vault)]
+    pub market: Account<'info, Market>,
+    // 用户 OpenOrders 账户。如果不存在，则自动创建。
+    #[account(
+        init_if_needed,
+        payer = owner,
+        space = OpenOrders::space(DEFAULT_OPEN_ORDERS_CAPACITY),
+        seeds = [b"open_orders", market.key().as_ref(), owner.key().as_ref()],
+        bump,
+    )]
+    pub open_orders: Account<'info, OpenOrders>,
+    // 用户账户，必须签名。
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    // 基础代币金库。
+    #[account(mut, address = market.base_vault)]
+    pub base_vault: InterfaceAccount<'info, TokenAccount>,
+    // 报价代币金库。
+    #[account(mut, address = market.quote_vault)]
+    pub quote_vault: InterfaceAccount<'info, TokenAccount>,
+    // 用户的基础代币账户。
+    #[account(mut)]
+    pub user_base_token_account: InterfaceAccount<'info, TokenAccount>,
+    // 用户的报价代币账户。
+    #[account(mut)]
+    pub user_quote_token_account: InterfaceAccount<'info, TokenAccount>,
     // 基础代币 mint。
     pub base_mint: InterfaceAccount<'info, Mint>,
     // 报价代币 mint。
     pub quote_mint: InterfaceAccount<'info, Mint>,
     // Token Program。
     pub token_program: Interface<'info, TokenInterface>,
+    // System Program。
+    pub system_program: Program<'info, System>,
 }
 
 // `SettleFunds` 指令的账户上下文。
@@ -1698,6 +4666,30 @@ pub struct CloseOpenOrders<'info> {
     pub market: Account<'info, Market>,
 }
 
+// `ResizeOpenOrders` 指令的账户上下文。
+#[derive(Accounts)]
+#[instruction(capacity: u16)]
+pub struct ResizeOpenOrders<'info> {
+    // `open_orders` 账户：按新容量重新分配空间。
+    #[account(
+        mut,
+        seeds = [b"open_orders", market.key().as_ref(), owner.key().as_ref()],
+        bump = open_orders.bump,
+        has_one = owner,
+        realloc = OpenOrders::space(capacity),
+        realloc::payer = owner,
+        realloc::zero = false,
+    )]
+    pub open_orders: Account<'info, OpenOrders>,
+    // 用户账户，必须签名，并支付或收回租金差额。
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    // 市场账户。
+    pub market: Account<'info, Market>,
+    // System Program。
+    pub system_program: Program<'info, System>,
+}
+
 // 管理员指令的通用账户上下文。
 #[derive(Accounts)]
 pub struct AdminOnly<'info> {
@@ -1708,6 +4700,192 @@ pub struct AdminOnly<'info> {
     pub authority: Signer<'info>,
 }
 
+// `AcceptAuthority` 指令的账户上下文。
+#[derive(Accounts)]
+pub struct AcceptAuthority<'info> {
+    // 验证签名者是市场记录的待接受管理员。
+    #[account(
+        mut,
+        constraint = market.pending_authority == new_authority.key() @ ErrorCode::Unauthorized,
+    )]
+    pub market: Account<'info, Market>,
+    // 新管理员账户，必须签名。
+    pub new_authority: Signer<'info>,
+}
+
+// `WithdrawFees` 指令的账户上下文。
+#[derive(Accounts)]
+pub struct WithdrawFees<'info> {
+    // `has_one = authority` 验证签名者是市场管理员。
+    #[account(has_one = authority)]
+    pub market: Account<'info, Market>,
+    // 手续费金库：报价代币的 `fee_vault` 或基础代币的 `base_fee_vault`。
+    #[account(
+        mut,
+        constraint = fee_vault.key() == market.fee_vault || fee_vault.key() == market.base_fee_vault @ ErrorCode::InvalidVault,
+    )]
+    pub fee_vault: InterfaceAccount<'info, TokenAccount>,
+    // 接收手续费的代币账户。
+    #[account(mut)]
+    pub destination: InterfaceAccount<'info, TokenAccount>,
+    // 手续费代币的 mint。
+    #[account(address = fee_vault.mint)]
+    pub mint: InterfaceAccount<'info, Mint>,
+    // 管理员账户，必须签名。
+    pub authority: Signer<'info>,
+    // Token Program。
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+// `SetTakerFeeMode` 指令的账户上下文。
+#[derive(Accounts)]
+pub struct SetTakerFeeMode<'info> {
+    // `has_one = authority` 验证签名者是市场管理员。
+    #[account(mut, has_one = authority, has_one = base_mint)]
+    pub market: Account<'info, Market>,
+    // 基础代币手续费金库，首次使用时创建。
+    #[account(
+        init_if_needed,
+        payer = authority,
+        token::mint = base_mint,
+        token::authority = market,
+        seeds = [b"base_fee_vault", market.key().as_ref()],
+        bump
+    )]
+    pub base_fee_vault: InterfaceAccount<'info, TokenAccount>,
+    // 可选：手续费等级账户，市场配置了手续费等级时切换为以收到的资产收费必须传入。
+    #[account(seeds = [b"fee_tiers", market.key().as_ref()], bump = fee_tiers.bump)]
+    pub fee_tiers: Option<Account<'info, FeeTiers>>,
+    // 基础代币 mint。
+    pub base_mint: InterfaceAccount<'info, Mint>,
+    // 管理员账户，必须签名，并支付金库的租金。
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    // Token Program。
+    pub token_program: Interface<'info, TokenInterface>,
+    // System Program。
+    pub system_program: Program<'info, System>,
+}
+
+// `SetFeeTiers` 指令的账户上下文。
+#[derive(Accounts)]
+pub struct SetFeeTiers<'info> {
+    // `has_one = authority` 验证签名者是市场管理员。
+    #[account(mut, has_one = authority)]
+    pub market: Account<'info, Market>,
+    // 手续费等级账户，首次使用时创建。
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = 8 + FeeTiers::INIT_SPACE,
+        seeds = [b"fee_tiers", market.key().as_ref()],
+        bump
+    )]
+    pub fee_tiers: Account<'info, FeeTiers>,
+    // 管理员账户，必须签名，并支付账户的租金。
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    // System Program。
+    pub system_program: Program<'info, System>,
+}
+
+// `SetFeeDistribution` 指令的账户上下文。
+#[derive(Accounts)]
+pub struct SetFeeDistribution<'info> {
+    // `has_one = authority` 验证签名者是市场管理员。
+    #[account(mut, has_one = authority)]
+    pub market: Account<'info, Market>,
+    // 协议金库的代币账户，必须是报价代币账户。
+    #[account(constraint = treasury.mint == market.quote_mint @ ErrorCode::InvalidMint)]
+    pub treasury: InterfaceAccount<'info, TokenAccount>,
+    // 质押者奖励的代币账户，必须是报价代币账户（不分给质押者时可以与协议金库相同）。
+    #[account(constraint = stakers.mint == market.quote_mint @ ErrorCode::InvalidMint)]
+    pub stakers: InterfaceAccount<'info, TokenAccount>,
+    // 管理员账户，必须签名。
+    pub authority: Signer<'info>,
+}
+
+// `DistributeFees` 指令的账户上下文。
+#[derive(Accounts)]
+pub struct DistributeFees<'info> {
+    // 市场账户。
+    #[account(has_one = fee_vault, has_one = quote_mint)]
+    pub market: Account<'info, Market>,
+    // 手续费金库。
+    #[account(mut)]
+    pub fee_vault: InterfaceAccount<'info, TokenAccount>,
+    // 协议金库的代币账户，必须是市场配置的地址。
+    #[account(mut, address = market.fee_treasury)]
+    pub treasury: InterfaceAccount<'info, TokenAccount>,
+    // 质押者奖励的代币账户，必须是市场配置的地址。
+    #[account(mut, address = market.fee_stakers)]
+    pub stakers: InterfaceAccount<'info, TokenAccount>,
+    // 报价代币 mint。
+    pub quote_mint: InterfaceAccount<'info, Mint>,
+    // Token Program。
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+// `DistributeBaseFees` 指令的账户上下文。
+#[derive(Accounts)]
+pub struct DistributeBaseFees<'info> {
+    // 市场账户。
+    #[account(has_one = base_mint)]
+    pub market: Account<'info, Market>,
+    // 基础代币手续费金库。
+    #[account(mut, address = market.base_fee_vault @ ErrorCode::InvalidVault)]
+    pub base_fee_vault: InterfaceAccount<'info, TokenAccount>,
+    // 已配置的协议金库报价代币账户，用于确定基础代币的接收者。
+    #[account(address = market.fee_treasury)]
+    pub treasury: InterfaceAccount<'info, TokenAccount>,
+    // 已配置的质押者报价代币账户，用于确定基础代币的接收者。
+    #[account(address = market.fee_stakers)]
+    pub stakers: InterfaceAccount<'info, TokenAccount>,
+    // 协议金库的基础代币账户，必须与 `treasury` 属于同一所有者。
+    #[account(
+        mut,
+        constraint = base_treasury.mint == market.base_mint @ ErrorCode::InvalidMint,
+        constraint = base_treasury.owner == treasury.owner @ ErrorCode::Unauthorized,
+    )]
+    pub base_treasury: InterfaceAccount<'info, TokenAccount>,
+    // 质押者的基础代币账户，必须与 `stakers` 属于同一所有者。
+    #[account(
+        mut,
+        constraint = base_stakers.mint == market.base_mint @ ErrorCode::InvalidMint,
+        constraint = base_stakers.owner == stakers.owner @ ErrorCode::Unauthorized,
+    )]
+    pub base_stakers: InterfaceAccount<'info, TokenAccount>,
+    // 基础代币 mint。
+    pub base_mint: InterfaceAccount<'info, Mint>,
+    // Token Program。
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+// `GrowOrderBook` 指令的账户上下文。
+#[derive(Accounts)]
+#[instruction(additional_capacity: u32)]
+pub struct GrowOrderBook<'info> {
+    // `has_one = authority` 验证签名者是市场管理员。
+    #[account(has_one = authority)]
+    pub market: Account<'info, Market>,
+    // 需要扩容的订单簿，必须是该市场的买单簿或卖单簿。
+    #[account(
+        mut,
+        constraint = order_book.key() == market.bids || order_book.key() == market.asks @ ErrorCode::InvalidMarketParams,
+        // 每增加一个容量需要一个叶子节点和一个内部节点的空间。
+        realloc = order_book.as_ref().data_len()
+            + additional_capacity as usize * (size_of::<OrderNode>() + size_of::<InnerNode>()),
+        realloc::payer = authority,
+        realloc::zero = true,
+    )]
+    pub order_book: AccountLoader<'info, OrderBook>,
+    // 管理员账户，必须签名，并支付新增空间的租金。
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    // Solana 系统程序，用于转移租金。
+    pub system_program: Program<'info, System>,
+}
+
 // --- 枚举、事件、错误 ---
 
 // 订单方向枚举。
@@ -1718,13 +4896,73 @@ pub enum Side {
     // 卖单
     Ask,
 }
-// 订单类型枚举（在此示例中未使用，但通常会包含）。
+// 为 Side 实现方法。
+impl Side {
+    // 返回对手方方向。
+    pub fn opposite(self) -> Side {
+        match self {
+            Side::Bid => Side::Ask,
+            Side::Ask => Side::Bid,
+        }
+    }
+}
+// 订单类型枚举，决定限价单在下单时如何与对手方订单簿交互。
 #[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
 pub enum OrderType {
-    // 限价单
+    // 限价单：先撮合交叉部分，剩余部分挂单
     Limit,
-    // 只做 Maker 的限价单
+    // 只做 Maker 的限价单：如果会与对手方成交则拒绝
     PostOnly,
+    // 只做 Maker 的限价单：如果会与对手方成交，则将价格调整到对手方最优价的前一个 tick
+    PostOnlySlide,
+    // 立即成交否则取消：能成交的部分立即成交，剩余部分不挂单
+    ImmediateOrCancel,
+    // 全部成交否则取消：必须全部立即成交，否则整笔交易失败
+    FillOrKill,
+}
+
+// 为 OrderType 实现方法。
+impl OrderType {
+    // 未成交的剩余部分是否挂入订单簿。
+    pub fn rests(self) -> bool {
+        !matches!(self, OrderType::ImmediateOrCancel | OrderType::FillOrKill)
+    }
+}
+
+// taker 手续费的收取币种。
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Default, InitSpace)]
+pub enum TakerFeeMode {
+    // 买卖双方都以报价代币支付
+    #[default]
+    Quote,
+    // 以收到的资产支付：买方以基础代币支付，卖方以报价代币支付
+    ReceivedAsset,
+}
+
+// 市价单的数量计量方式。
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum MarketOrderMode {
+    // 按基础代币数量成交
+    BaseQuantity,
+    // 按报价代币预算成交
+    QuoteBudget,
+}
+
+// 限价单参数，供替换订单等需要完整下单参数的指令使用。
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct LimitOrderParams {
+    // 订单方向（买或卖）
+    pub side: Side,
+    // 价格
+    pub price: u64,
+    // 数量
+    pub quantity: u64,
+    // 订单类型
+    pub order_type: OrderType,
+    // 自成交处理方式
+    pub self_trade_behavior: SelfTradeBehavior,
+    // 客户端订单 ID，0 表示不指定
+    pub client_order_id: u64,
 }
 
 // 市场初始化事件。
@@ -1736,8 +4974,8 @@ pub struct MarketInitializedEvent {
     pub base_mint: Pubkey,
     // 报价代币 mint。
     pub quote_mint: Pubkey,
-    // maker 手续费率。
-    pub maker_fee_bps: u16,
+    // maker 手续费率（负数为返佣）。
+    pub maker_fee_bps: i16,
     // taker 手续费率。
     pub taker_fee_bps: u16,
     // 价格精度。
@@ -1746,21 +4984,39 @@ pub struct MarketInitializedEvent {
     pub base_lot_size: u64,
 }
 
-// 交易成交事件。
+// 交易成交事件，每一笔 maker 与 taker 的成交发出一次。
 #[event]
 pub struct TradeEvent {
     // 市场地址。
     pub market: Pubkey,
-    // taker 地址。
-    pub taker: Pubkey,
-    // maker (买方) 地址。
-    pub maker_bid: Pubkey,
-    // maker (卖方) 地址。
-    pub maker_ask: Pubkey,
-    // 成交价格。
+    // maker 订单 ID。
+    pub maker_order_id: u64,
+    // taker 订单 ID（兑换为 0）。
+    pub taker_order_id: u64,
+    // maker 的 OpenOrders 账户地址（maker 一方在事件队列中结算，撮合时不加载其账户）。
+    pub maker_open_orders: Pubkey,
+    // maker 钱包地址（取自挂单记录的所有者）。
+    pub maker_owner: Pubkey,
+    // taker 钱包地址。
+    pub taker_owner: Pubkey,
+    // taker 的 OpenOrders 账户地址（兑换时为按钱包推导的地址，该账户可能并不存在）。
+    pub taker_open_orders: Pubkey,
+    // taker 的方向。
+    pub taker_side: Side,
+    // 成交价格（maker 的挂单价格）。
     pub price: u64,
-    // 成交数量。
-    pub quantity: u64,
+    // 成交的基础代币数量。
+    pub base_qty: u64,
+    // 成交的报价代币金额（不含手续费）。
+    pub quote_qty: u64,
+    // maker 支付的手续费，负数为 maker 获得的返佣。
+    pub maker_fee: i64,
+    // taker 支付的手续费。
+    pub taker_fee: u64,
+    // taker 手续费是否以基础代币支付（否则为报价代币）。
+    pub taker_fee_in_base: bool,
+    // taker 手续费中分给推荐人的报价代币。
+    pub referrer_fee: u64,
 }
 
 // 下单事件。
@@ -1772,6 +5028,8 @@ pub struct OrderPlacedEvent {
     pub owner: Pubkey,
     // 订单 ID。
     pub order_id: u64,
+    // 客户端订单 ID。
+    pub client_order_id: u64,
     // 价格。
     pub price: u64,
     // 数量。
@@ -1780,6 +5038,46 @@ pub struct OrderPlacedEvent {
     pub side: Side,
 }
 
+// 市价单成交事件。
+#[event]
+pub struct MarketOrderFilledEvent {
+    // 市场地址。
+    pub market: Pubkey,
+    // 订单所有者地址。
+    pub owner: Pubkey,
+    // 订单 ID。
+    pub order_id: u64,
+    // 订单方向。
+    pub side: Side,
+    // 成交的基础代币数量。
+    pub base_qty: u64,
+    // 成交的报价代币金额（不含手续费）。
+    pub quote_qty: u64,
+    // taker 手续费。
+    pub taker_fee: u64,
+    // taker 手续费是否以基础代币支付（否则为报价代币）。
+    pub taker_fee_in_base: bool,
+}
+
+// 兑换事件。
+#[event]
+pub struct SwapEvent {
+    // 市场地址。
+    pub market: Pubkey,
+    // 用户地址。
+    pub owner: Pubkey,
+    // 兑换方向（买入或卖出基础代币）。
+    pub side: Side,
+    // 实际支付的输入代币数量。
+    pub amount_in: u64,
+    // 实际得到的输出代币数量。
+    pub amount_out: u64,
+    // taker 手续费。
+    pub taker_fee: u64,
+    // taker 手续费是否以基础代币支付（否则为报价代币）。
+    pub taker_fee_in_base: bool,
+}
+
 // 取消订单事件。
 #[event]
 pub struct OrderCancelledEvent {
@@ -1789,17 +5087,130 @@ pub struct OrderCancelledEvent {
     pub owner: Pubkey,
     // 被取消的订单 ID。
     pub order_id: u64,
+    // 被取消订单的客户端订单 ID。
+    pub client_order_id: u64,
+}
+
+// 修改订单事件。
+#[event]
+pub struct OrderAmendedEvent {
+    // 市场地址。
+    pub market: Pubkey,
+    // 订单所有者地址。
+    pub owner: Pubkey,
+    // 被修改的订单 ID。
+    pub order_id: u64,
+    // 被修改订单的客户端订单 ID。
+    pub client_order_id: u64,
+    // 修改后的剩余数量。
+    pub base_qty: u64,
 }
 
-// 手续费收取事件。
+// 存款事件。
+#[event]
+pub struct FundsDepositedEvent {
+    // 市场地址。
+    pub market: Pubkey,
+    // 用户地址。
+    pub owner: Pubkey,
+    // 存入的基础代币数量。
+    pub base_amount: u64,
+    // 存入的报价代币数量。
+    pub quote_amount: u64,
+}
+
+// 提款事件。
+#[event]
+pub struct FundsWithdrawnEvent {
+    // 市场地址。
+    pub market: Pubkey,
+    // 用户地址。
+    pub owner: Pubkey,
+    // 提取的代币（买方向为报价代币，卖方向为基础代币）。
+    pub side: Side,
+    // 提取的数量。
+    pub amount: u64,
+}
+
+// 手续费收取事件，每次调用汇总发出一次。
 #[event]
 pub struct FeeCollectedEvent {
     // 市场地址。
     pub market: Pubkey,
+    // 手续费代币的 mint。
+    pub mint: Pubkey,
     // 手续费金额。
     pub amount: u64,
 }
 
+// taker 手续费收取方式更新事件。
+#[event]
+pub struct TakerFeeModeUpdatedEvent {
+    // 市场地址。
+    pub market: Pubkey,
+    // 新的收费方式。
+    pub mode: TakerFeeMode,
+    // 基础代币手续费金库地址。
+    pub base_fee_vault: Pubkey,
+}
+
+// 推荐人分成比例更新事件。
+#[event]
+pub struct ReferrerShareUpdatedEvent {
+    // 市场地址。
+    pub market: Pubkey,
+    // 新的推荐人分成比例（基点）。
+    pub referrer_share_bps: u16,
+}
+
+// 手续费等级更新事件。
+#[event]
+pub struct FeeTiersUpdatedEvent {
+    // 市场地址。
+    pub market: Pubkey,
+    // 用于持仓门槛的代币 mint。
+    pub holding_mint: Pubkey,
+    // 新的手续费等级。
+    pub tiers: Vec<FeeTier>,
+}
+
+// 手续费提取事件。
+#[event]
+pub struct FeesWithdrawnEvent {
+    // 市场地址。
+    pub market: Pubkey,
+    // 目标代币账户。
+    pub destination: Pubkey,
+    // 提取的数量。
+    pub amount: u64,
+}
+
+// 手续费分配配置更新事件。
+#[event]
+pub struct FeeDistributionUpdatedEvent {
+    // 市场地址。
+    pub market: Pubkey,
+    // 协议金库的代币账户。
+    pub treasury: Pubkey,
+    // 质押者奖励的代币账户。
+    pub stakers: Pubkey,
+    // 分给质押者的比例（基点）。
+    pub stakers_share_bps: u16,
+}
+
+// 手续费分配事件。
+#[event]
+pub struct FeesDistributedEvent {
+    // 市场地址。
+    pub market: Pubkey,
+    // 手续费代币的 mint。
+    pub mint: Pubkey,
+    // 分给协议金库的数量。
+    pub treasury_amount: u64,
+    // 分给质押者的数量。
+    pub stakers_amount: u64,
+}
+
 // 市场暂停/恢复事件。
 #[event]
 pub struct PauseEvent {
@@ -1809,17 +5220,85 @@ pub struct PauseEvent {
     pub paused: bool,
 }
 
+// 管理员提议事件。
+#[event]
+pub struct AuthorityProposedEvent {
+    // 市场地址。
+    pub market: Pubkey,
+    // 当前管理员地址。
+    pub authority: Pubkey,
+    // 提议的新管理员地址（默认值表示撤销提议）。
+    pub pending_authority: Pubkey,
+}
+
+// 管理员转移事件。
+#[event]
+pub struct AuthorityTransferredEvent {
+    // 市场地址。
+    pub market: Pubkey,
+    // 原管理员地址。
+    pub old_authority: Pubkey,
+    // 新管理员地址。
+    pub new_authority: Pubkey,
+}
+
+// 管理员放弃事件。
+#[event]
+pub struct AuthorityRenouncedEvent {
+    // 市场地址。
+    pub market: Pubkey,
+    // 原管理员地址。
+    pub old_authority: Pubkey,
+}
+
+// 订单簿扩容事件。
+#[event]
+pub struct OrderBookGrownEvent {
+    // 市场地址。
+    pub market: Pubkey,
+    // 订单簿地址。
+    pub order_book: Pubkey,
+    // 扩容后的容量。
+    pub capacity: u32,
+}
+
 // 手续费更新事件。
 #[event]
 pub struct FeesUpdatedEvent {
     // 市场地址。
     pub market: Pubkey,
-    // 新的 maker 手续费率。
-    pub maker_fee_bps: u16,
+    // 新的 maker 手续费率（负数为返佣）。
+    pub maker_fee_bps: i16,
     // 新的 taker 手续费率。
     pub taker_fee_bps: u16,
 }
 
+// 市场参数更新事件。
+#[event]
+pub struct MarketParamsUpdatedEvent {
+    // 市场地址。
+    pub market: Pubkey,
+    // 原有的价格精度。
+    pub old_tick_size: u64,
+    // 新的价格精度。
+    pub tick_size: u64,
+    // 原有的数量精度。
+    pub old_base_lot_size: u64,
+    // 新的数量精度。
+    pub base_lot_size: u64,
+}
+
+// 下单限制更新事件。
+#[event]
+pub struct OrderSizeLimitsUpdatedEvent {
+    // 市场地址。
+    pub market: Pubkey,
+    // 新的最小基础代币下单量。
+    pub min_base_qty: u64,
+    // 新的最小名义价值。
+    pub min_notional: u64,
+}
+
 // 自定义错误码。
 #[error_code]
 pub enum ErrorCode {
@@ -1889,4 +5368,58 @@ pub enum ErrorCode {
     // 无法关闭仍持有资金或有未结订单的 OpenOrders 账户。
     #[msg("Cannot close an OpenOrders account that still holds funds or has open orders.")]
     OpenOrdersAccountNotEmpty,
+    // FillOrKill 订单无法全部成交。
+    #[msg("Fill-or-kill order could not be completely filled.")]
+    FillOrKillNotFilled,
+    // 兑换得到的数量低于用户要求的最小值。
+    #[msg("Swap output is below the minimum amount out.")]
+    SlippageExceeded,
+    // 事件队列已满，需要先调用 consume_events。
+    #[msg("Event queue is full; consume events first.")]
+    EventQueueFull,
+    // 事件队列为空。
+    #[msg("Event queue is empty.")]
+    EventQueueEmpty,
+    // 订单簿中已存在相同关键字（价格和订单 ID）的订单。
+    #[msg("An order with the same price and order ID already exists in the book.")]
+    DuplicateOrderKey,
+    // 订单簿容量无效。
+    #[msg("Invalid order book capacity.")]
+    InvalidBookCapacity,
+    // OpenOrders 容量无效：超出上限或少于当前的活动订单数量。
+    #[msg("Invalid OpenOrders capacity.")]
+    InvalidOpenOrdersCapacity,
+    // 客户端订单 ID 已被同一 OpenOrders 账户中的活动订单使用。
+    #[msg("Client order ID is already used by an open order.")]
+    DuplicateClientOrderId,
+    // 无效的数量。
+    #[msg("Amount must be greater than zero.")]
+    InvalidAmount,
+    // OpenOrders 账户的可用余额不足。
+    #[msg("Insufficient free balance in the OpenOrders account.")]
+    InsufficientFreeBalance,
+    // 手续费金库余额不足。
+    #[msg("Insufficient balance in the fee vault.")]
+    InsufficientFeeBalance,
+    // 尚未配置手续费分配。
+    #[msg("Fee distribution is not configured.")]
+    FeeDistributionNotConfigured,
+    // 买方以基础代币支付手续费时必须传入基础代币手续费金库。
+    #[msg("Base fee vault account is required.")]
+    BaseFeeVaultRequired,
+    // maker 返佣率超过了 taker 费率。
+    #[msg("Maker rebate cannot exceed the taker fee.")]
+    MakerRebateExceedsTakerFee,
+    // maker 返佣要求 taker 手续费以报价代币收取。
+    #[msg("Maker rebates require taker fees charged in quote.")]
+    RebateRequiresQuoteTakerFee,
+    // 手续费等级数量超过上限。
+    #[msg("Too many fee tiers.")]
+    TooManyFeeTiers,
+    // 市场配置了手续费等级，但没有传入手续费等级账户。
+    #[msg("Fee tiers account is required.")]
+    FeeTiersRequired,
+    // 推荐人账户属于下单用户自己。
+    #[msg("Cannot refer yourself.")]
+    SelfReferral,
 }
@@ -42,6 +42,9 @@ describe('order_book_dex', () => {
   let baseVaultPda: PublicKey // 基础代币金库 PDA
   let quoteVaultPda: PublicKey // 报价代币金库 PDA
   let feeVaultPda: PublicKey // 费用金库 PDA
+  let eventQueuePda: PublicKey // 事件队列 PDA
+  let baseFeeVaultPda: PublicKey // 基础代币手续费金库 PDA
+  let feeTiersPda: PublicKey // 手续费等级账户 PDA
   let openOrdersA: PublicKey // 用户 A 的开放订单账户 PDA
   let openOrdersB: PublicKey // 用户 B 的开放订单账户 PDA
 
@@ -50,6 +53,95 @@ describe('order_book_dex', () => {
   const takerFeeBps = new BN(40) // Taker 费用：0.4%
   const tickSize = new BN(100) // 价格最小单位：1.00
   const baseLotSize = new BN(1_000_000) // 数量最小单位：0.001（假设 9 位小数）
+  const bookCapacity = 64 // 每个订单簿的初始容量
+
+  // 处理事件队列，将成交和取消结果结算到 maker 的开放订单账户
+  const consumeEvents = async (openOrdersAccounts: PublicKey[]) => {
+    await program.methods
+      .consumeEvents(64) // 最多处理 64 个事件
+      .accounts({
+        market: marketPda, // 市场账户
+        eventQueue: eventQueuePda, // 事件队列
+      })
+      .remainingAccounts(openOrdersAccounts.map((pubkey) => ({ pubkey, isSigner: false, isWritable: true })))
+      .rpc() // 发送交易
+  }
+
+  // 辅助函数：读取订单簿中的最优订单（买单簿价格最高、卖单簿价格最低，同价格时订单 ID 最小者优先）
+  // 节点数组紧跟在账户头部之后，不属于 IDL 中的 OrderBook 结构体，因此按链上布局直接解析账户数据
+  const fetchBestOrder = async (bookPda: PublicKey, isBids: boolean) => {
+    const book = await program.account.orderBook.fetch(bookPda) // 获取订单簿头部
+    const data = (await connection.getAccountInfo(bookPda))!.data // 获取原始账户数据
+    const headerLen = 8 + 64 // discriminator + OrderBook 头部
+    const nodeLen = 120 // 每个 OrderNode 叶子节点的大小
+    const orders = []
+    for (let i = 0; i < book.leafBumpIndex; i++) {
+      const offset = headerLen + i * nodeLen // 叶子节点的偏移
+      if (data[offset + 116] !== 2) continue // 只保留存放订单的叶子节点（NodeTag::OrderNode）
+      orders.push({
+        ownerAccount: new PublicKey(data.subarray(offset, offset + 32)), // 所有者的开放订单账户
+        owner: new PublicKey(data.subarray(offset + 32, offset + 64)), // 所有者的钱包
+        orderId: new BN(data.subarray(offset + 64, offset + 72), 'le'), // 订单 ID
+        clientOrderId: new BN(data.subarray(offset + 72, offset + 80), 'le'), // 客户端订单 ID
+        price: new BN(data.subarray(offset + 80, offset + 88), 'le'), // 价格
+        baseQty: new BN(data.subarray(offset + 88, offset + 96), 'le'), // 剩余数量
+      })
+    }
+    return orders.sort((a, b) => {
+      const byPrice = isBids ? b.price.cmp(a.price) : a.price.cmp(b.price) // 价格优先
+      return byPrice !== 0 ? byPrice : a.orderId.cmp(b.orderId) // 时间优先
+    })[0]
+  }
+
+  // 辅助函数：读取 Token 2022 代币账户的余额
+  const balance = async (account: PublicKey) => (await getAccount(connection, account, undefined, TOKEN_2022_PROGRAM_ID)).amount
+
+  // 辅助函数：下单指令的必需账户，可选账户（基础代币手续费金库、手续费等级、推荐人等）由调用方按需追加
+  const accountsFor = (user: Keypair, openOrders: PublicKey, baseAccount: PublicKey, quoteAccount: PublicKey) => ({
+    market: marketPda, // 市场账户
+    bids: bidsPda, // 买单簿
+    asks: asksPda, // 卖单簿
+    eventQueue: eventQueuePda, // 事件队列
+    openOrders, // 开放订单账户
+    baseVault: baseVaultPda, // 基础代币金库
+    quoteVault: quoteVaultPda, // 报价代币金库
+    feeVault: feeVaultPda, // 费用金库
+    userBaseTokenAccount: baseAccount, // 用户的基础代币账户
+    userQuoteTokenAccount: quoteAccount, // 用户的报价代币账户
+    owner: user.publicKey, // 用户公钥
+    baseMint: baseMint, // 基础代币 Mint
+    quoteMint: quoteMint, // 报价代币 Mint
+    tokenProgram: TOKEN_2022_PROGRAM_ID, // Token 2022 程序
+    systemProgram: SystemProgram.programId, // 系统程序
+    rent: anchor.web3.SYSVAR_RENT_PUBKEY, // 租金账户
+  })
+
+  // 辅助函数：管理员设置手续费率
+  const setFees = (newMakerFeeBps: number, newTakerFeeBps: number) =>
+    program.methods
+      .setFees(newMakerFeeBps, newTakerFeeBps)
+      .accounts({
+        market: marketPda, // 市场账户
+        authority: authority.publicKey, // 管理员公钥
+      })
+      .signers([authority]) // 管理员签名
+      .rpc()
+
+  // 辅助函数：管理员设置 taker 手续费的收取币种，市场配置了手续费等级时需要传入手续费等级账户
+  const setTakerFeeMode = (mode: any, feeTiers: PublicKey | null = null) =>
+    program.methods
+      .setTakerFeeMode(mode)
+      .accounts({
+        market: marketPda, // 市场账户
+        baseFeeVault: baseFeeVaultPda, // 基础代币手续费金库
+        feeTiers, // 可选：手续费等级账户
+        baseMint: baseMint, // 基础代币 Mint
+        authority: authority.publicKey, // 管理员公钥
+        tokenProgram: TOKEN_2022_PROGRAM_ID, // Token 2022 程序
+        systemProgram: SystemProgram.programId, // 系统程序
+      })
+      .signers([authority]) // 管理员签名
+      .rpc()
 
   // 在所有测试前执行的初始化设置
   beforeAll(async () => {
@@ -111,6 +203,10 @@ describe('order_book_dex', () => {
       [Buffer.from('fee_vault'), baseMint.toBuffer(), quoteMint.toBuffer()],
       program.programId,
     ) // 费用金库 PDA
+    ;[eventQueuePda] = PublicKey.findProgramAddressSync(
+      [Buffer.from('event_queue'), baseMint.toBuffer(), quoteMint.toBuffer()],
+      program.programId,
+    ) // 事件队列 PDA
     ;[openOrdersA] = PublicKey.findProgramAddressSync(
       [Buffer.from('open_orders'), marketPda.toBuffer(), userA.publicKey.toBuffer()],
       program.programId,
@@ -119,6 +215,8 @@ describe('order_book_dex', () => {
       [Buffer.from('open_orders'), marketPda.toBuffer(), userB.publicKey.toBuffer()],
       program.programId,
     ) // 用户 B 的开放订单 PDA
+    ;[baseFeeVaultPda] = PublicKey.findProgramAddressSync([Buffer.from('base_fee_vault'), marketPda.toBuffer()], program.programId) // 基础代币手续费金库 PDA
+    ;[feeTiersPda] = PublicKey.findProgramAddressSync([Buffer.from('fee_tiers'), marketPda.toBuffer()], program.programId) // 手续费等级账户 PDA
 
     // 为用户 A 创建代币账户并铸造代币
     userABaseTokenAccount = await createAssociatedTokenAccount(
@@ -181,33 +279,43 @@ describe('order_book_dex', () => {
 
   // 测试用例：初始化市场
   it('✅ Initializes the market', async () => {
+    // 使用指定的初始容量初始化市场
+    const initializeMarket = (capacity: number) =>
+      program.methods
+        .initializeMarket(
+          makerFeeBps.toNumber(), // Maker 费用（0.2%）
+          takerFeeBps.toNumber(), // Taker 费用（0.4%）
+          tickSize, // 价格最小单位
+          baseLotSize, // 数量最小单位
+          null, // 最小基础数量（未设置）
+          null, // 最小名义价值（未设置）
+          capacity, // 订单簿初始容量
+        )
+        .accounts({
+          market: marketPda, // 市场账户
+          baseMint: baseMint, // 基础代币 Mint
+          quoteMint: quoteMint, // 报价代币 Mint
+          baseVault: baseVaultPda, // 基础代币金库
+          quoteVault: quoteVaultPda, // 报价代币金库
+          feeVault: feeVaultPda, // 费用金库
+          bids: bidsPda, // 买单簿
+          asks: asksPda, // 卖单簿
+          eventQueue: eventQueuePda, // 事件队列
+          authority: authority.publicKey, // 管理员公钥
+          systemProgram: SystemProgram.programId, // 系统程序
+          tokenProgram: TOKEN_2022_PROGRAM_ID, // Token 2022 程序
+          associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID, // 关联代币程序
+          rent: anchor.web3.SYSVAR_RENT_PUBKEY, // 租金账户
+        })
+        .signers([authority]) // 管理员签名
+        .rpc() // 发送交易
+
+    // 初始容量超过创建账户的 10240 字节上限（约 66 个订单）时被拒绝
+    await expect(initializeMarket(67)).rejects.toThrow('Invalid order book capacity.')
+    // 初始容量为 0 时同样被拒绝
+    await expect(initializeMarket(0)).rejects.toThrow('Invalid order book capacity.')
     // 调用程序的 initializeMarket 方法
-    await program.methods
-      .initializeMarket(
-        makerFeeBps.toNumber(), // Maker 费用（0.2%）
-        takerFeeBps.toNumber(), // Taker 费用（0.4%）
-        tickSize, // 价格最小单位
-        baseLotSize, // 数量最小单位
-        null, // 最小基础数量（未设置）
-        null, // 最小名义价值（未设置）
-      )
-      .accounts({
-        market: marketPda, // 市场账户
-        baseMint: baseMint, // 基础代币 Mint
-        quoteMint: quoteMint, // 报价代币 Mint
-        baseVault: baseVaultPda, // 基础代币金库
-        quoteVault: quoteVaultPda, // 报价代币金库
-        feeVault: feeVaultPda, // 费用金库
-        bids: bidsPda, // 买单簿
-        asks: asksPda, // 卖单簿
-        authority: authority.publicKey, // 管理员公钥
-        systemProgram: SystemProgram.programId, // 系统程序
-        tokenProgram: TOKEN_2022_PROGRAM_ID, // Token 2022 程序
-        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID, // 关联代币程序
-        rent: anchor.web3.SYSVAR_RENT_PUBKEY, // 租金账户
-      })
-      .signers([authority]) // 管理员签名
-      .rpc() // 发送交易
+    await initializeMarket(bookCapacity)
 
     // 验证市场状态
     const marketAccount = await program.account.market.fetch(marketPda) // 获取市场账户数据
@@ -233,14 +341,16 @@ describe('order_book_dex', () => {
 
     // 调用程序的 newLimitOrder 方法放置卖单
     await program.methods
-      .newLimitOrder({ ask: {} }, price, quantity)
+      .newLimitOrder({ ask: {} }, price, quantity, { limit: {} }, { decrementAndCancel: {} }, new BN(0))
       .accounts({
         market: marketPda, // 市场账户
         bids: bidsPda, // 买单簿
         asks: asksPda, // 卖单簿
+        eventQueue: eventQueuePda, // 事件队列
         openOrders: openOrdersB, // 用户 B 的开放订单账户
         baseVault: baseVaultPda, // 基础代币金库
         quoteVault: quoteVaultPda, // 报价代币金库
+        feeVault: feeVaultPda, // 费用金库
         userBaseTokenAccount: userBBaseTokenAccount, // 用户 B 的基础代币账户
         userQuoteTokenAccount: userBQuoteTokenAccount, // 用户 B 的报价代币账户
         owner: userB.publicKey, // 用户 B 公钥
@@ -267,14 +377,16 @@ describe('order_book_dex', () => {
       console.log("--> [TEST] Building transaction for user A's bid...") // 调试日志：开始构建交易
       // 构建限价买单交易
       const tx = await program.methods
-        .newLimitOrder({ bid: {} }, price, quantity)
+        .newLimitOrder({ bid: {} }, price, quantity, { limit: {} }, { decrementAndCancel: {} }, new BN(0))
         .accounts({
           market: marketPda, // 市场账户
           bids: bidsPda, // 买单簿
           asks: asksPda, // 卖单簿
+          eventQueue: eventQueuePda, // 事件队列
           openOrders: openOrdersA, // 用户 A 的开放订单账户
           baseVault: baseVaultPda, // 基础代币金库
           quoteVault: quoteVaultPda, // 报价代币金库
+          feeVault: feeVaultPda, // 费用金库
           userBaseTokenAccount: userABaseTokenAccount, // 用户 A 的基础代币账户
           userQuoteTokenAccount: userAQuoteTokenAccount, // 用户 A 的报价代币账户
           owner: userA.publicKey, // 用户 A 公钥
@@ -301,7 +413,22 @@ describe('order_book_dex', () => {
       // 验证买单簿状态
       console.log('--> [TEST] Fetching account states for verification...') // 调试日志：获取账户状态
       const bidsAccount = await program.account.orderBook.fetch(bidsPda) // 获取买单簿数据
-      expect(bidsAccount.count).toBe(1) // 验证买单簿有一个订单
+      expect(bidsAccount.count).toBe(0) // 验证买单已在下单时全部成交，没有挂入买单簿
+      expect((await fetchBestOrder(asksPda, false)).baseQty.eq(new BN(5 * 1e9))).toBe(true) // 验证卖单剩余 5 SOL
+
+      // Maker 一方的成交写入了事件队列，由 consumeEvents 结算到用户 B 的开放订单账户
+      let eventQueueAccount = await program.account.eventQueue.fetch(eventQueuePda) // 获取事件队列数据
+      expect(eventQueueAccount.count).toBe(1) // 验证有一个待处理的成交事件
+      await consumeEvents([openOrdersB])
+      eventQueueAccount = await program.account.eventQueue.fetch(eventQueuePda) // 获取更新后的事件队列
+      expect(eventQueueAccount.count).toBe(0) // 验证事件已处理
+
+      // 验证双方的资金状态
+      const openOrdersAAccount = await program.account.openOrders.fetch(openOrdersA) // 获取用户 A 的开放订单账户
+      expect(openOrdersAAccount.baseTokenFree.eq(quantity)).toBe(true) // 验证 Taker 获得 5 SOL
+      expect(openOrdersAAccount.quoteTokenLocked.toNumber()).toBe(0) // 验证 Taker 没有剩余锁定资金
+      const openOrdersBAccount = await program.account.openOrders.fetch(openOrdersB) // 获取用户 B 的开放订单账户
+      expect(openOrdersBAccount.baseTokenLocked.eq(new BN(5 * 1e9))).toBe(true) // 验证 Maker 剩余锁定 5 SOL
       console.log('--> [TEST] ✅ PASSED') // 调试日志：测试通过
     } catch (error) {
       // 错误处理
@@ -317,8 +444,8 @@ describe('order_book_dex', () => {
     }
   }, 60000) // 设置 60 秒超时
 
-  // 测试用例：撮合订单
-  it('✅ Matches the orders', async () => {
+  // 测试用例：订单簿没有交叉时撮合指令直接返回
+  it('✅ Match orders is a no-op when the book is not crossed', async () => {
     try {
       console.log('--> [TEST] Building matchOrders transaction...') // 调试日志：构建撮合交易
       // 构建撮合订单指令
@@ -328,6 +455,7 @@ describe('order_book_dex', () => {
           market: marketPda, // 市场账户
           bids: bidsPda, // 买单簿
           asks: asksPda, // 卖单簿
+          eventQueue: eventQueuePda, // 事件队列
           baseVault: baseVaultPda, // 基础代币金库
           quoteVault: quoteVaultPda, // 报价代币金库
           feeVault: feeVaultPda, // 费用金库
@@ -335,10 +463,6 @@ describe('order_book_dex', () => {
           quoteMint: quoteMint, // 报价代币 Mint
           tokenProgram: TOKEN_2022_PROGRAM_ID, // Token 2022 程序
         })
-        .remainingAccounts([
-          { pubkey: openOrdersA, isSigner: false, isWritable: true }, // 用户 A 的开放订单账户（Taker）
-          { pubkey: openOrdersB, isSigner: false, isWritable: true }, // 用户 B 的开放订单账户（Maker）
-        ])
         .instruction() // 生成指令
 
       // 创建交易对象
@@ -359,9 +483,11 @@ describe('order_book_dex', () => {
 
       console.log('--> [TEST] ✅ PASSED (after confirmation)') // 调试日志：测试通过
 
-      // 验证买单簿状态
+      // 验证订单簿状态未改变
       const bidsAccount = await program.account.orderBook.fetch(bidsPda) // 获取买单簿数据
-      expect(bidsAccount.count).toBe(0) // 验证买单簿为空（订单已撮合）
+      expect(bidsAccount.count).toBe(0) // 验证买单簿仍为空
+      const asksAccount = await program.account.orderBook.fetch(asksPda) // 获取卖单簿数据
+      expect(asksAccount.count).toBe(1) // 验证卖单簿仍有一个订单
     } catch (error) {
       console.error('--> [TEST] ❌ FAILED with error object:', error) // 调试日志：测试失败
       if (error && error.logs) {
@@ -378,8 +504,7 @@ describe('order_book_dex', () => {
   // 测试用例：用户 B 取消剩余订单
   it('✅ User B cancels the remaining order', async () => {
     // 获取卖单簿数据以找到要取消的订单
-    const asksAccountBefore = await program.account.orderBook.fetch(asksPda) // 获取卖单簿数据
-    const orderToCancel = asksAccountBefore.nodes[asksAccountBefore.head].order // 获取第一个订单
+    const orderToCancel = await fetchBestOrder(asksPda, false) // 获取第一个订单
     const orderId = orderToCancel.orderId // 获取订单 ID
 
     // 调用取消限价订单方法
@@ -398,6 +523,8 @@ describe('order_book_dex', () => {
     // 验证卖单簿状态
     const asksAccountAfter = await program.account.orderBook.fetch(asksPda) // 获取更新后的卖单簿
     expect(asksAccountAfter.count).toBe(0) // 验证卖单簿为空
+    const openOrdersBAfter = await program.account.openOrders.fetch(openOrdersB) // 获取用户 B 的开放订单账户
+    expect(openOrdersBAfter.orders.length).toBe(0) // 验证活动订单已移除
   })
 
   // 测试用例：用户结算资金
@@ -475,6 +602,59 @@ describe('order_book_dex', () => {
     expect(openOrdersB_afterSettle.quoteTokenFree.toNumber()).toBe(0) // 验证无剩余报价代币
   }, 60000) // 设置 60 秒超时
 
+  // 测试用例：用户调整开放订单账户的容量
+  it('✅ User B resizes their open orders account', async () => {
+    const openOrdersBefore = await program.account.openOrders.fetch(openOrdersB) // 获取用户 B 的开放订单账户
+    expect(openOrdersBefore.orderCapacity).toBe(16) // 验证默认容量
+    const dataLenBefore = (await connection.getAccountInfo(openOrdersB))!.data.length // 扩容前的账户大小
+
+    // 扩容到 64 个活动订单
+    await program.methods
+      .resizeOpenOrders(64)
+      .accounts({
+        openOrders: openOrdersB, // 用户 B 的开放订单账户
+        owner: userB.publicKey, // 用户 B 公钥
+        market: marketPda, // 市场账户
+      })
+      .signers([userB]) // 用户 B 签名
+      .rpc() // 发送交易
+
+    // 验证容量和账户大小
+    const openOrdersAfter = await program.account.openOrders.fetch(openOrdersB) // 获取更新后的开放订单账户
+    expect(openOrdersAfter.orderCapacity).toBe(64) // 验证新容量
+    const dataLenAfter = (await connection.getAccountInfo(openOrdersB))!.data.length // 扩容后的账户大小
+    expect(dataLenAfter - dataLenBefore).toBe(48 * 25) // 每个槽位 25 字节（订单 ID、客户端订单 ID、价格和方向）
+
+    // 容量为 0 无效
+    await expect(
+      program.methods
+        .resizeOpenOrders(0)
+        .accounts({
+          openOrders: openOrdersB, // 用户 B 的开放订单账户
+          owner: userB.publicKey, // 用户 B 公钥
+          market: marketPda, // 市场账户
+        })
+        .signers([userB]) // 用户 B 签名
+        .rpc(),
+    ).rejects.toThrow('Invalid OpenOrders capacity.') // 验证抛出容量无效错误
+
+    // 边界：从最小容量 1 一次扩容到最大容量 410，扩大 409 * 25 = 10225 字节，不超过单条指令 10240 字节的限制
+    const resize = (capacity: number) =>
+      program.methods
+        .resizeOpenOrders(capacity)
+        .accounts({
+          openOrders: openOrdersB, // 用户 B 的开放订单账户
+          owner: userB.publicKey, // 用户 B 公钥
+          market: marketPda, // 市场账户
+        })
+        .signers([userB]) // 用户 B 签名
+        .rpc()
+    await resize(1) // 缩容到最小容量
+    await resize(410) // 一次扩容到最大容量
+    expect((await program.account.openOrders.fetch(openOrdersB)).orderCapacity).toBe(410) // 验证达到最大容量
+    await expect(resize(411)).rejects.toThrow('Invalid OpenOrders capacity.') // 超过最大容量被拒绝
+  })
+
   // 测试用例：用户关闭开放订单账户
   it('✅ Users close their open orders accounts', async () => {
     // 为用户 A 关闭开放订单账户
@@ -562,7 +742,128 @@ describe('order_book_dex', () => {
     expect(marketAccount.takerFeeBps).toBe(newTakerFee) // 验证新 Taker 费用
   })
 
+  // 测试用例：管理员扩容订单簿
+  it('✅ Admin can grow an order book', async () => {
+    const additionalCapacity = 32 // 新增容量
+    const dataLenBefore = (await connection.getAccountInfo(asksPda))!.data.length // 扩容前的账户大小
+
+    // 调用扩容方法
+    await program.methods
+      .growOrderBook(additionalCapacity)
+      .accounts({
+        market: marketPda, // 市场账户
+        orderBook: asksPda, // 卖单簿
+        authority: authority.publicKey, // 管理员公钥
+      })
+      .signers([authority]) // 管理员签名
+      .rpc() // 发送交易
+
+    // 验证容量和账户大小
+    const asksAccount = await program.account.orderBook.fetch(asksPda) // 获取卖单簿数据
+    expect(asksAccount.capacity).toBe(bookCapacity + additionalCapacity) // 验证新容量
+    const dataLenAfter = (await connection.getAccountInfo(asksPda))!.data.length // 扩容后的账户大小
+    expect(dataLenAfter - dataLenBefore).toBe(additionalCapacity * (120 + 32)) // 每个容量包含一个叶子节点和一个内部节点
+
+    // 非管理员无法扩容
+    await expect(
+      program.methods
+        .growOrderBook(additionalCapacity)
+        .accounts({
+          market: marketPda, // 市场账户
+          orderBook: asksPda, // 卖单簿
+          authority: userA.publicKey, // 非管理员
+        })
+        .signers([userA]) // 用户 A 签名
+        .rpc(),
+    ).rejects.toThrow() // 验证交易失败
+  })
+
   // 高级测试用例套件
+  // 测试用例：两步转移管理员权限
+  it('✅ Admin authority can be transferred in two steps', async () => {
+    // 提议用户 D 成为新管理员
+    await program.methods
+      .proposeAuthority(userD.publicKey)
+      .accounts({
+        market: marketPda, // 市场账户
+        authority: authority.publicKey, // 管理员公钥
+      })
+      .signers([authority]) // 管理员签名
+      .rpc() // 发送交易
+    let marketAccount = await program.account.market.fetch(marketPda) // 获取市场账户数据
+    expect(marketAccount.pendingAuthority.equals(userD.publicKey)).toBe(true) // 验证待接受的管理员
+    expect(marketAccount.authority.equals(authority.publicKey)).toBe(true) // 验证管理员尚未改变
+
+    // 非被提议者不能接受
+    await expect(
+      program.methods
+        .acceptAuthority()
+        .accounts({
+          market: marketPda, // 市场账户
+          newAuthority: userA.publicKey, // 用户 A 公钥
+        })
+        .signers([userA]) // 用户 A 签名
+        .rpc(),
+    ).rejects.toThrow('Unauthorized action.') // 验证抛出未授权错误
+
+    // 用户 D 接受管理员权限
+    await program.methods
+      .acceptAuthority()
+      .accounts({
+        market: marketPda, // 市场账户
+        newAuthority: userD.publicKey, // 用户 D 公钥
+      })
+      .signers([userD]) // 用户 D 签名
+      .rpc() // 发送交易
+    marketAccount = await program.account.market.fetch(marketPda) // 获取市场账户数据
+    expect(marketAccount.authority.equals(userD.publicKey)).toBe(true) // 验证管理员已转移
+    expect(marketAccount.pendingAuthority.equals(PublicKey.default)).toBe(true) // 验证提议已清除
+
+    // 原管理员不再拥有权限
+    await expect(
+      program.methods
+        .setPause(true)
+        .accounts({
+          market: marketPda, // 市场账户
+          authority: authority.publicKey, // 原管理员公钥
+        })
+        .signers([authority]) // 原管理员签名
+        .rpc(),
+    ).rejects.toThrow() // 验证原管理员被拒绝
+
+    // 将管理员权限交还给原管理员
+    await program.methods
+      .proposeAuthority(authority.publicKey)
+      .accounts({
+        market: marketPda, // 市场账户
+        authority: userD.publicKey, // 用户 D 公钥
+      })
+      .signers([userD]) // 用户 D 签名
+      .rpc() // 发送交易
+    await program.methods
+      .acceptAuthority()
+      .accounts({
+        market: marketPda, // 市场账户
+        newAuthority: authority.publicKey, // 管理员公钥
+      })
+      .signers([authority]) // 管理员签名
+      .rpc() // 发送交易
+    marketAccount = await program.account.market.fetch(marketPda) // 获取市场账户数据
+    expect(marketAccount.authority.equals(authority.publicKey)).toBe(true) // 验证管理员已恢复
+
+    // 尚未配置手续费分配账户时不能放弃管理员权限，否则手续费将无法再被提取
+    await expect(
+      program.methods
+        .renounceAuthority()
+        .accounts({
+          market: marketPda, // 市场账户
+          authority: authority.publicKey, // 管理员公钥
+        })
+        .signers([authority]) // 管理员签名
+        .rpc(),
+    ).rejects.toThrow('Fee distribution is not configured.') // 验证抛出手续费分配未配置错误
+  })
+
   describe('Advanced Scenarios', () => {
     // 定义新用户
     const userC = Keypair.generate() // 新用户 C（买家）
@@ -659,14 +960,16 @@ describe('order_book_dex', () => {
       const priceD = new BN(160 * 100) // 卖单价格：160.00 USDC
       const quantityD = new BN(10 * 1e9) // 卖单数量：10 SOL
       await program.methods
-        .newLimitOrder({ ask: {} }, priceD, quantityD)
+        .newLimitOrder({ ask: {} }, priceD, quantityD, { limit: {} }, { decrementAndCancel: {} }, new BN(0))
         .accounts({
           market: marketPda, // 市场账户
           bids: bidsPda, // 买单簿
           asks: asksPda, // 卖单簿
+          eventQueue: eventQueuePda, // 事件队列
           openOrders: openOrdersD, // 用户 D 的开放订单账户
           baseVault: baseVaultPda, // 基础代币金库
           quoteVault: quoteVaultPda, // 报价代币金库
+          feeVault: feeVaultPda, // 费用金库
           userBaseTokenAccount: userDBaseTokenAccount, // 用户 D 的基础代币账户
           userQuoteTokenAccount: userDQuoteTokenAccount, // 用户 D 的报价代币账户
           owner: userD.publicKey, // 用户 D 公钥
@@ -687,14 +990,16 @@ describe('order_book_dex', () => {
       const priceC = new BN(161 * 100) // 买单价格：161.00 USDC
       const quantityC = new BN(20 * 1e9) // 买单数量：20 SOL
       await program.methods
-        .newLimitOrder({ bid: {} }, priceC, quantityC)
+        .newLimitOrder({ bid: {} }, priceC, quantityC, { limit: {} }, { decrementAndCancel: {} }, new BN(0))
         .accounts({
           market: marketPda, // 市场账户
           bids: bidsPda, // 买单簿
           asks: asksPda, // 卖单簿
+          eventQueue: eventQueuePda, // 事件队列
           openOrders: openOrdersC, // 用户 C 的开放订单账户
           baseVault: baseVaultPda, // 基础代币金库
           quoteVault: quoteVaultPda, // 报价代币金库
+          feeVault: feeVaultPda, // 费用金库
           userBaseTokenAccount: userCBaseTokenAccount, // 用户 C 的基础代币账户
           userQuoteTokenAccount: userCQuoteTokenAccount, // 用户 C 的报价代币账户
           owner: userC.publicKey, // 用户 C 公钥
@@ -707,46 +1012,24 @@ describe('order_book_dex', () => {
         .signers([userC]) // 用户 C 签名
         .rpc() // 发送交易
 
-      // 验证买单簿状态
-      let bidsAccount = await program.account.orderBook.fetch(bidsPda) // 获取买单簿数据
-      expect(bidsAccount.count).toBe(1) // 验证有一个买单
-
-      // 撮合订单
-      await program.methods
-        .matchOrders(new BN(5)) // 撮合 5 个订单
-        .accounts({
-          market: marketPda, // 市场账户
-          bids: bidsPda, // 买单簿
-          asks: asksPda, // 卖单簿
-          baseVault: baseVaultPda, // 基础代币金库
-          quoteVault: quoteVaultPda, // 报价代币金库
-          feeVault: feeVaultPda, // 费用金库
-          baseMint: baseMint, // 基础代币 Mint
-          quoteMint: quoteMint, // 报价代币 Mint
-          tokenProgram: TOKEN_2022_PROGRAM_ID, // Token 2022 程序
-        })
-        .remainingAccounts([
-          { pubkey: openOrdersC, isSigner: false, isWritable: true }, // 用户 C 的开放订单账户（Taker）
-          { pubkey: openOrdersD, isSigner: false, isWritable: true }, // 用户 D 的开放订单账户（Maker）
-        ])
-        .rpc() // 发送交易
-
       // 验证状态
       asksAccount = await program.account.orderBook.fetch(asksPda) // 获取更新后的卖单簿
       expect(asksAccount.count).toBe(0) // 验证卖单簿为空（卖单已成交）
 
-      bidsAccount = await program.account.orderBook.fetch(bidsPda) // 获取更新后的买单簿
+      const bidsAccount = await program.account.orderBook.fetch(bidsPda) // 获取更新后的买单簿
       expect(bidsAccount.count).toBe(1) // 验证买单簿有一个剩余订单
-      const remainingBidOrder = bidsAccount.nodes[bidsAccount.head].order // 获取剩余买单
+      const remainingBidOrder = await fetchBestOrder(bidsPda, true) // 获取剩余买单
       expect(remainingBidOrder.baseQty.eq(new BN(10 * 1e9))).toBe(true) // 验证剩余数量为 10 SOL
       expect(remainingBidOrder.ownerAccount).toEqual(openOrdersC) // 验证订单拥有者
+
+      // 结算用户 D 的成交事件
+      await consumeEvents([openOrdersD])
     })
 
     // 测试用例：一个 Taker 订单撮合多个 Maker 订单
     it('✅ Multi-match: One Taker order fills multiple Maker orders', async () => {
       // 清理用户 C 的剩余买单
-      const bidsAccountBefore = await program.account.orderBook.fetch(bidsPda) // 获取买单簿数据
-      const orderToCancelId = bidsAccountBefore.nodes[bidsAccountBefore.head].order.orderId // 获取订单 ID
+      const orderToCancelId = (await fetchBestOrder(bidsPda, true)).orderId // 获取订单 ID
       await program.methods
         .cancelLimitOrder(orderToCancelId)
         .accounts({
@@ -761,14 +1044,16 @@ describe('order_book_dex', () => {
 
       // 用户 B 放置 5 SOL 的卖单，价格 158 USDC
       await program.methods
-        .newLimitOrder({ ask: {} }, new BN(158 * 100), new BN(5 * 1e9))
+        .newLimitOrder({ ask: {} }, new BN(158 * 100), new BN(5 * 1e9), { limit: {} }, { decrementAndCancel: {} }, new BN(0))
         .accounts({
           market: marketPda, // 市场账户
           bids: bidsPda, // 买单簿
           asks: asksPda, // 卖单簿
+          eventQueue: eventQueuePda, // 事件队列
           openOrders: openOrdersB, // 用户 B 的开放订单账户
           baseVault: baseVaultPda, // 基础代币金库
           quoteVault: quoteVaultPda, // 报价代币金库
+          feeVault: feeVaultPda, // 费用金库
           userBaseTokenAccount: userBBaseTokenAccount, // 用户 B 的基础代币账户
           userQuoteTokenAccount: userBQuoteTokenAccount, // 用户 B 的报价代币账户
           owner: userB.publicKey, // 用户 B 公钥
@@ -783,14 +1068,16 @@ describe('order_book_dex', () => {
 
       // 用户 D 放置 8 SOL 的卖单，价格 159 USDC
       await program.methods
-        .newLimitOrder({ ask: {} }, new BN(159 * 100), new BN(8 * 1e9))
+        .newLimitOrder({ ask: {} }, new BN(159 * 100), new BN(8 * 1e9), { limit: {} }, { decrementAndCancel: {} }, new BN(0))
         .accounts({
           market: marketPda, // 市场账户
           bids: bidsPda, // 买单簿
           asks: asksPda, // 卖单簿
+          eventQueue: eventQueuePda, // 事件队列
           openOrders: openOrdersD, // 用户 D 的开放订单账户
           baseVault: baseVaultPda, // 基础代币金库
           quoteVault: quoteVaultPda, // 报价代币金库
+          feeVault: feeVaultPda, // 费用金库
           userBaseTokenAccount: userDBaseTokenAccount, // 用户 D 的基础代币账户
           userQuoteTokenAccount: userDQuoteTokenAccount, // 用户 D 的报价代币账户
           owner: userD.publicKey, // 用户 D 公钥
@@ -807,16 +1094,18 @@ describe('order_book_dex', () => {
       let asksAccount = await program.account.orderBook.fetch(asksPda) // 获取卖单簿数据
       expect(asksAccount.count).toBe(2) // 验证有两个卖单
 
-      // 用户 C 放置 15 SOL 的买单，价格 160 USDC
+      // 用户 C 放置 15 SOL 的买单，价格 160 USDC，依次吃掉用户 B 和用户 D 的卖单
       await program.methods
-        .newLimitOrder({ bid: {} }, new BN(160 * 100), new BN(15 * 1e9))
+        .newLimitOrder({ bid: {} }, new BN(160 * 100), new BN(15 * 1e9), { limit: {} }, { decrementAndCancel: {} }, new BN(0))
         .accounts({
           market: marketPda, // 市场账户
           bids: bidsPda, // 买单簿
           asks: asksPda, // 卖单簿
+          eventQueue: eventQueuePda, // 事件队列
           openOrders: openOrdersC, // 用户 C 的开放订单账户
           baseVault: baseVaultPda, // 基础代币金库
           quoteVault: quoteVaultPda, // 报价代币金库
+          feeVault: feeVaultPda, // 费用金库
           userBaseTokenAccount: userCBaseTokenAccount, // 用户 C 的基础代币账户
           userQuoteTokenAccount: userCQuoteTokenAccount, // 用户 C 的报价代币账户
           owner: userC.publicKey, // 用户 C 公钥
@@ -829,83 +1118,34 @@ describe('order_book_dex', () => {
         .signers([userC]) // 用户 C 签名
         .rpc() // 发送交易
 
-      // 第一次撮合：用户 C vs 用户 B
-      await program.methods
-        .matchOrders(new BN(1))
-        .accounts({
-          market: marketPda, // 市场账户
-          bids: bidsPda, // 买单簿
-          asks: asksPda, // 卖单簿
-          baseVault: baseVaultPda, // 基础代币金库
-          quoteVault: quoteVaultPda, // 报价代币金库
-          feeVault: feeVaultPda, // 费用金库
-          baseMint: baseMint, // 基础代币 Mint
-          quoteMint: quoteMint, // 报价代币 Mint
-          tokenProgram: TOKEN_2022_PROGRAM_ID, // Token 2022 程序
-        })
-        .remainingAccounts([
-          { pubkey: openOrdersC, isSigner: false, isWritable: true }, // 用户 C 的开放订单账户（Taker）
-          { pubkey: openOrdersB, isSigner: false, isWritable: true }, // 用户 B 的开放订单账户（Maker）
-        ])
-        .rpc() // 发送交易
-
-      // 验证卖单簿状态
-      asksAccount = await program.account.orderBook.fetch(asksPda) // 获取更新后的卖单簿
-      expect(asksAccount.count).toBe(1) // 验证剩下一个卖单
-
-      // 第二次撮合：用户 C vs 用户 D
-      await program.methods
-        .matchOrders(new BN(1))
-        .accounts({
-          market: marketPda, // 市场账户
-          bids: bidsPda, // 买单簿
-          asks: asksPda, // 卖单簿
-          baseVault: baseVaultPda, // 基础代币金库
-          quoteVault: quoteVaultPda, // 报价代币金库
-          feeVault: feeVaultPda, // 费用金库
-          baseMint: baseMint, // 基础代币 Mint
-          quoteMint: quoteMint, // 报价代币 Mint
-          tokenProgram: TOKEN_2022_PROGRAM_ID, // Token 2022 程序
-        })
-        .remainingAccounts([
-          { pubkey: openOrdersC, isSigner: false, isWritable: true }, // 用户 C 的开放订单账户（Taker）
-          { pubkey: openOrdersD, isSigner: false, isWritable: true }, // 用户 D 的开放订单账户（Maker）
-        ])
-        .rpc() // 发送交易
-
       // 验证状态
       asksAccount = await program.account.orderBook.fetch(asksPda) // 获取更新后的卖单簿
       expect(asksAccount.count).toBe(0) // 验证卖单簿为空
 
       const bidsAccount = await program.account.orderBook.fetch(bidsPda) // 获取买单簿数据
       expect(bidsAccount.count).toBe(1) // 验证买单簿有一个剩余订单
-      const finalBidOrder = bidsAccount.nodes[bidsAccount.head].order // 获取剩余买单
+      const finalBidOrder = await fetchBestOrder(bidsPda, true) // 获取剩余买单
       expect(finalBidOrder.baseQty.eq(new BN(2 * 1e9))).toBe(true) // 验证剩余数量为 2 SOL
-    })
 
-    // 测试用例：市场暂停时无法下单
-    it('✅ Should fail to place order when market is paused', async () => {
-      // 暂停市场
-      await program.methods
-        .setPause(true)
-        .accounts({
-          market: marketPda, // 市场账户
-          authority: authority.publicKey, // 管理员公钥
-        })
-        .signers([authority]) // 管理员签名
-        .rpc() // 发送交易
+      // 结算用户 B 和用户 D 的成交事件
+      await consumeEvents([openOrdersB, openOrdersD])
+    })
 
-      // 尝试下单，期望失败
+    // 测试用例：PostOnly 与 IOC 订单类型
+    it('✅ Order types: PostOnly rejects crossing orders and IOC never rests', async () => {
+      // 用户 D 以 160 USDC 挂出 PostOnly 卖单，会与用户 C 在 160 USDC 的剩余买单成交，期望失败
       await expect(
         program.methods
-          .newLimitOrder({ ask: {} }, new BN(200 * 100), new BN(1 * 1e9))
+          .newLimitOrder({ ask: {} }, new BN(160 * 100), new BN(1 * 1e9), { postOnly: {} }, { decrementAndCancel: {} }, new BN(0))
           .accounts({
             market: marketPda, // 市场账户
             bids: bidsPda, // 买单簿
             asks: asksPda, // 卖单簿
+            eventQueue: eventQueuePda, // 事件队列
             openOrders: openOrdersD, // 用户 D 的开放订单账户
             baseVault: baseVaultPda, // 基础代币金库
             quoteVault: quoteVaultPda, // 报价代币金库
+            feeVault: feeVaultPda, // 费用金库
             userBaseTokenAccount: userDBaseTokenAccount, // 用户 D 的基础代币账户
             userQuoteTokenAccount: userDQuoteTokenAccount, // 用户 D 的报价代币账户
             owner: userD.publicKey, // 用户 D 公钥
@@ -917,39 +1157,1344 @@ describe('order_book_dex', () => {
           })
           .signers([userD]) // 用户 D 签名
           .rpc(),
-      ).rejects.toThrow('Market is paused.') // 验证抛出“市场暂停”错误
+      ).rejects.toThrow('Order would cross the spread, violating PostOnly.') // 验证抛出 PostOnly 错误
 
-      // 恢复市场
+      // 用户 D 以 200 USDC 下 IOC 卖单，没有可成交的买单，剩余部分不应挂单
       await program.methods
-        .setPause(false)
+        .newLimitOrder({ ask: {} }, new BN(200 * 100), new BN(1 * 1e9), { immediateOrCancel: {} }, { decrementAndCancel: {} }, new BN(0))
         .accounts({
           market: marketPda, // 市场账户
-          authority: authority.publicKey, // 管理员公钥
+          bids: bidsPda, // 买单簿
+          asks: asksPda, // 卖单簿
+          eventQueue: eventQueuePda, // 事件队列
+          openOrders: openOrdersD, // 用户 D 的开放订单账户
+          baseVault: baseVaultPda, // 基础代币金库
+          quoteVault: quoteVaultPda, // 报价代币金库
+          feeVault: feeVaultPda, // 费用金库
+          userBaseTokenAccount: userDBaseTokenAccount, // 用户 D 的基础代币账户
+          userQuoteTokenAccount: userDQuoteTokenAccount, // 用户 D 的报价代币账户
+          owner: userD.publicKey, // 用户 D 公钥
+          baseMint: baseMint, // 基础代币 Mint
+          quoteMint: quoteMint, // 报价代币 Mint
+          tokenProgram: TOKEN_2022_PROGRAM_ID, // Token 2022 程序
+          systemProgram: SystemProgram.programId, // 系统程序
+          rent: anchor.web3.SYSVAR_RENT_PUBKEY, // 租金账户
         })
-        .signers([authority]) // 管理员签名
+        .signers([userD]) // 用户 D 签名
         .rpc() // 发送交易
+
+      // 验证卖单簿仍为空
+      const asksAccount = await program.account.orderBook.fetch(asksPda) // 获取卖单簿数据
+      expect(asksAccount.count).toBe(0) // 验证 IOC 订单没有挂单
     })
 
-    // 测试用例：无法取消他人订单
-    it("✅ Should fail to cancel someone else's order", async () => {
-      // 获取用户 C 的订单 ID
-      const bidsAccount = await program.account.orderBook.fetch(bidsPda) // 获取买单簿数据
-      const userCOrderId = bidsAccount.nodes[bidsAccount.head].order.orderId // 获取用户 C 的订单 ID
+    // 测试用例：市价单按基础代币数量成交
+    it('✅ Market order: sells a base quantity into the best bid', async () => {
+      const openOrdersDBefore = await program.account.openOrders.fetch(openOrdersD) // 获取用户 D 的开放订单账户
 
-      // 用户 D 尝试取消用户 C 的订单，期望失败
-      await expect(
-        program.methods
-          .cancelLimitOrder(userCOrderId)
-          .accounts({
-            market: marketPda, // 市场账户
-            bids: bidsPda, // 买单簿
-            asks: asksPda, // 卖单簿
-            openOrders: openOrdersD, // 用户 D 的开放订单账户
-            owner: userD.publicKey, // 用户 D 公钥
-          })
-          .signers([userD]) // 用户 D 签名
+      // 用户 D 市价卖出 1 SOL，最差价格 150 USDC，吃掉用户 C 在 160 USDC 的买单
+      await program.methods
+        .newMarketOrder({ ask: {} }, { baseQuantity: {} }, new BN(1 * 1e9), new BN(150 * 100), { decrementAndCancel: {} })
+        .accounts({
+          market: marketPda, // 市场账户
+          bids: bidsPda, // 买单簿
+          asks: asksPda, // 卖单簿
+          eventQueue: eventQueuePda, // 事件队列
+          openOrders: openOrdersD, // 用户 D 的开放订单账户
+          baseVault: baseVaultPda, // 基础代币金库
+          quoteVault: quoteVaultPda, // 报价代币金库
+          feeVault: feeVaultPda, // 费用金库
+          userBaseTokenAccount: userDBaseTokenAccount, // 用户 D 的基础代币账户
+          userQuoteTokenAccount: userDQuoteTokenAccount, // 用户 D 的报价代币账户
+          owner: userD.publicKey, // 用户 D 公钥
+          baseMint: baseMint, // 基础代币 Mint
+          quoteMint: quoteMint, // 报价代币 Mint
+          tokenProgram: TOKEN_2022_PROGRAM_ID, // Token 2022 程序
+          systemProgram: SystemProgram.programId, // 系统程序
+          rent: anchor.web3.SYSVAR_RENT_PUBKEY, // 租金账户
+        })
+        .signers([userD]) // 用户 D 签名
+        .rpc() // 发送交易
+
+      // 验证用户 C 的买单剩余 1 SOL
+      expect((await fetchBestOrder(bidsPda, true)).baseQty.eq(new BN(1 * 1e9))).toBe(true) // 验证剩余数量
+
+      // 验证用户 D 获得了报价代币，且没有新增锁定资金
+      const openOrdersDAfter = await program.account.openOrders.fetch(openOrdersD) // 获取用户 D 的开放订单账户
+      expect(openOrdersDAfter.quoteTokenFree.gt(openOrdersDBefore.quoteTokenFree)).toBe(true) // 验证卖出所得
+      expect(openOrdersDAfter.baseTokenLocked.eq(openOrdersDBefore.baseTokenLocked)).toBe(true) // 验证没有挂单
+
+      // 结算用户 C 的成交事件
+      await consumeEvents([openOrdersC])
+    })
+
+    // 测试用例：兑换指令直接结算到用户代币账户，并受最小输出保护
+    it('✅ Swap: sells base directly into the best bid with a minimum-out guarantee and emits fill events', async () => {
+      const swapAccounts = {
+        market: marketPda, // 市场账户
+        bids: bidsPda, // 买单簿
+        asks: asksPda, // 卖单簿
+        eventQueue: eventQueuePda, // 事件队列
+        baseVault: baseVaultPda, // 基础代币金库
+        quoteVault: quoteVaultPda, // 报价代币金库
+        feeVault: feeVaultPda, // 费用金库
+        userSourceTokenAccount: userDBaseTokenAccount, // 用户 D 支付基础代币
+        userDestinationTokenAccount: userDQuoteTokenAccount, // 用户 D 接收报价代币
+        owner: userD.publicKey, // 用户 D 公钥
+        baseMint: baseMint, // 基础代币 Mint
+        quoteMint: quoteMint, // 报价代币 Mint
+        tokenProgram: TOKEN_2022_PROGRAM_ID, // Token 2022 程序
+      }
+
+      // 卖出 0.5 SOL 在价格 160*100 下最多得到 8 USDC（价格精度 PRICE_SCALE = 1e6），要求 10 USDC 应该失败
+      await expect(
+        program.methods
+          .swap(new BN(0.5 * 1e9), new BN(10 * 1e6))
+          .accounts(swapAccounts)
+          .signers([userD])
+          .rpc(),
+      ).rejects.toThrow(/SlippageExceeded/)
+
+      const quoteBefore = await getAccount(connection, userDQuoteTokenAccount, undefined, TOKEN_2022_PROGRAM_ID) // 兑换前的报价代币余额
+
+      // 要求至少 7.9 USDC（扣除 0.4% 的 taker 手续费后为 7.968 USDC）
+      const swapSig = await program.methods
+        .swap(new BN(0.5 * 1e9), new BN(7.9 * 1e6))
+        .accounts(swapAccounts)
+        .signers([userD])
+        .rpc({ commitment: 'confirmed' })
+
+      // 验证报价代币直接到达用户 D 的代币账户
+      const quoteAfter = await getAccount(connection, userDQuoteTokenAccount, undefined, TOKEN_2022_PROGRAM_ID) // 兑换后的报价代币余额
+      expect(quoteAfter.amount - quoteBefore.amount).toBe(BigInt(7_968_000)) // 8 USDC 扣除 0.032 USDC 手续费
+
+      // 验证用户 C 的买单剩余 0.5 SOL
+      expect((await fetchBestOrder(bidsPda, true)).baseQty.eq(new BN(0.5 * 1e9))).toBe(true) // 验证剩余数量
+
+      // 从交易日志中解析成交事件和手续费事件
+      const tx = await connection.getTransaction(swapSig, { commitment: 'confirmed', maxSupportedTransactionVersion: 0 })
+      const eventParser = new anchor.EventParser(program.programId, program.coder)
+      const events = [...eventParser.parseLogs(tx!.meta!.logMessages!)]
+      const trade = events.find((e) => e.name === 'tradeEvent')!.data // 成交事件
+      expect(trade.makerOpenOrders.equals(openOrdersC)).toBe(true) // 验证 maker 是用户 C
+      expect(trade.makerOwner.equals(userC.publicKey)).toBe(true) // 验证 maker 钱包是用户 C
+      expect(trade.takerOwner.equals(userD.publicKey)).toBe(true) // 验证 taker 是用户 D
+      expect(trade.takerOpenOrders.equals(openOrdersD)).toBe(true) // 验证 taker 的开放订单账户地址
+      expect(trade.baseQty.eq(new BN(0.5 * 1e9))).toBe(true) // 验证成交数量
+      expect(trade.quoteQty.eq(new BN(8_000_000))).toBe(true) // 验证成交金额
+      expect(trade.makerFee.eq(new BN(16_000))).toBe(true) // 验证 maker 手续费 0.2%
+      expect(trade.takerFee.eq(new BN(32_000))).toBe(true) // 验证 taker 手续费 0.4%
+      const feeCollected = events.find((e) => e.name === 'feeCollectedEvent')!.data // 手续费事件
+      expect(feeCollected.amount.eq(new BN(48_000))).toBe(true) // 验证手续费总额
+
+      // 结算用户 C 的成交事件
+      await consumeEvents([openOrdersC])
+    })
+
+    // 测试用例：自成交按订单指定的方式处理，而不是让交易失败
+    it('✅ Self-trade: cancel-maker removes the own resting order instead of failing', async () => {
+      const accountsD = {
+        market: marketPda, // 市场账户
+        bids: bidsPda, // 买单簿
+        asks: asksPda, // 卖单簿
+        eventQueue: eventQueuePda, // 事件队列
+        openOrders: openOrdersD, // 用户 D 的开放订单账户
+        baseVault: baseVaultPda, // 基础代币金库
+        quoteVault: quoteVaultPda, // 报价代币金库
+        feeVault: feeVaultPda, // 费用金库
+        userBaseTokenAccount: userDBaseTokenAccount, // 用户 D 的基础代币账户
+        userQuoteTokenAccount: userDQuoteTokenAccount, // 用户 D 的报价代币账户
+        owner: userD.publicKey, // 用户 D 公钥
+        baseMint: baseMint, // 基础代币 Mint
+        quoteMint: quoteMint, // 报价代币 Mint
+        tokenProgram: TOKEN_2022_PROGRAM_ID, // Token 2022 程序
+        systemProgram: SystemProgram.programId, // 系统程序
+        rent: anchor.web3.SYSVAR_RENT_PUBKEY, // 租金账户
+      }
+
+      // 用户 D 以 170 USDC 挂出 0.1 SOL 的卖单
+      await program.methods
+        .newLimitOrder({ ask: {} }, new BN(170 * 100), new BN(0.1 * 1e9), { limit: {} }, { decrementAndCancel: {} }, new BN(0))
+        .accounts(accountsD)
+        .signers([userD]) // 用户 D 签名
+        .rpc() // 发送交易
+      const openOrdersDBefore = await program.account.openOrders.fetch(openOrdersD) // 获取用户 D 的开放订单账户
+
+      // 用户 D 以 170 USDC 下 IOC 买单，与自己的卖单交叉，选择取消 maker
+      await program.methods
+        .newLimitOrder({ bid: {} }, new BN(170 * 100), new BN(0.1 * 1e9), { immediateOrCancel: {} }, { cancelMaker: {} }, new BN(0))
+        .accounts(accountsD)
+        .signers([userD]) // 用户 D 签名
+        .rpc() // 发送交易
+
+      // 自成交取消的卖单以 Out 事件写入事件队列，处理后资金返还到可用余额
+      await consumeEvents([openOrdersD])
+
+      // 验证卖单被取消，且没有产生成交
+      const asksAccount = await program.account.orderBook.fetch(asksPda) // 获取卖单簿数据
+      expect(asksAccount.count).toBe(0) // 验证卖单簿为空
+      const openOrdersDAfter = await program.account.openOrders.fetch(openOrdersD) // 获取用户 D 的开放订单账户
+      expect(openOrdersDAfter.baseTokenLocked.eq(openOrdersDBefore.baseTokenLocked.sub(new BN(0.1 * 1e9)))).toBe(true) // 验证解锁
+      expect(openOrdersDAfter.baseTokenFree.eq(openOrdersDBefore.baseTokenFree.add(new BN(0.1 * 1e9)))).toBe(true) // 验证退回可用余额
+      expect(openOrdersDAfter.quoteTokenFree.eq(openOrdersDBefore.quoteTokenFree)).toBe(true) // 验证没有成交
+    })
+
+    // 测试用例：客户端订单 ID 在同一账户内唯一，并可用于取消订单
+    it('✅ Client order ids: duplicates are rejected and orders can be cancelled by client id', async () => {
+      const accountsD = {
+        market: marketPda, // 市场账户
+        bids: bidsPda, // 买单簿
+        asks: asksPda, // 卖单簿
+        eventQueue: eventQueuePda, // 事件队列
+        openOrders: openOrdersD, // 用户 D 的开放订单账户
+        baseVault: baseVaultPda, // 基础代币金库
+        quoteVault: quoteVaultPda, // 报价代币金库
+        feeVault: feeVaultPda, // 费用金库
+        userBaseTokenAccount: userDBaseTokenAccount, // 用户 D 的基础代币账户
+        userQuoteTokenAccount: userDQuoteTokenAccount, // 用户 D 的报价代币账户
+        owner: userD.publicKey, // 用户 D 公钥
+        baseMint: baseMint, // 基础代币 Mint
+        quoteMint: quoteMint, // 报价代币 Mint
+        tokenProgram: TOKEN_2022_PROGRAM_ID, // Token 2022 程序
+        systemProgram: SystemProgram.programId, // 系统程序
+        rent: anchor.web3.SYSVAR_RENT_PUBKEY, // 租金账户
+      }
+      const clientOrderId = new BN(42) // 客户端订单 ID
+
+      // 用户 D 以 200 USDC 挂出 0.1 SOL 的卖单，并指定客户端订单 ID
+      await program.methods
+        .newLimitOrder({ ask: {} }, new BN(200 * 100), new BN(0.1 * 1e9), { limit: {} }, { decrementAndCancel: {} }, clientOrderId)
+        .accounts(accountsD)
+        .signers([userD]) // 用户 D 签名
+        .rpc() // 发送交易
+      const openOrdersDBefore = await program.account.openOrders.fetch(openOrdersD) // 获取用户 D 的开放订单账户
+      const slot = openOrdersDBefore.orders.find((o) => o.clientOrderId.eq(clientOrderId))! // 按客户端订单 ID 查找活动订单
+      const bestAsk = await fetchBestOrder(asksPda, false) // 获取卖单簿中的最优订单
+      expect(bestAsk.orderId.eq(slot.orderId)).toBe(true) // 验证订单在卖单簿中
+      expect(bestAsk.clientOrderId.eq(clientOrderId)).toBe(true) // 验证订单簿中也记录了客户端订单 ID
+
+      // 使用相同的客户端订单 ID 再次下单，期望失败
+      await expect(
+        program.methods
+          .newLimitOrder({ ask: {} }, new BN(201 * 100), new BN(0.1 * 1e9), { limit: {} }, { decrementAndCancel: {} }, clientOrderId)
+          .accounts(accountsD)
+          .signers([userD]) // 用户 D 签名
+          .rpc(),
+      ).rejects.toThrow('Client order ID is already used by an open order.') // 验证抛出重复客户端订单 ID 错误
+
+      // 按客户端订单 ID 取消订单
+      await program.methods
+        .cancelOrderByClientId(clientOrderId)
+        .accounts({
+          market: marketPda, // 市场账户
+          bids: bidsPda, // 买单簿
+          asks: asksPda, // 卖单簿
+          openOrders: openOrdersD, // 用户 D 的开放订单账户
+          owner: userD.publicKey, // 用户 D 公钥
+        })
+        .signers([userD]) // 用户 D 签名
+        .rpc() // 发送交易
+
+      // 验证订单已移除，锁定的资金已返还
+      const asksAccount = await program.account.orderBook.fetch(asksPda) // 获取卖单簿数据
+      expect(asksAccount.count).toBe(0) // 验证卖单簿为空
+      const openOrdersDAfter = await program.account.openOrders.fetch(openOrdersD) // 获取用户 D 的开放订单账户
+      expect(openOrdersDAfter.orders.length).toBe(0) // 验证活动订单已移除
+      expect(openOrdersDAfter.baseTokenLocked.eq(openOrdersDBefore.baseTokenLocked.sub(new BN(0.1 * 1e9)))).toBe(true) // 验证解锁
+    })
+
+    // 测试用例：批量取消订单
+    it('✅ Bulk cancel: cancel_orders and cancel_all_orders unlock funds in one instruction', async () => {
+      const accountsD = {
+        market: marketPda, // 市场账户
+        bids: bidsPda, // 买单簿
+        asks: asksPda, // 卖单簿
+        eventQueue: eventQueuePda, // 事件队列
+        openOrders: openOrdersD, // 用户 D 的开放订单账户
+        baseVault: baseVaultPda, // 基础代币金库
+        quoteVault: quoteVaultPda, // 报价代币金库
+        feeVault: feeVaultPda, // 费用金库
+        userBaseTokenAccount: userDBaseTokenAccount, // 用户 D 的基础代币账户
+        userQuoteTokenAccount: userDQuoteTokenAccount, // 用户 D 的报价代币账户
+        owner: userD.publicKey, // 用户 D 公钥
+        baseMint: baseMint, // 基础代币 Mint
+        quoteMint: quoteMint, // 报价代币 Mint
+        tokenProgram: TOKEN_2022_PROGRAM_ID, // Token 2022 程序
+        systemProgram: SystemProgram.programId, // 系统程序
+        rent: anchor.web3.SYSVAR_RENT_PUBKEY, // 租金账户
+      }
+      const cancelAccountsD = {
+        market: marketPda, // 市场账户
+        bids: bidsPda, // 买单簿
+        asks: asksPda, // 卖单簿
+        openOrders: openOrdersD, // 用户 D 的开放订单账户
+        owner: userD.publicKey, // 用户 D 公钥
+      }
+      const openOrdersDBefore = await program.account.openOrders.fetch(openOrdersD) // 获取用户 D 的开放订单账户
+
+      // 用户 D 挂出三个卖单和一个不会成交的买单
+      for (const price of [200, 201, 202]) {
+        await program.methods
+          .newLimitOrder({ ask: {} }, new BN(price * 100), new BN(0.1 * 1e9), { limit: {} }, { decrementAndCancel: {} }, new BN(0))
+          .accounts(accountsD)
+          .signers([userD]) // 用户 D 签名
+          .rpc() // 发送交易
+      }
+      await program.methods
+        .newLimitOrder({ bid: {} }, new BN(100 * 100), new BN(0.1 * 1e9), { limit: {} }, { decrementAndCancel: {} }, new BN(0))
+        .accounts(accountsD)
+        .signers([userD]) // 用户 D 签名
+        .rpc() // 发送交易
+      let openOrdersDAccount = await program.account.openOrders.fetch(openOrdersD) // 获取用户 D 的开放订单账户
+      expect(openOrdersDAccount.orders.length).toBe(4) // 验证有四个活动订单
+
+      // 按订单 ID 取消价格为 200 的卖单，未知的订单 ID 被跳过
+      const firstAskId = openOrdersDAccount.orders.find((slot) => slot.price.eq(new BN(200 * 100)))!.orderId // 价格为 200 的卖单
+      await program.methods
+        .cancelOrders([firstAskId, new BN(999_999)])
+        .accounts(cancelAccountsD)
+        .signers([userD]) // 用户 D 签名
+        .rpc() // 发送交易
+      expect((await program.account.orderBook.fetch(asksPda)).count).toBe(2) // 验证剩余两个卖单
+
+      // 取消全部卖单，买单保留
+      await program.methods
+        .cancelAllOrders({ ask: {} }, null)
+        .accounts(cancelAccountsD)
+        .signers([userD]) // 用户 D 签名
+        .rpc() // 发送交易
+      expect((await program.account.orderBook.fetch(asksPda)).count).toBe(0) // 验证卖单簿为空
+      openOrdersDAccount = await program.account.openOrders.fetch(openOrdersD) // 获取用户 D 的开放订单账户
+      expect(openOrdersDAccount.orders.length).toBe(1) // 验证只剩买单
+      expect(openOrdersDAccount.orders[0].isBid).toBe(true) // 验证剩余订单为买单
+
+      // 取消全部剩余订单
+      await program.methods
+        .cancelAllOrders(null, null)
+        .accounts(cancelAccountsD)
+        .signers([userD]) // 用户 D 签名
+        .rpc() // 发送交易
+
+      // 验证所有订单均已取消，锁定资金全部返还
+      const openOrdersDAfter = await program.account.openOrders.fetch(openOrdersD) // 获取用户 D 的开放订单账户
+      expect(openOrdersDAfter.orders.length).toBe(0) // 验证没有活动订单
+      expect(openOrdersDAfter.baseTokenLocked.eq(openOrdersDBefore.baseTokenLocked)).toBe(true) // 验证基础代币锁定余额恢复
+      expect(openOrdersDAfter.quoteTokenLocked.eq(openOrdersDBefore.quoteTokenLocked)).toBe(true) // 验证报价代币锁定余额恢复
+    })
+
+    // 测试用例：修改订单数量与替换订单
+    it('✅ Amend and replace: amend keeps priority and replace reuses the released funds', async () => {
+      const accountsD = {
+        market: marketPda, // 市场账户
+        bids: bidsPda, // 买单簿
+        asks: asksPda, // 卖单簿
+        eventQueue: eventQueuePda, // 事件队列
+        openOrders: openOrdersD, // 用户 D 的开放订单账户
+        baseVault: baseVaultPda, // 基础代币金库
+        quoteVault: quoteVaultPda, // 报价代币金库
+        feeVault: feeVaultPda, // 费用金库
+        userBaseTokenAccount: userDBaseTokenAccount, // 用户 D 的基础代币账户
+        userQuoteTokenAccount: userDQuoteTokenAccount, // 用户 D 的报价代币账户
+        owner: userD.publicKey, // 用户 D 公钥
+        baseMint: baseMint, // 基础代币 Mint
+        quoteMint: quoteMint, // 报价代币 Mint
+        tokenProgram: TOKEN_2022_PROGRAM_ID, // Token 2022 程序
+        systemProgram: SystemProgram.programId, // 系统程序
+        rent: anchor.web3.SYSVAR_RENT_PUBKEY, // 租金账户
+      }
+      const cancelAccountsD = {
+        market: marketPda, // 市场账户
+        bids: bidsPda, // 买单簿
+        asks: asksPda, // 卖单簿
+        openOrders: openOrdersD, // 用户 D 的开放订单账户
+        owner: userD.publicKey, // 用户 D 公钥
+      }
+
+      // 用户 D 以 200 USDC 挂出 0.2 SOL 的卖单
+      await program.methods
+        .newLimitOrder({ ask: {} }, new BN(200 * 100), new BN(0.2 * 1e9), { limit: {} }, { decrementAndCancel: {} }, new BN(0))
+        .accounts(accountsD)
+        .signers([userD]) // 用户 D 签名
+        .rpc() // 发送交易
+      const openOrdersDBefore = await program.account.openOrders.fetch(openOrdersD) // 获取用户 D 的开放订单账户
+      const orderId = openOrdersDBefore.orders[0].orderId // 卖单的订单 ID
+
+      // 将数量减少到 0.1 SOL，订单 ID 不变
+      await program.methods
+        .amendOrder(orderId, new BN(0.1 * 1e9))
+        .accounts(cancelAccountsD)
+        .signers([userD]) // 用户 D 签名
+        .rpc() // 发送交易
+      const bestAsk = await fetchBestOrder(asksPda, false) // 获取卖单簿中的最优订单
+      expect(bestAsk.orderId.eq(orderId)).toBe(true) // 验证仍是同一个订单
+      expect(bestAsk.baseQty.eq(new BN(0.1 * 1e9))).toBe(true) // 验证数量已减少
+      let openOrdersDAccount = await program.account.openOrders.fetch(openOrdersD) // 获取用户 D 的开放订单账户
+      expect(openOrdersDAccount.baseTokenLocked.eq(openOrdersDBefore.baseTokenLocked.sub(new BN(0.1 * 1e9)))).toBe(true) // 验证解锁减少的部分
+      expect(openOrdersDAccount.baseTokenFree.eq(openOrdersDBefore.baseTokenFree.add(new BN(0.1 * 1e9)))).toBe(true) // 验证解锁到可用余额
+
+      // 不能通过修改增加订单数量
+      await expect(
+        program.methods
+          .amendOrder(orderId, new BN(0.3 * 1e9))
+          .accounts(cancelAccountsD)
+          .signers([userD]) // 用户 D 签名
+          .rpc(),
+      ).rejects.toThrow('Order input is invalid.') // 验证抛出无效订单输入错误
+
+      // 以 205 USDC 替换该订单，所需的基础代币完全来自被取消订单解锁的资金
+      const baseBefore = await getAccount(connection, userDBaseTokenAccount, undefined, TOKEN_2022_PROGRAM_ID) // 替换前的基础代币余额
+      await program.methods
+        .replaceOrder(orderId, {
+          side: { ask: {} },
+          price: new BN(205 * 100),
+          quantity: new BN(0.1 * 1e9),
+          orderType: { limit: {} },
+          selfTradeBehavior: { decrementAndCancel: {} },
+          clientOrderId: new BN(0),
+        })
+        .accounts(accountsD)
+        .signers([userD]) // 用户 D 签名
+        .rpc() // 发送交易
+      const baseAfter = await getAccount(connection, userDBaseTokenAccount, undefined, TOKEN_2022_PROGRAM_ID) // 替换后的基础代币余额
+      expect(baseAfter.amount).toBe(baseBefore.amount) // 验证没有发生代币转账
+
+      // 验证旧订单已移除，新订单以新价格挂单
+      const replacedAsk = await fetchBestOrder(asksPda, false) // 获取卖单簿中的最优订单
+      expect(replacedAsk.orderId.eq(orderId)).toBe(false) // 验证分配了新的订单 ID
+      expect(replacedAsk.price.eq(new BN(205 * 100))).toBe(true) // 验证新价格
+      expect((await program.account.orderBook.fetch(asksPda)).count).toBe(1) // 验证卖单簿只有一个订单
+      openOrdersDAccount = await program.account.openOrders.fetch(openOrdersD) // 获取用户 D 的开放订单账户
+      expect(openOrdersDAccount.orders.length).toBe(1) // 验证只有一个活动订单
+      expect(openOrdersDAccount.baseTokenFree.eq(openOrdersDBefore.baseTokenFree)).toBe(true) // 验证新订单使用了解锁到可用余额的资金
+
+      // 清理：取消全部订单
+      await program.methods
+        .cancelAllOrders(null, null)
+        .accounts(cancelAccountsD)
+        .signers([userD]) // 用户 D 签名
+        .rpc() // 发送交易
+    })
+
+    // 测试用例：存入资金后下单优先使用可用余额
+    it('✅ Deposit: orders draw from free balances before transferring tokens', async () => {
+      const depositAccountsD = {
+        market: marketPda, // 市场账户
+        openOrders: openOrdersD, // 用户 D 的开放订单账户
+        owner: userD.publicKey, // 用户 D 公钥
+        baseVault: baseVaultPda, // 基础代币金库
+        quoteVault: quoteVaultPda, // 报价代币金库
+        userBaseTokenAccount: userDBaseTokenAccount, // 用户 D 的基础代币账户
+        userQuoteTokenAccount: userDQuoteTokenAccount, // 用户 D 的报价代币账户
+        baseMint: baseMint, // 基础代币 Mint
+        quoteMint: quoteMint, // 报价代币 Mint
+        tokenProgram: TOKEN_2022_PROGRAM_ID, // Token 2022 程序
+        systemProgram: SystemProgram.programId, // 系统程序
+      }
+
+      // 存入数量不能全为 0
+      await expect(
+        program.methods
+          .deposit(new BN(0), new BN(0))
+          .accounts(depositAccountsD)
+          .signers([userD]) // 用户 D 签名
+          .rpc(),
+      ).rejects.toThrow('Amount must be greater than zero.') // 验证抛出无效数量错误
+
+      // 用户 D 存入 0.5 SOL 和 10 USDC
+      const openOrdersDBefore = await program.account.openOrders.fetch(openOrdersD) // 获取用户 D 的开放订单账户
+      const baseBefore = await getAccount(connection, userDBaseTokenAccount, undefined, TOKEN_2022_PROGRAM_ID) // 存入前的基础代币余额
+      await program.methods
+        .deposit(new BN(0.5 * 1e9), new BN(10 * 1e6))
+        .accounts(depositAccountsD)
+        .signers([userD]) // 用户 D 签名
+        .rpc() // 发送交易
+      const baseAfterDeposit = await getAccount(connection, userDBaseTokenAccount, undefined, TOKEN_2022_PROGRAM_ID) // 存入后的基础代币余额
+      expect(baseAfterDeposit.amount).toBe(baseBefore.amount - BigInt(0.5 * 1e9)) // 验证基础代币已转出
+      let openOrdersDAccount = await program.account.openOrders.fetch(openOrdersD) // 获取用户 D 的开放订单账户
+      expect(openOrdersDAccount.baseTokenFree.eq(openOrdersDBefore.baseTokenFree.add(new BN(0.5 * 1e9)))).toBe(true) // 验证计入可用基础代币
+      expect(openOrdersDAccount.quoteTokenFree.eq(openOrdersDBefore.quoteTokenFree.add(new BN(10 * 1e6)))).toBe(true) // 验证计入可用报价代币
+
+      // 用户 D 以 300 USDC 挂出 0.5 SOL 的卖单，资金来自可用余额
+      await program.methods
+        .newLimitOrder({ ask: {} }, new BN(300 * 100), new BN(0.5 * 1e9), { limit: {} }, { decrementAndCancel: {} }, new BN(0))
+        .accounts({
+          market: marketPda, // 市场账户
+          bids: bidsPda, // 买单簿
+          asks: asksPda, // 卖单簿
+          eventQueue: eventQueuePda, // 事件队列
+          openOrders: openOrdersD, // 用户 D 的开放订单账户
+          baseVault: baseVaultPda, // 基础代币金库
+          quoteVault: quoteVaultPda, // 报价代币金库
+          feeVault: feeVaultPda, // 费用金库
+          userBaseTokenAccount: userDBaseTokenAccount, // 用户 D 的基础代币账户
+          userQuoteTokenAccount: userDQuoteTokenAccount, // 用户 D 的报价代币账户
+          owner: userD.publicKey, // 用户 D 公钥
+          baseMint: baseMint, // 基础代币 Mint
+          quoteMint: quoteMint, // 报价代币 Mint
+          tokenProgram: TOKEN_2022_PROGRAM_ID, // Token 2022 程序
+          systemProgram: SystemProgram.programId, // 系统程序
+          rent: anchor.web3.SYSVAR_RENT_PUBKEY, // 租金账户
+        })
+        .signers([userD]) // 用户 D 签名
+        .rpc() // 发送交易
+      const baseAfterOrder = await getAccount(connection, userDBaseTokenAccount, undefined, TOKEN_2022_PROGRAM_ID) // 下单后的基础代币余额
+      expect(baseAfterOrder.amount).toBe(baseAfterDeposit.amount) // 验证没有发生代币转账
+      openOrdersDAccount = await program.account.openOrders.fetch(openOrdersD) // 获取用户 D 的开放订单账户
+      expect(openOrdersDAccount.baseTokenFree.eq(openOrdersDBefore.baseTokenFree)).toBe(true) // 验证可用余额被使用
+      expect(openOrdersDAccount.baseTokenLocked.eq(openOrdersDBefore.baseTokenLocked.add(new BN(0.5 * 1e9)))).toBe(true) // 验证锁定余额增加
+
+      // 清理：取消全部订单
+      await program.methods
+        .cancelAllOrders(null, null)
+        .accounts({
+          market: marketPda, // 市场账户
+          bids: bidsPda, // 买单簿
+          asks: asksPda, // 卖单簿
+          openOrders: openOrdersD, // 用户 D 的开放订单账户
+          owner: userD.publicKey, // 用户 D 公钥
+        })
+        .signers([userD]) // 用户 D 签名
+        .rpc() // 发送交易
+    })
+
+    // 测试用例：部分提取可用余额
+    it('✅ Withdraw: partially withdraws a free balance and keeps the rest in the market', async () => {
+      const withdrawAccountsD = {
+        market: marketPda, // 市场账户
+        openOrders: openOrdersD, // 用户 D 的开放订单账户
+        owner: userD.publicKey, // 用户 D 公钥
+        baseVault: baseVaultPda, // 基础代币金库
+        quoteVault: quoteVaultPda, // 报价代币金库
+        userBaseTokenAccount: userDBaseTokenAccount, // 用户 D 的基础代币账户
+        userQuoteTokenAccount: userDQuoteTokenAccount, // 用户 D 的报价代币账户
+        baseMint: baseMint, // 基础代币 Mint
+        quoteMint: quoteMint, // 报价代币 Mint
+        tokenProgram: TOKEN_2022_PROGRAM_ID, // Token 2022 程序
+      }
+      const openOrdersDBefore = await program.account.openOrders.fetch(openOrdersD) // 获取用户 D 的开放订单账户
+      const quoteBefore = await getAccount(connection, userDQuoteTokenAccount, undefined, TOKEN_2022_PROGRAM_ID) // 提取前的报价代币余额
+
+      // 提取 5 USDC 的报价代币
+      await program.methods
+        .withdraw({ bid: {} }, new BN(5 * 1e6))
+        .accounts(withdrawAccountsD)
+        .signers([userD]) // 用户 D 签名
+        .rpc() // 发送交易
+
+      // 验证钱包余额增加，可用余额相应减少，基础代币不受影响
+      const quoteAfter = await getAccount(connection, userDQuoteTokenAccount, undefined, TOKEN_2022_PROGRAM_ID) // 提取后的报价代币余额
+      expect(quoteAfter.amount - quoteBefore.amount).toBe(BigInt(5 * 1e6)) // 验证转入钱包
+      const openOrdersDAfter = await program.account.openOrders.fetch(openOrdersD) // 获取用户 D 的开放订单账户
+      expect(openOrdersDAfter.quoteTokenFree.eq(openOrdersDBefore.quoteTokenFree.sub(new BN(5 * 1e6)))).toBe(true) // 验证可用余额减少
+      expect(openOrdersDAfter.baseTokenFree.eq(openOrdersDBefore.baseTokenFree)).toBe(true) // 验证基础代币可用余额不变
+
+      // 提取超过可用余额的数量，期望失败
+      await expect(
+        program.methods
+          .withdraw({ ask: {} }, openOrdersDAfter.baseTokenFree.add(new BN(1)))
+          .accounts(withdrawAccountsD)
+          .signers([userD]) // 用户 D 签名
+          .rpc(),
+      ).rejects.toThrow('Insufficient free balance in the OpenOrders account.') // 验证抛出余额不足错误
+    })
+
+    // 测试用例：批量下单
+    it('✅ Batch place: place_orders inserts a ladder of orders on both sides', async () => {
+      const accountsD = {
+        market: marketPda, // 市场账户
+        bids: bidsPda, // 买单簿
+        asks: asksPda, // 卖单簿
+        eventQueue: eventQueuePda, // 事件队列
+        openOrders: openOrdersD, // 用户 D 的开放订单账户
+        baseVault: baseVaultPda, // 基础代币金库
+        quoteVault: quoteVaultPda, // 报价代币金库
+        feeVault: feeVaultPda, // 费用金库
+        userBaseTokenAccount: userDBaseTokenAccount, // 用户 D 的基础代币账户
+        userQuoteTokenAccount: userDQuoteTokenAccount, // 用户 D 的报价代币账户
+        owner: userD.publicKey, // 用户 D 公钥
+        baseMint: baseMint, // 基础代币 Mint
+        quoteMint: quoteMint, // 报价代币 Mint
+        tokenProgram: TOKEN_2022_PROGRAM_ID, // Token 2022 程序
+        systemProgram: SystemProgram.programId, // 系统程序
+        rent: anchor.web3.SYSVAR_RENT_PUBKEY, // 租金账户
+      }
+      // 构造订单参数
+      const order = (side: object, price: number) => ({
+        side,
+        price: new BN(price * 100),
+        quantity: new BN(0.1 * 1e9),
+        orderType: { postOnly: {} },
+        selfTradeBehavior: { decrementAndCancel: {} },
+        clientOrderId: new BN(0),
+      })
+      const openOrdersDBefore = await program.account.openOrders.fetch(openOrdersD) // 获取用户 D 的开放订单账户
+      const bidsCountBefore = (await program.account.orderBook.fetch(bidsPda)).count // 买单簿中已有的订单数量
+
+      // 任一订单不符合 tick_size 时整批失败
+      await expect(
+        program.methods
+          .placeOrders([order({ ask: {} }, 300), { ...order({ ask: {} }, 301), price: new BN(301 * 100 + 1) }])
+          .accounts(accountsD)
+          .signers([userD]) // 用户 D 签名
+          .rpc(),
+      ).rejects.toThrow() // 验证整批被拒绝
+      expect((await program.account.orderBook.fetch(asksPda)).count).toBe(0) // 验证没有订单被挂出
+
+      // 一次挂出三个卖单和两个买单
+      await program.methods
+        .placeOrders([
+          order({ ask: {} }, 300),
+          order({ ask: {} }, 301),
+          order({ ask: {} }, 302),
+          order({ bid: {} }, 50),
+          order({ bid: {} }, 51),
+        ])
+        .accounts(accountsD)
+        .signers([userD]) // 用户 D 签名
+        .rpc() // 发送交易
+
+      // 验证订单全部挂出，资金按方向汇总锁定
+      expect((await program.account.orderBook.fetch(asksPda)).count).toBe(3) // 验证三个卖单
+      expect((await fetchBestOrder(asksPda, false)).price.eq(new BN(300 * 100))).toBe(true) // 验证最优卖价
+      expect((await program.account.orderBook.fetch(bidsPda)).count).toBe(bidsCountBefore + 2) // 验证两个买单
+      const openOrdersDAfter = await program.account.openOrders.fetch(openOrdersD) // 获取用户 D 的开放订单账户
+      expect(openOrdersDAfter.orders.length).toBe(openOrdersDBefore.orders.length + 5) // 验证五个活动订单
+      expect(openOrdersDAfter.baseTokenLocked.eq(openOrdersDBefore.baseTokenLocked.add(new BN(0.3 * 1e9)))).toBe(true) // 验证卖单锁定的基础代币
+
+      // 清理：取消全部订单
+      await program.methods
+        .cancelAllOrders(null, null)
+        .accounts({
+          market: marketPda, // 市场账户
+          bids: bidsPda, // 买单簿
+          asks: asksPda, // 卖单簿
+          openOrders: openOrdersD, // 用户 D 的开放订单账户
+          owner: userD.publicKey, // 用户 D 公钥
+        })
+        .signers([userD]) // 用户 D 签名
+        .rpc() // 发送交易
+    })
+
+    // 测试用例：最小下单量和最小名义价值
+    it('✅ Order size limits: dust orders are rejected after the admin raises the minimums', async () => {
+      const accountsD = {
+        market: marketPda, // 市场账户
+        bids: bidsPda, // 买单簿
+        asks: asksPda, // 卖单簿
+        eventQueue: eventQueuePda, // 事件队列
+        openOrders: openOrdersD, // 用户 D 的开放订单账户
+        baseVault: baseVaultPda, // 基础代币金库
+        quoteVault: quoteVaultPda, // 报价代币金库
+        feeVault: feeVaultPda, // 费用金库
+        userBaseTokenAccount: userDBaseTokenAccount, // 用户 D 的基础代币账户
+        userQuoteTokenAccount: userDQuoteTokenAccount, // 用户 D 的报价代币账户
+        owner: userD.publicKey, // 用户 D 公钥
+        baseMint: baseMint, // 基础代币 Mint
+        quoteMint: quoteMint, // 报价代币 Mint
+        tokenProgram: TOKEN_2022_PROGRAM_ID, // Token 2022 程序
+        systemProgram: SystemProgram.programId, // 系统程序
+        rent: anchor.web3.SYSVAR_RENT_PUBKEY, // 租金账户
+      }
+      // 设置下单限制
+      const setLimits = (minBaseQty: BN, minNotional: BN, signer: Keypair) =>
+        program.methods
+          .setOrderSizeLimits(minBaseQty, minNotional)
+          .accounts({
+            market: marketPda, // 市场账户
+            authority: signer.publicKey, // 签名者公钥
+          })
+          .signers([signer])
+          .rpc()
+
+      // 非管理员不能修改下单限制
+      await expect(setLimits(new BN(1), new BN(1), userD)).rejects.toThrow() // 验证非管理员被拒绝
+
+      // 用户 D 在提高限制之前以 300 USDC 挂出 0.2 SOL 的卖单
+      await program.methods
+        .newLimitOrder({ ask: {} }, new BN(300 * 100), new BN(0.2 * 1e9), { limit: {} }, { decrementAndCancel: {} }, new BN(0))
+        .accounts(accountsD)
+        .signers([userD]) // 用户 D 签名
+        .rpc() // 发送交易
+      const restingOrderId = (await program.account.openOrders.fetch(openOrdersD)).orders.find((o) =>
+        o.price.eq(new BN(300 * 100)),
+      )!.orderId // 该卖单的订单 ID
+      const cancelAccountsD = {
+        market: marketPda, // 市场账户
+        bids: bidsPda, // 买单簿
+        asks: asksPda, // 卖单簿
+        openOrders: openOrdersD, // 用户 D 的开放订单账户
+        owner: userD.publicKey, // 用户 D 公钥
+      }
+
+      // 最小下单量提高到 1 SOL 后，0.1 SOL 的订单被拒绝
+      await setLimits(new BN(1 * 1e9), new BN(1), authority)
+      let marketAccount = await program.account.market.fetch(marketPda) // 获取市场账户数据
+      expect(marketAccount.minBaseQty.eq(new BN(1 * 1e9))).toBe(true) // 验证最小下单量已更新
+      await expect(
+        program.methods
+          .newLimitOrder({ ask: {} }, new BN(300 * 100), new BN(0.1 * 1e9), { limit: {} }, { decrementAndCancel: {} }, new BN(0))
+          .accounts(accountsD)
+          .signers([userD]) // 用户 D 签名
+          .rpc(),
+      ).rejects.toThrow('Below minimal base quantity.') // 验证抛出低于最小下单量错误
+
+      // 修改订单同样不能把数量减到最小下单量以下
+      await expect(
+        program.methods
+          .amendOrder(restingOrderId, new BN(0.1 * 1e9))
+          .accounts(cancelAccountsD)
+          .signers([userD]) // 用户 D 签名
+          .rpc(),
+      ).rejects.toThrow('Below minimal base quantity.') // 验证抛出低于最小下单量错误
+
+      // 最小名义价值高于订单价值时，订单被拒绝
+      await setLimits(new BN(1), new BN(1_000_000 * 1e6), authority)
+      await expect(
+        program.methods
+          .newLimitOrder({ ask: {} }, new BN(300 * 100), new BN(0.1 * 1e9), { limit: {} }, { decrementAndCancel: {} }, new BN(0))
+          .accounts(accountsD)
+          .signers([userD]) // 用户 D 签名
+          .rpc(),
+      ).rejects.toThrow('Below minimal notional') // 验证抛出低于最小名义价值错误
+
+      // 恢复默认限制
+      await setLimits(new BN(1), new BN(1), authority)
+      marketAccount = await program.account.market.fetch(marketPda) // 获取市场账户数据
+      expect(marketAccount.minNotional.eq(new BN(1))).toBe(true) // 验证最小名义价值已恢复
+
+      // 清理：取消该卖单
+      await program.methods
+        .cancelLimitOrder(restingOrderId)
+        .accounts(cancelAccountsD)
+        .signers([userD]) // 用户 D 签名
+        .rpc() // 发送交易
+    })
+
+    // 测试用例：修改价格精度和数量精度
+    it('✅ Market params: admin updates tick and lot sizes and resting orders are grandfathered', async () => {
+      const accountsD = {
+        market: marketPda, // 市场账户
+        bids: bidsPda, // 买单簿
+        asks: asksPda, // 卖单簿
+        eventQueue: eventQueuePda, // 事件队列
+        openOrders: openOrdersD, // 用户 D 的开放订单账户
+        baseVault: baseVaultPda, // 基础代币金库
+        quoteVault: quoteVaultPda, // 报价代币金库
+        feeVault: feeVaultPda, // 费用金库
+        userBaseTokenAccount: userDBaseTokenAccount, // 用户 D 的基础代币账户
+        userQuoteTokenAccount: userDQuoteTokenAccount, // 用户 D 的报价代币账户
+        owner: userD.publicKey, // 用户 D 公钥
+        baseMint: baseMint, // 基础代币 Mint
+        quoteMint: quoteMint, // 报价代币 Mint
+        tokenProgram: TOKEN_2022_PROGRAM_ID, // Token 2022 程序
+        systemProgram: SystemProgram.programId, // 系统程序
+        rent: anchor.web3.SYSVAR_RENT_PUBKEY, // 租金账户
+      }
+      // 设置市场参数
+      const setParams = (newTickSize: BN, newBaseLotSize: BN) =>
+        program.methods
+          .setMarketParams(newTickSize, newBaseLotSize)
+          .accounts({
+            market: marketPda, // 市场账户
+            authority: authority.publicKey, // 管理员公钥
+          })
+          .signers([authority]) // 管理员签名
+          .rpc()
+
+      // 用户 D 按当前精度以 301 USDC 挂出卖单
+      await program.methods
+        .newLimitOrder({ ask: {} }, new BN(301 * 100), new BN(0.1 * 1e9), { limit: {} }, { decrementAndCancel: {} }, new BN(0))
+        .accounts(accountsD)
+        .signers([userD]) // 用户 D 签名
+        .rpc() // 发送交易
+      const restingAsk = await fetchBestOrder(asksPda, false) // 获取卖单簿中的最优订单
+
+      // tick_size 不能为 0
+      await expect(setParams(new BN(0), baseLotSize)).rejects.toThrow('Invalid market config params.') // 验证抛出无效参数错误
+
+      // 将价格精度改为 5.00
+      await setParams(new BN(500), baseLotSize)
+      const marketAccount = await program.account.market.fetch(marketPda) // 获取市场账户数据
+      expect(marketAccount.tickSize.eq(new BN(500))).toBe(true) // 验证价格精度已更新
+
+      // 新订单必须符合新的价格精度
+      await expect(
+        program.methods
+          .newLimitOrder({ ask: {} }, new BN(302 * 100), new BN(0.1 * 1e9), { limit: {} }, { decrementAndCancel: {} }, new BN(0))
+          .accounts(accountsD)
+          .signers([userD]) // 用户 D 签名
+          .rpc(),
+      ).rejects.toThrow('Invalid tick size.') // 验证抛出无效价格精度错误
+
+      // 原有订单保留在订单簿中，并且可以正常取消
+      expect((await fetchBestOrder(asksPda, false)).orderId.eq(restingAsk.orderId)).toBe(true) // 验证原有订单仍在
+      await program.methods
+        .cancelLimitOrder(restingAsk.orderId)
+        .accounts({
+          market: marketPda, // 市场账户
+          bids: bidsPda, // 买单簿
+          asks: asksPda, // 卖单簿
+          openOrders: openOrdersD, // 用户 D 的开放订单账户
+          owner: userD.publicKey, // 用户 D 公钥
+        })
+        .signers([userD]) // 用户 D 签名
+        .rpc() // 发送交易
+      expect((await program.account.orderBook.fetch(asksPda)).count).toBe(0) // 验证卖单簿为空
+
+      // 恢复原有精度
+      await setParams(tickSize, baseLotSize)
+    })
+
+    // 测试用例：市场暂停时无法下单
+    it('✅ Should fail to place order when market is paused', async () => {
+      // 暂停市场
+      await program.methods
+        .setPause(true)
+        .accounts({
+          market: marketPda, // 市场账户
+          authority: authority.publicKey, // 管理员公钥
+        })
+        .signers([authority]) // 管理员签名
+        .rpc() // 发送交易
+
+      // 尝试下单，期望失败
+      await expect(
+        program.methods
+          .newLimitOrder({ ask: {} }, new BN(200 * 100), new BN(1 * 1e9), { limit: {} }, { decrementAndCancel: {} }, new BN(0))
+          .accounts({
+            market: marketPda, // 市场账户
+            bids: bidsPda, // 买单簿
+            asks: asksPda, // 卖单簿
+            eventQueue: eventQueuePda, // 事件队列
+            openOrders: openOrdersD, // 用户 D 的开放订单账户
+            baseVault: baseVaultPda, // 基础代币金库
+            quoteVault: quoteVaultPda, // 报价代币金库
+            feeVault: feeVaultPda, // 费用金库
+            userBaseTokenAccount: userDBaseTokenAccount, // 用户 D 的基础代币账户
+            userQuoteTokenAccount: userDQuoteTokenAccount, // 用户 D 的报价代币账户
+            owner: userD.publicKey, // 用户 D 公钥
+            baseMint: baseMint, // 基础代币 Mint
+            quoteMint: quoteMint, // 报价代币 Mint
+            tokenProgram: TOKEN_2022_PROGRAM_ID, // Token 2022 程序
+            systemProgram: SystemProgram.programId, // 系统程序
+            rent: anchor.web3.SYSVAR_RENT_PUBKEY, // 租金账户
+          })
+          .signers([userD]) // 用户 D 签名
+          .rpc(),
+      ).rejects.toThrow('Market is paused.') // 验证抛出“市场暂停”错误
+
+      // 恢复市场
+      await program.methods
+        .setPause(false)
+        .accounts({
+          market: marketPda, // 市场账户
+          authority: authority.publicKey, // 管理员公钥
+        })
+        .signers([authority]) // 管理员签名
+        .rpc() // 发送交易
+    })
+
+    // 测试用例：无法取消他人订单
+    it("✅ Should fail to cancel someone else's order", async () => {
+      // 获取用户 C 的订单 ID
+      const userCOrderId = (await fetchBestOrder(bidsPda, true)).orderId // 获取用户 C 的订单 ID
+
+      // 用户 D 尝试取消用户 C 的订单，期望失败
+      await expect(
+        program.methods
+          .cancelLimitOrder(userCOrderId)
+          .accounts({
+            market: marketPda, // 市场账户
+            bids: bidsPda, // 买单簿
+            asks: asksPda, // 卖单簿
+            openOrders: openOrdersD, // 用户 D 的开放订单账户
+            owner: userD.publicKey, // 用户 D 公钥
+          })
+          .signers([userD]) // 用户 D 签名
+          .rpc(),
+      ).rejects.toThrow("Order ID not found in the user's OpenOrders account.") // 验证订单不在用户 D 的活动订单中
+    })
+
+    // 测试用例：提取与分配手续费
+    it('✅ Fees: admin withdraws fees and anyone distributes the rest by the configured split', async () => {
+      const feesBefore = await balance(feeVaultPda) // 手续费金库余额
+      expect(feesBefore > BigInt(1)).toBe(true) // 之前的成交已产生手续费
+
+      // 非管理员不能提取手续费
+      const withdrawAccounts = (signer: Keypair) => ({
+        market: marketPda, // 市场账户
+        feeVault: feeVaultPda, // 费用金库
+        destination: userAQuoteTokenAccount, // 接收手续费的账户
+        mint: quoteMint, // 手续费金库对应的 Mint
+        authority: signer.publicKey, // 签名者公钥
+        tokenProgram: TOKEN_2022_PROGRAM_ID, // Token 2022 程序
+      })
+      await expect(
+        program.methods.withdrawFees(new BN(1)).accounts(withdrawAccounts(userD)).signers([userD]).rpc(),
+      ).rejects.toThrow() // 验证非管理员被拒绝
+
+      // 管理员提取 1 个最小单位的手续费
+      const destinationBefore = await balance(userAQuoteTokenAccount) // 目标账户余额
+      await program.methods
+        .withdrawFees(new BN(1))
+        .accounts(withdrawAccounts(authority))
+        .signers([authority]) // 管理员签名
+        .rpc() // 发送交易
+      expect((await balance(userAQuoteTokenAccount)) - destinationBefore).toBe(BigInt(1)) // 验证目标账户收到手续费
+      expect(await balance(feeVaultPda)).toBe(feesBefore - BigInt(1)) // 验证金库余额减少
+
+      // 协议金库和质押者账户必须是报价代币账户
+      const setDistribution = (treasury: PublicKey, stakers: PublicKey) =>
+        program.methods
+          .setFeeDistribution(2_500)
+          .accounts({
+            market: marketPda, // 市场账户
+            treasury, // 协议金库
+            stakers, // 质押者账户
+            authority: authority.publicKey, // 管理员公钥
+          })
+          .signers([authority]) // 管理员签名
+          .rpc()
+      await expect(setDistribution(userAQuoteTokenAccount, userBBaseTokenAccount)).rejects.toThrow('Invalid mint account.') // 验证拒绝基础代币账户
+
+      // 配置分配：25% 给质押者，其余给协议金库
+      await setDistribution(userAQuoteTokenAccount, userBQuoteTokenAccount)
+      const marketAccount = await program.account.market.fetch(marketPda) // 获取市场账户数据
+      expect(marketAccount.feeTreasury.equals(userAQuoteTokenAccount)).toBe(true) // 验证协议金库地址
+      expect(marketAccount.feeStakers.equals(userBQuoteTokenAccount)).toBe(true) // 验证质押者账户地址
+
+      // 分配金库中的全部手续费
+      const remaining = await balance(feeVaultPda) // 待分配的手续费
+      const treasuryBefore = await balance(userAQuoteTokenAccount) // 协议金库余额
+      const stakersBefore = await balance(userBQuoteTokenAccount) // 质押者账户余额
+      await program.methods
+        .distributeFees()
+        .accounts({
+          market: marketPda, // 市场账户
+          feeVault: feeVaultPda, // 费用金库
+          treasury: userAQuoteTokenAccount, // 协议金库
+          stakers: userBQuoteTokenAccount, // 质押者账户
+          quoteMint: quoteMint, // 报价代币 Mint
+          tokenProgram: TOKEN_2022_PROGRAM_ID, // Token 2022 程序
+        })
+        .rpc() // 发送交易
+      const stakersAmount = (remaining * BigInt(2_500)) / BigInt(10_000) // 质押者的份额
+      expect((await balance(userBQuoteTokenAccount)) - stakersBefore).toBe(stakersAmount) // 验证质押者份额
+      expect((await balance(userAQuoteTokenAccount)) - treasuryBefore).toBe(remaining - stakersAmount) // 验证协议金库份额
+      expect(await balance(feeVaultPda)).toBe(BigInt(0)) // 验证金库已清空
+    })
+
+    // 测试用例：买方 taker 手续费以基础代币收取
+    it('✅ Taker fee mode: buyers pay the taker fee in base into the base fee vault', async () => {
+      // 切换为以收到的资产收取 taker 手续费
+      await setTakerFeeMode({ receivedAsset: {} })
+      const marketAccount = await program.account.market.fetch(marketPda) // 获取市场账户数据
+      expect(marketAccount.takerFeeMode).toEqual({ receivedAsset: {} }) // 验证收费方式已更新
+      expect(marketAccount.baseFeeVault.equals(baseFeeVaultPda)).toBe(true) // 验证基础代币手续费金库地址
+
+      // 用户 D 以 301 USDC 挂出 0.1 SOL 的卖单
+      await program.methods
+        .newLimitOrder({ ask: {} }, new BN(301 * 100), new BN(0.1 * 1e9), { limit: {} }, { decrementAndCancel: {} }, new BN(0))
+        .accounts(accountsFor(userD, openOrdersD, userDBaseTokenAccount, userDQuoteTokenAccount))
+        .signers([userD]) // 用户 D 签名
+        .rpc() // 发送交易
+
+      // 买方吃单时必须提供基础代币手续费金库
+      const takerBid = () =>
+        program.methods.newLimitOrder({ bid: {} }, new BN(301 * 100), new BN(0.1 * 1e9), { immediateOrCancel: {} }, { decrementAndCancel: {} }, new BN(0))
+      await expect(
+        takerBid()
+          .accounts(accountsFor(userC, openOrdersC, userCBaseTokenAccount, userCQuoteTokenAccount))
+          .signers([userC]) // 用户 C 签名
+          .rpc(),
+      ).rejects.toThrow('Base fee vault account is required.') // 验证抛出缺少基础代币手续费金库错误
+
+      // 用户 C 以 IOC 买单吃掉卖单，手续费从收到的基础代币中扣除
+      const openOrdersCBefore = await program.account.openOrders.fetch(openOrdersC) // 获取用户 C 的开放订单账户
+      const baseFeesBefore = await balance(baseFeeVaultPda) // 基础代币手续费金库余额
+      await takerBid()
+        .accounts({ ...accountsFor(userC, openOrdersC, userCBaseTokenAccount, userCQuoteTokenAccount), baseFeeVault: baseFeeVaultPda })
+        .signers([userC]) // 用户 C 签名
+        .rpc() // 发送交易
+      await consumeEvents([openOrdersD])
+      const takerFee = new BN(0.1 * 1e9).muln(marketAccount.takerFeeBps).divn(10_000) // 以基础代币计的 taker 手续费
+      const openOrdersCAfter = await program.account.openOrders.fetch(openOrdersC) // 获取用户 C 的开放订单账户
+      expect(openOrdersCAfter.baseTokenFree.sub(openOrdersCBefore.baseTokenFree).eq(new BN(0.1 * 1e9).sub(takerFee))).toBe(true) // 验证收到扣费后的基础代币
+      expect((await balance(baseFeeVaultPda)) - baseFeesBefore).toBe(BigInt(takerFee.toString())) // 验证手续费进入基础代币手续费金库
+
+      // 管理员可以从基础代币手续费金库中提取手续费
+      await program.methods
+        .withdrawFees(new BN(1))
+        .accounts({
+          market: marketPda, // 市场账户
+          feeVault: baseFeeVaultPda, // 基础代币手续费金库
+          destination: userCBaseTokenAccount, // 接收手续费的账户
+          mint: baseMint, // 基础代币 Mint
+          authority: authority.publicKey, // 管理员公钥
+          tokenProgram: TOKEN_2022_PROGRAM_ID, // Token 2022 程序
+        })
+        .signers([authority]) // 管理员签名
+        .rpc() // 发送交易
+      expect(await balance(baseFeeVaultPda)).toBe(baseFeesBefore + BigInt(takerFee.toString()) - BigInt(1)) // 验证金库余额减少
+
+      // 任何人都可以把基础代币手续费分给协议金库和质押者账户的所有者（用户 A 和用户 B）
+      const distributeBaseFees = (baseTreasury: PublicKey) =>
+        program.methods
+          .distributeBaseFees()
+          .accounts({
+            market: marketPda, // 市场账户
+            baseFeeVault: baseFeeVaultPda, // 基础代币手续费金库
+            treasury: userAQuoteTokenAccount, // 已配置的协议金库
+            stakers: userBQuoteTokenAccount, // 已配置的质押者账户
+            baseTreasury, // 协议金库的基础代币账户
+            baseStakers: userBBaseTokenAccount, // 质押者的基础代币账户
+            baseMint: baseMint, // 基础代币 Mint
+            tokenProgram: TOKEN_2022_PROGRAM_ID, // Token 2022 程序
+          })
+          .rpc()
+      await expect(distributeBaseFees(userCBaseTokenAccount)).rejects.toThrow('Unauthorized action.') // 验证拒绝其他所有者的账户
+      const baseRemaining = await balance(baseFeeVaultPda) // 待分配的基础代币手续费
+      const baseStakersBefore = await balance(userBBaseTokenAccount) // 质押者的基础代币余额
+      const baseTreasuryBefore = await balance(userABaseTokenAccount) // 协议金库的基础代币余额
+      await distributeBaseFees(userABaseTokenAccount)
+      const baseStakersAmount = (baseRemaining * BigInt(2_500)) / BigInt(10_000) // 质押者的份额
+      expect((await balance(userBBaseTokenAccount)) - baseStakersBefore).toBe(baseStakersAmount) // 验证质押者份额
+      expect((await balance(userABaseTokenAccount)) - baseTreasuryBefore).toBe(baseRemaining - baseStakersAmount) // 验证协议金库份额
+      expect(await balance(baseFeeVaultPda)).toBe(BigInt(0)) // 验证金库已清空
+
+      // 恢复以报价代币收费
+      await setTakerFeeMode({ quote: {} })
+    })
+
+    // 测试用例：负的 maker 费率（maker 返佣）
+    it('✅ Maker rebates: negative maker fees are paid to makers out of the taker fee', async () => {
+      const { makerFeeBps, takerFeeBps } = await program.account.market.fetch(marketPda) // 当前的手续费率
+
+      // maker 返佣率不能超过 taker 费率
+      await expect(setFees(-(takerFeeBps + 1), takerFeeBps)).rejects.toThrow('Maker rebate cannot exceed the taker fee.') // 验证抛出返佣过高错误
+
+      // 设置 0.2% 的 maker 返佣
+      const rebateBps = 20 // maker 返佣率
+      await setFees(-rebateBps, takerFeeBps)
+      expect((await program.account.market.fetch(marketPda)).makerFeeBps).toBe(-rebateBps) // 验证 maker 费率为负数
+
+      // 存在返佣时，taker 手续费不能改为以基础代币收取
+      await expect(setTakerFeeMode({ receivedAsset: {} })).rejects.toThrow('Maker rebates require taker fees charged in quote.') // 验证抛出返佣需要报价代币收费错误
+
+      // 用户 D 以 301 USDC 挂出 0.1 SOL 的卖单，用户 C 以 IOC 买单吃掉
+      const price = new BN(301 * 100) // 成交价格
+      const quantity = new BN(0.1 * 1e9) // 成交数量
+      await program.methods
+        .newLimitOrder({ ask: {} }, price, quantity, { limit: {} }, { decrementAndCancel: {} }, new BN(0))
+        .accounts(accountsFor(userD, openOrdersD, userDBaseTokenAccount, userDQuoteTokenAccount))
+        .signers([userD]) // 用户 D 签名
+        .rpc() // 发送交易
+      const openOrdersDBefore = await program.account.openOrders.fetch(openOrdersD) // 获取用户 D 的开放订单账户
+      const feesBefore = await balance(feeVaultPda) // 手续费金库余额
+      await program.methods
+        .newLimitOrder({ bid: {} }, price, quantity, { immediateOrCancel: {} }, { decrementAndCancel: {} }, new BN(0))
+        .accounts(accountsFor(userC, openOrdersC, userCBaseTokenAccount, userCQuoteTokenAccount))
+        .signers([userC]) // 用户 C 签名
+        .rpc() // 发送交易
+      await consumeEvents([openOrdersD])
+
+      // maker 收到成交额加返佣，手续费金库只收到扣除返佣后的 taker 手续费
+      const quoteQty = price.mul(quantity).divn(1e6) // 成交额
+      const rebate = quoteQty.muln(rebateBps).divn(10_000) // maker 返佣
+      const takerFee = quoteQty.muln(takerFeeBps).divn(10_000) // taker 手续费
+      const openOrdersDAfter = await program.account.openOrders.fetch(openOrdersD) // 获取用户 D 的开放订单账户
+      expect(openOrdersDAfter.quoteTokenFree.sub(openOrdersDBefore.quoteTokenFree).eq(quoteQty.add(rebate))).toBe(true) // 验证 maker 收到返佣
+      expect((await balance(feeVaultPda)) - feesBefore).toBe(BigInt(takerFee.sub(rebate).toString())) // 验证手续费金库的净收入
+
+      // 恢复原有手续费率
+      await setFees(makerFeeBps, takerFeeBps)
+    })
+
+    // 测试用例：按持仓或交易量划分的手续费等级
+    it('✅ Fee tiers: takers pay their tier rate and fills count towards their 30-day volume', async () => {
+      // 设置手续费等级
+      const setTiers = (tiers: any[], signer: Keypair) =>
+        program.methods
+          .setFeeTiers(baseMint, tiers)
+          .accounts({
+            market: marketPda, // 市场账户
+            feeTiers: feeTiersPda, // 手续费等级账户
+            authority: signer.publicKey, // 签名者公钥
+            systemProgram: SystemProgram.programId, // 系统程序
+          })
+          .signers([signer])
+          .rpc()
+      // 持有任意数量基础代币的用户 taker 费率为 0.1%
+      const tierTakerFeeBps = 10 // 等级的 taker 费率
+      const holderTier = { minVolume: new BN(0), minHolding: new BN(1), makerFeeBps: 0, takerFeeBps: tierTakerFeeBps }
+
+      // 非管理员不能配置手续费等级
+      await expect(setTiers([holderTier], userD)).rejects.toThrow() // 验证非管理员被拒绝
+      await setTiers([holderTier], authority)
+      const feeTiersAccount = await program.account.feeTiers.fetch(feeTiersPda) // 获取手续费等级账户
+      expect(feeTiersAccount.holdingMint.equals(baseMint)).toBe(true) // 验证持仓门槛的代币
+      expect(feeTiersAccount.tiers.length).toBe(1) // 验证等级数量
+
+      // 配置了等级后，下单不能省略等级账户
+      const { makerFeeBps } = await program.account.market.fetch(marketPda) // 市场统一的 maker 费率
+      const price = new BN(301 * 100) // 成交价格
+      const quantity = new BN(0.1 * 1e9) // 成交数量
+      const makerAsk = () =>
+        program.methods.newLimitOrder({ ask: {} }, price, quantity, { limit: {} }, { decrementAndCancel: {} }, new BN(0))
+      await expect(
+        makerAsk()
+          .accounts(accountsFor(userD, openOrdersD, userDBaseTokenAccount, userDQuoteTokenAccount))
+          .signers([userD]) // 用户 D 签名
+          .rpc(),
+      ).rejects.toThrow('Fee tiers account is required.') // 验证抛出缺少等级账户错误
+
+      // 用户 D 传入等级账户但没有持仓，不满足任何等级，按市场统一的 maker 费率计费
+      await makerAsk()
+        .accounts({ ...accountsFor(userD, openOrdersD, userDBaseTokenAccount, userDQuoteTokenAccount), feeTiers: feeTiersPda })
+        .signers([userD]) // 用户 D 签名
+        .rpc() // 发送交易
+
+      // 用户 C 传入等级账户和持仓账户吃单，按等级费率支付 taker 手续费
+      const feesBefore = await balance(feeVaultPda) // 手续费金库余额
+      const openOrdersCBefore = await program.account.openOrders.fetch(openOrdersC) // 获取用户 C 的开放订单账户
+      await program.methods
+        .newLimitOrder({ bid: {} }, price, quantity, { immediateOrCancel: {} }, { decrementAndCancel: {} }, new BN(0))
+        .accounts({
+          ...accountsFor(userC, openOrdersC, userCBaseTokenAccount, userCQuoteTokenAccount),
+          feeTiers: feeTiersPda, // 手续费等级账户
+          holdingTokenAccount: userCBaseTokenAccount, // 用户 C 持有的基础代币
+        })
+        .signers([userC]) // 用户 C 签名
+        .rpc() // 发送交易
+      await consumeEvents([openOrdersD])
+      const quoteQty = price.mul(quantity).divn(1e6) // 成交额
+      const expectedFees = quoteQty.muln(tierTakerFeeBps).divn(10_000).add(quoteQty.muln(makerFeeBps).divn(10_000)) // 等级 taker 手续费 + 统一 maker 手续费
+      expect((await balance(feeVaultPda)) - feesBefore).toBe(BigInt(expectedFees.toString())) // 验证手续费按等级费率收取
+
+      // 成交额计入用户 C 当天的交易量
+      const totalVolume = (account: { dailyVolume: BN[] }) => account.dailyVolume.reduce((sum, v) => sum.add(v), new BN(0)) // 窗口内的交易量
+      const openOrdersCAfter = await program.account.openOrders.fetch(openOrdersC) // 获取用户 C 的开放订单账户
+      expect(totalVolume(openOrdersCAfter).sub(totalVolume(openOrdersCBefore)).eq(quoteQty)).toBe(true) // 验证交易量增加
+
+      // 兑换同样按等级费率计费：用户 D 再次挂出卖单，用户 C 用刚好覆盖成交额和等级手续费的报价代币买入
+      await makerAsk()
+        .accounts({ ...accountsFor(userD, openOrdersD, userDBaseTokenAccount, userDQuoteTokenAccount), feeTiers: feeTiersPda })
+        .signers([userD]) // 用户 D 签名
+        .rpc() // 发送交易
+      const swapAccountsC = {
+        market: marketPda, // 市场账户
+        bids: bidsPda, // 买单簿
+        asks: asksPda, // 卖单簿
+        eventQueue: eventQueuePda, // 事件队列
+        baseVault: baseVaultPda, // 基础代币金库
+        quoteVault: quoteVaultPda, // 报价代币金库
+        feeVault: feeVaultPda, // 费用金库
+        userSourceTokenAccount: userCQuoteTokenAccount, // 用户 C 支付报价代币
+        userDestinationTokenAccount: userCBaseTokenAccount, // 用户 C 接收基础代币
+        owner: userC.publicKey, // 用户 C 公钥
+        baseMint: baseMint, // 基础代币 Mint
+        quoteMint: quoteMint, // 报价代币 Mint
+        tokenProgram: TOKEN_2022_PROGRAM_ID, // Token 2022 程序
+      }
+      const swapAmountIn = quoteQty.add(quoteQty.muln(tierTakerFeeBps).divn(10_000)) // 成交额 + 等级 taker 手续费
+      // 配置了等级后，兑换不能省略等级账户
+      await expect(
+        program.methods.swap(swapAmountIn, quantity).accounts(swapAccountsC).signers([userC]).rpc(),
+      ).rejects.toThrow('Fee tiers account is required.') // 验证抛出缺少等级账户错误
+      const swapFeesBefore = await balance(feeVaultPda) // 手续费金库余额
+      await program.methods
+        .swap(swapAmountIn, quantity)
+        .accounts({
+          ...swapAccountsC,
+          openOrders: openOrdersC, // 用户 C 的开放订单账户，用于统计交易量
+          feeTiers: feeTiersPda, // 手续费等级账户
+          holdingTokenAccount: userCBaseTokenAccount, // 用户 C 持有的基础代币
+        })
+        .signers([userC]) // 用户 C 签名
+        .rpc() // 发送交易
+      await consumeEvents([openOrdersD])
+      expect((await balance(feeVaultPda)) - swapFeesBefore).toBe(BigInt(expectedFees.toString())) // 验证兑换按等级费率收费
+      const openOrdersCSwapped = await program.account.openOrders.fetch(openOrdersC) // 获取用户 C 的开放订单账户
+      expect(totalVolume(openOrdersCSwapped).sub(totalVolume(openOrdersCAfter)).eq(quoteQty)).toBe(true) // 验证兑换的成交额计入交易量
+
+      // 存在含 maker 返佣的等级时，切换收费方式必须传入等级账户，且不能改为以基础代币收取 taker 手续费
+      const rebateTier = { minVolume: new BN(0), minHolding: new BN(1_000 * 1e9), makerFeeBps: -5, takerFeeBps: tierTakerFeeBps }
+      await setTiers([holderTier, rebateTier], authority)
+      await expect(setTakerFeeMode({ receivedAsset: {} })).rejects.toThrow('Fee tiers account is required.') // 验证抛出缺少等级账户错误
+      await expect(setTakerFeeMode({ receivedAsset: {} }, feeTiersPda)).rejects.toThrow(
+        'Maker rebates require taker fees charged in quote.',
+      ) // 验证抛出返佣需要报价代币收费错误
+
+      // 清空手续费等级，恢复市场统一费率
+      await setTiers([], authority)
+    })
+
+    // 测试用例：推荐人分得 taker 手续费
+    it('✅ Referrals: a share of the taker fee is paid to the referrer instead of the fee vault', async () => {
+      // 设置推荐人分成比例
+      const setShare = (shareBps: number, signer: Keypair) =>
+        program.methods
+          .setReferrerShare(shareBps)
+          .accounts({
+            market: marketPda, // 市场账户
+            authority: signer.publicKey, // 签名者公钥
+          })
+          .signers([signer])
+          .rpc()
+
+      // 非管理员不能设置分成比例，比例不能超过 100%
+      await expect(setShare(1_000, userD)).rejects.toThrow() // 验证非管理员被拒绝
+      await expect(setShare(10_001, authority)).rejects.toThrow('Fee bps value is invalid.') // 验证抛出无效费率错误
+
+      // 推荐人分得 20% 的 taker 手续费
+      const shareBps = 2_000 // 推荐人分成比例
+      await setShare(shareBps, authority)
+      expect((await program.account.market.fetch(marketPda)).referrerShareBps).toBe(shareBps) // 验证分成比例已更新
+      const { makerFeeBps, takerFeeBps } = await program.account.market.fetch(marketPda) // 当前的手续费率
+
+      // 用户 D 挂出卖单，用户 C 指定推荐人（用户 A 的报价代币账户）吃单
+      const price = new BN(301 * 100) // 成交价格
+      const quantity = new BN(0.1 * 1e9) // 成交数量
+      await program.methods
+        .newLimitOrder({ ask: {} }, price, quantity, { limit: {} }, { decrementAndCancel: {} }, new BN(0))
+        .accounts(accountsFor(userD, openOrdersD, userDBaseTokenAccount, userDQuoteTokenAccount))
+        .signers([userD]) // 用户 D 签名
+        .rpc() // 发送交易
+      // 用户不能把自己的账户指定为推荐人
+      const takerBid = (referrer: PublicKey) =>
+        program.methods
+          .newLimitOrder({ bid: {} }, price, quantity, { immediateOrCancel: {} }, { decrementAndCancel: {} }, new BN(0))
+          .accounts({
+            ...accountsFor(userC, openOrdersC, userCBaseTokenAccount, userCQuoteTokenAccount),
+            referrer, // 推荐人的报价代币账户
+          })
+          .signers([userC]) // 用户 C 签名
+          .rpc()
+      await expect(takerBid(userCQuoteTokenAccount)).rejects.toThrow('Cannot refer yourself.') // 验证抛出自我推荐错误
+
+      const feesBefore = await balance(feeVaultPda) // 手续费金库余额
+      const referrerBefore = await balance(userAQuoteTokenAccount) // 推荐人余额
+      await takerBid(userAQuoteTokenAccount)
+      await consumeEvents([openOrdersD])
+
+      // 推荐人分得 taker 手续费的 20%，其余手续费进入手续费金库
+      const quoteQty = price.mul(quantity).divn(1e6) // 成交额
+      const takerFee = quoteQty.muln(takerFeeBps).divn(10_000) // taker 手续费
+      const makerFee = quoteQty.muln(makerFeeBps).divn(10_000) // maker 手续费
+      const referrerFee = takerFee.muln(shareBps).divn(10_000) // 推荐人分成
+      expect((await balance(userAQuoteTokenAccount)) - referrerBefore).toBe(BigInt(referrerFee.toString())) // 验证推荐人收到分成
+      expect((await balance(feeVaultPda)) - feesBefore).toBe(BigInt(takerFee.add(makerFee).sub(referrerFee).toString())) // 验证手续费金库的净收入
+
+      // 恢复为不分成
+      await setShare(0, authority)
+    })
+
+    // 测试用例：买单按下单时锁定的金额解锁，不受之后费率调整的影响
+    it('✅ Locked quote snapshot: cancelling a bid after a fee change releases exactly what was locked', async () => {
+      const { makerFeeBps, takerFeeBps } = await program.account.market.fetch(marketPda) // 当前的手续费率
+      // 用户 D 以 100 USDC 挂出 0.1 SOL 的买单（不与卖单交叉）
+      const price = new BN(100 * 100) // 买单价格
+      const quantity = new BN(0.1 * 1e9) // 买单数量
+      const openOrdersDBefore = await program.account.openOrders.fetch(openOrdersD) // 获取用户 D 的开放订单账户
+      await program.methods
+        .newLimitOrder({ bid: {} }, price, quantity, { postOnly: {} }, { decrementAndCancel: {} }, new BN(0))
+        .accounts(accountsFor(userD, openOrdersD, userDBaseTokenAccount, userDQuoteTokenAccount))
+        .signers([userD]) // 用户 D 签名
+        .rpc() // 发送交易
+      const quoteQty = price.mul(quantity).divn(1e6) // 成交额
+      const lockedQuote = quoteQty.add(quoteQty.muln(takerFeeBps).divn(10_000)) // 按下单时的 taker 费率锁定的金额
+      let openOrdersDAfter = await program.account.openOrders.fetch(openOrdersD) // 获取用户 D 的开放订单账户
+      expect(openOrdersDAfter.quoteTokenLocked.sub(openOrdersDBefore.quoteTokenLocked).eq(lockedQuote)).toBe(true) // 验证锁定金额
+      const orderId = openOrdersDAfter.orders[openOrdersDAfter.orders.length - 1].orderId // 新买单的订单 ID
+
+      // 管理员上调 taker 费率后取消买单
+      await setFees(makerFeeBps, takerFeeBps * 2)
+      await program.methods
+        .cancelLimitOrder(orderId)
+        .accounts({
+          market: marketPda, // 市场账户
+          bids: bidsPda, // 买单簿
+          asks: asksPda, // 卖单簿
+          openOrders: openOrdersD, // 用户 D 的开放订单账户
+          owner: userD.publicKey, // 用户 D 公钥
+        })
+        .signers([userD]) // 用户 D 签名
+        .rpc() // 发送交易
+
+      // 解锁的金额与下单时锁定的金额完全一致
+      openOrdersDAfter = await program.account.openOrders.fetch(openOrdersD) // 获取用户 D 的开放订单账户
+      expect(openOrdersDAfter.quoteTokenLocked.eq(openOrdersDBefore.quoteTokenLocked)).toBe(true) // 验证锁定余额恢复
+
+      // 恢复原有手续费率
+      await setFees(makerFeeBps, takerFeeBps)
+    })
+
+    // 测试用例：费率调整后买单作为 maker 成交，按下单时锁定的金额结算
+    it('✅ Locked quote snapshot: a bid filled after a fee change settles from the amount locked at placement', async () => {
+      const { makerFeeBps, takerFeeBps } = await program.account.market.fetch(marketPda) // 当前的手续费率
+
+      // 用户 D 以高于当前最优买价一个 tick 的价格挂出 0.1 SOL 的买单，成为最优买单
+      const bestBid = await fetchBestOrder(bidsPda, true) // 当前的最优买单
+      const price = bestBid ? bestBid.price.add(tickSize) : new BN(100 * 100) // 买单价格
+      const quantity = new BN(0.1 * 1e9) // 买单数量
+      const lockedBefore = (await program.account.openOrders.fetch(openOrdersD)).quoteTokenLocked // 下单前的锁定余额
+      await program.methods
+        .newLimitOrder({ bid: {} }, price, quantity, { postOnly: {} }, { decrementAndCancel: {} }, new BN(0))
+        .accounts(accountsFor(userD, openOrdersD, userDBaseTokenAccount, userDQuoteTokenAccount))
+        .signers([userD]) // 用户 D 签名
+        .rpc() // 发送交易
+      const quoteQty = price.mul(quantity).divn(1e6) // 成交额
+      const lockedQuote = quoteQty.add(quoteQty.muln(takerFeeBps).divn(10_000)) // 按下单时的 taker 费率锁定的金额
+      const openOrdersDPlaced = await program.account.openOrders.fetch(openOrdersD) // 下单后的开放订单账户
+      expect(openOrdersDPlaced.quoteTokenLocked.sub(lockedBefore).eq(lockedQuote)).toBe(true) // 验证锁定金额
+
+      // 管理员上调 taker 费率后，用户 C 以 IOC 卖单吃掉该买单，再由 crank 结算 maker
+      await setFees(makerFeeBps, takerFeeBps * 2)
+      await program.methods
+        .newLimitOrder({ ask: {} }, price, quantity, { immediateOrCancel: {} }, { decrementAndCancel: {} }, new BN(0))
+        .accounts(accountsFor(userC, openOrdersC, userCBaseTokenAccount, userCQuoteTokenAccount))
+        .signers([userC]) // 用户 C 签名
+        .rpc() // 发送交易
+      await consumeEvents([openOrdersD])
+
+      // 锁定金额全部释放：扣除成交额和 maker 手续费后的余额退回可用余额，不按新的 taker 费率多退或少退
+      const makerFee = quoteQty.muln(makerFeeBps).divn(10_000) // maker 手续费
+      const openOrdersDFilled = await program.account.openOrders.fetch(openOrdersD) // 成交结算后的开放订单账户
+      expect(openOrdersDFilled.quoteTokenLocked.eq(lockedBefore)).toBe(true) // 验证锁定余额恢复，没有残留
+      expect(openOrdersDFilled.quoteTokenFree.sub(openOrdersDPlaced.quoteTokenFree).eq(lockedQuote.sub(quoteQty).sub(makerFee))).toBe(
+        true,
+      ) // 验证退回的金额来自下单时的锁定金额
+      expect(openOrdersDFilled.baseTokenFree.sub(openOrdersDPlaced.baseTokenFree).eq(quantity)).toBe(true) // 验证收到基础代币
+
+      // 恢复原有手续费率
+      await setFees(makerFeeBps, takerFeeBps)
+    })
+
+    // 测试用例：放弃管理员权限（放在最后，之后市场不再有管理员）
+    it('✅ Admin can renounce the market authority', async () => {
+      // 管理员放弃权限
+      await program.methods
+        .renounceAuthority()
+        .accounts({
+          market: marketPda, // 市场账户
+          authority: authority.publicKey, // 管理员公钥
+        })
+        .signers([authority]) // 管理员签名
+        .rpc() // 发送交易
+      const marketAccount = await program.account.market.fetch(marketPda) // 获取市场账户数据
+      expect(marketAccount.authority.equals(PublicKey.default)).toBe(true) // 验证管理员已清除
+
+      // 之后任何管理员指令都会失败
+      await expect(
+        program.methods
+          .setPause(true)
+          .accounts({
+            market: marketPda, // 市场账户
+            authority: authority.publicKey, // 原管理员公钥
+          })
+          .signers([authority]) // 原管理员签名
           .rpc(),
-      ).rejects.toThrow('Unauthorized action.') // 验证抛出“未授权操作”错误
+      ).rejects.toThrow() // 验证原管理员被拒绝
     })
   })
 })